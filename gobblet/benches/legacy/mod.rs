@@ -0,0 +1,168 @@
+// The Vec based representation Gobblet used before bitboards, kept only to benchmark against.
+// Same rules and action order as `Gobblet<Gobblers>`, so both can replay the same random games.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+const SIZE: usize = 3;
+const NUM_SIZES: u8 = 3;
+const REPETITIONS: usize = 3;
+const MAX_TURNS: usize = 72;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Token {
+    color: u8,
+    size: u8,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+struct Block {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LegacyAction {
+    Drop { size: u8, to: (usize, usize) },
+    Move { from: (usize, usize), to: (usize, usize) },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LegacyGobblet {
+    uid: String,
+    plate: Vec<Vec<Block>>,
+    player: u8,
+    inventories: [[u8; NUM_SIZES as usize]; 2],
+    turn_count: usize,
+    winner: Option<u8>,
+    history: Vec<u64>,
+    repeated: bool,
+}
+
+fn lines() -> Vec<Vec<(usize, usize)>> {
+    let mut lines = Vec::new();
+    for i in 0..SIZE {
+        lines.push((0..SIZE).map(|j| (j, i)).collect());
+        lines.push((0..SIZE).map(|j| (i, j)).collect());
+    }
+    lines.push((0..SIZE).map(|i| (i, i)).collect());
+    lines.push((0..SIZE).map(|i| (i, SIZE - 1 - i)).collect());
+    lines
+}
+
+fn top(plate: &[Vec<Block>], (x, y): (usize, usize)) -> Option<Token> {
+    plate[y][x].tokens.last().cloned()
+}
+
+fn winning_lines(plate: &[Vec<Block>], color: u8) -> Vec<Vec<(usize, usize)>> {
+    lines()
+        .into_iter()
+        .filter(|line| {
+            let tokens: Vec<Token> = line.iter().filter_map(|&xy| top(plate, xy)).collect();
+            tokens.len() == SIZE && tokens.iter().all(|t| t.color == color)
+        })
+        .collect()
+}
+
+impl LegacyGobblet {
+    pub fn new() -> Self {
+        let mut game = Self {
+            uid: String::from("0e7f6a52-3c1d-4b8e-9f2a-6d5c4b3a2918"),
+            plate: vec![vec![Block::default(); SIZE]; SIZE],
+            player: 0,
+            inventories: [[2; NUM_SIZES as usize]; 2],
+            turn_count: 0,
+            winner: None,
+            history: Vec::new(),
+            repeated: false,
+        };
+        game.history.push(game.position_hash());
+        game
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.winner.is_some() || self.repeated || self.turn_count >= MAX_TURNS
+    }
+
+    fn stackable(&self, xy: (usize, usize), size: u8) -> bool {
+        top(&self.plate, xy).map_or(true, |t| t.size < size)
+    }
+
+    // same order as the action ids of Gobblet<Gobblers>
+    pub fn actions(&self) -> Vec<LegacyAction> {
+        let mut actions = Vec::new();
+        let cells: Vec<(usize, usize)> = (0..SIZE)
+            .flat_map(|y| (0..SIZE).map(move |x| (x, y)))
+            .collect();
+        for size in 0..NUM_SIZES {
+            if self.inventories[self.player as usize][size as usize] == 0 {
+                continue;
+            }
+            for &to in cells.iter() {
+                if self.stackable(to, size) {
+                    actions.push(LegacyAction::Drop { size, to });
+                }
+            }
+        }
+        for &from in cells.iter() {
+            if let Some(token) = top(&self.plate, from) {
+                if token.color != self.player {
+                    continue;
+                }
+                for &to in cells.iter() {
+                    if to != from && self.stackable(to, token.size) {
+                        actions.push(LegacyAction::Move { from, to });
+                    }
+                }
+            }
+        }
+        actions
+    }
+
+    // the old full recompute over the whole board after every move
+    fn position_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.plate.hash(&mut hasher);
+        self.player.hash(&mut hasher);
+        self.inventories.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn step(&mut self, action: &LegacyAction) -> bool {
+        let mover = self.player;
+        let opponent = 1 - mover;
+        let (revealed, to) = match *action {
+            LegacyAction::Drop { size, to } => {
+                self.inventories[mover as usize][size as usize] -= 1;
+                let token = Token { color: mover, size };
+                self.plate[to.1][to.0].tokens.push(token);
+                (Vec::new(), to)
+            }
+            LegacyAction::Move { from, to } => {
+                let mut lifted = self.plate.clone();
+                lifted[from.1][from.0].tokens.pop();
+                let revealed = winning_lines(&lifted, opponent);
+                let token = self.plate[from.1][from.0].tokens.pop().unwrap();
+                self.plate[to.1][to.0].tokens.push(token);
+                (revealed, to)
+            }
+        };
+        self.winner = if revealed.iter().any(|line| !line.contains(&to)) {
+            Some(opponent)
+        } else if !winning_lines(&self.plate, mover).is_empty() {
+            Some(mover)
+        } else if !winning_lines(&self.plate, opponent).is_empty() {
+            Some(opponent)
+        } else {
+            None
+        };
+        self.player = opponent;
+        self.turn_count += 1;
+
+        if let LegacyAction::Drop { .. } = action {
+            self.history.clear();
+        }
+        let hash = self.position_hash();
+        self.history.push(hash);
+        self.repeated = self.history.iter().filter(|&&h| h == hash).count() >= REPETITIONS;
+        self.is_over()
+    }
+}
@@ -0,0 +1,107 @@
+// Node throughput of the bitboard Gobblet against the old Vec based representation.
+// cargo bench -p gobblet
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use gobblet::gobblet::{Action, Gobblet};
+use gobblet::variant::Gobblers;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use synthesis::game::Game;
+
+mod legacy;
+use legacy::LegacyGobblet;
+
+fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// both representations generate actions in the same order, so one seed replays the same game
+fn opening(seed: u64, num_turns: usize) -> (Gobblet<Gobblers>, LegacyGobblet) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut game = Gobblet::<Gobblers>::new();
+    let mut legacy = LegacyGobblet::new();
+    for _ in 0..num_turns {
+        let actions: Vec<Action<Gobblers>> = game.iter_actions().collect();
+        let legacy_actions = legacy.actions();
+        assert_eq!(actions.len(), legacy_actions.len());
+        let i = rng.gen_range(0..actions.len());
+        if game.step(&actions[i]) | legacy.step(&legacy_actions[i]) {
+            break;
+        }
+    }
+    (game, legacy)
+}
+
+fn clone_and_hash(c: &mut Criterion) {
+    let (game, legacy) = opening(0, 6);
+    let mut group = c.benchmark_group("clone_and_hash");
+    group.bench_function("bitboard", |b| b.iter(|| hash_of(&black_box(&game).clone())));
+    group.bench_function("legacy", |b| b.iter(|| hash_of(&black_box(&legacy).clone())));
+    group.finish();
+}
+
+// what MCTS does when it visits a node: one child per legal action
+fn expand_node(c: &mut Criterion) {
+    let (game, legacy) = opening(0, 6);
+    let mut group = c.benchmark_group("expand_node");
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            let mut total = 0;
+            for action in game.iter_actions() {
+                let mut child = game.clone();
+                child.step(&action);
+                total ^= hash_of(&child);
+            }
+            total
+        })
+    });
+    group.bench_function("legacy", |b| {
+        b.iter(|| {
+            let mut total = 0;
+            for action in legacy.actions() {
+                let mut child = legacy.clone();
+                child.step(&action);
+                total ^= hash_of(&child);
+            }
+            total
+        })
+    });
+    group.finish();
+}
+
+fn random_playout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("random_playout");
+    group.bench_function("bitboard", |b| {
+        let mut rng = StdRng::seed_from_u64(0);
+        b.iter(|| {
+            let mut game = Gobblet::<Gobblers>::new();
+            while !game.is_over() {
+                let actions: Vec<Action<Gobblers>> = game.iter_actions().collect();
+                let mut next = game.clone();
+                next.step(&actions[rng.gen_range(0..actions.len())]);
+                game = next;
+            }
+            game
+        })
+    });
+    group.bench_function("legacy", |b| {
+        let mut rng = StdRng::seed_from_u64(0);
+        b.iter(|| {
+            let mut game = LegacyGobblet::new();
+            while !game.is_over() {
+                let actions = game.actions();
+                let mut next = game.clone();
+                next.step(&actions[rng.gen_range(0..actions.len())]);
+                game = next;
+            }
+            game
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, clone_and_hash, expand_node, random_playout);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+use gobblet::gobblet::Gobblet;
+use gobblet::policies::*;
+use gobblet::variant::{Classic, Gobblers, Variant};
+use synthesis::prelude::*;
+
+const USAGE: &str = "usage: tournament [classic] [resnet] --config tournament.toml";
+
+fn run<G: Undo<N> + Notation<N> + Sync, P: Policy<G, N> + NNPolicy<G, N> + Send, const N: usize>(
+    config: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cfg = TournamentConfig::load(config)?;
+    if cfg.logs.as_os_str().is_empty() {
+        cfg.logs = train_dir("./_logs/tournaments", G::NAME)?;
+    }
+    std::fs::create_dir_all(&cfg.logs)?;
+    cfg.save(cfg.logs.join("tournament.toml"))?;
+
+    tch::set_num_threads(1);
+    tch::set_num_interop_threads(1);
+
+    let ratings = tournament::<G, P, N>(&cfg)?;
+    print!("{}", ratings.table());
+    Ok(())
+}
+
+fn main() {
+    // cargo run --release --bin tournament -- --config tournament.toml 3x3 版本，模型都是 MLP
+    // cargo run --release --bin tournament -- classic resnet --config tournament.toml
+    // 模型可以来自不同的训练，只要网络结构相同；tournament.example.toml 里有每种选手的写法
+    let mut args: Vec<String> = std::env::args().collect();
+    let config = match take_config_arg(&mut args) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let variant = args.get(1).map(String::as_str);
+    let arch = args.get(2).map(String::as_str);
+    match (variant, arch) {
+        (Some("classic"), Some("resnet")) => {
+            run::<Gobblet<Classic>, GobbletResNet<Classic>, { Classic::NUM_ACTIONS }>(&config).unwrap()
+        }
+        (Some("classic"), _) => run::<Gobblet<Classic>, GobbletNet<Classic>, { Classic::NUM_ACTIONS }>(&config).unwrap(),
+        (_, Some("resnet")) => {
+            run::<Gobblet<Gobblers>, GobbletResNet<Gobblers>, { Gobblers::NUM_ACTIONS }>(&config).unwrap()
+        }
+        _ => run::<Gobblet<Gobblers>, GobbletNet<Gobblers>, { Gobblers::NUM_ACTIONS }>(&config).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_config_loads() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tournament.example.toml");
+        let cfg = TournamentConfig::load(path).unwrap();
+        assert_eq!(cfg.pairing, Pairing::RoundRobin);
+        let names: Vec<&str> = cfg.players.iter().map(PlayerConfig::name).collect();
+        assert_eq!(
+            names,
+            ["run-a/model_100", "run-b/model_100", "VanillaMCTS3200", "AlphaBeta16000", "Random"]
+        );
+        assert!(matches!(cfg.players[0], PlayerConfig::Model { num_threads: 4, .. }));
+        assert!(matches!(cfg.players[1], PlayerConfig::Model { num_threads: 0, .. }));
+        assert!(cfg.logs.as_os_str().is_empty());
+    }
+}
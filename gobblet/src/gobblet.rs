@@ -1,11 +1,14 @@
+use std::marker::PhantomData;
 use std::ops::Not;
+use std::sync::Arc;
 
 use colored::*;
 use int_enum::IntEnum;
-use uuid::Uuid;
 use synthesis::game::*;
 
-const GAME_SIZE: usize = 3;
+use crate::rules;
+use crate::variant::{ActionSpace, Variant, MAX_SIDE, MAX_SIZES};
+use crate::zobrist;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum PlayerId {
@@ -38,6 +41,7 @@ impl HasTurnOrder for PlayerId {
 #[repr(usize)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq, IntEnum, PartialOrd, Hash)]
 pub enum Size {
+    HUGE = 3,
     BIG = 2,
     MID = 1,
     SMALL = 0,
@@ -56,11 +60,13 @@ impl Token {
     pub fn to_string(&self) -> String {
         match self.color {
             PlayerId::RED => match self.size {
+                Size::HUGE => "🔴".red().bold().underline().to_string(),
                 Size::BIG => "🔴".red().bold().to_string(),
                 Size::MID => "🔴".red().to_string(),
                 Size::SMALL => "🔴".red().dimmed().to_string(),
             },
             PlayerId::GREEN => match self.size {
+                Size::HUGE => "🟢".green().bold().underline().to_string(),
                 Size::BIG => "🟢".green().bold().to_string(),
                 Size::MID => "🟢".green().to_string(),
                 Size::SMALL => "🟢".green().dimmed().to_string(),
@@ -69,26 +75,65 @@ impl Token {
     }
 }
 
-#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+// 一个格子里的棋子堆，每种尺寸和颜色占一位：第 size * 2 + color 位
+// 同一个格子里每种尺寸最多一颗，所以最高位就是最外层的棋子，3x3 版本只用到 6 位
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct Block {
-    tokens: Vec<Token>,
+    mask: u8,
 }
 
 impl Block {
+    fn bit(token: Token) -> u8 {
+        1 << (token.size as usize * 2 + token.color as usize)
+    }
+    fn token_at(bit: u32) -> Token {
+        let color = if bit % 2 == 0 {
+            PlayerId::RED
+        } else {
+            PlayerId::GREEN
+        };
+        Token::new(color, Size::from_int(bit as usize / 2).unwrap())
+    }
     pub fn new(tokens: Vec<Token>) -> Block {
-        Block { tokens }
+        let mut block = Block::default();
+        for token in tokens {
+            block.mask |= Self::bit(token);
+        }
+        block
+    }
+    pub(crate) fn mask(&self) -> u8 {
+        self.mask
+    }
+    pub fn len(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.mask == 0
+    }
+    // tokens from the bottom of the stack to the top
+    pub fn tokens(&self) -> impl Iterator<Item = Token> {
+        let mask = self.mask;
+        (0..u8::BITS)
+            .filter(move |bit| mask & (1 << bit) != 0)
+            .map(Self::token_at)
     }
     pub fn get_outermost_token(&self) -> Option<Token> {
-        self.tokens.last().cloned()
+        if self.mask == 0 {
+            None
+        } else {
+            Some(Self::token_at(u8::BITS - 1 - self.mask.leading_zeros()))
+        }
     }
-    // TODO from copy to borrow, but how?
     pub fn pop_outermost_token(&mut self) -> Token {
-        self.tokens.remove(self.tokens.len() - 1)
+        let token = self.get_outermost_token().unwrap();
+        self.mask &= !Self::bit(token);
+        token
     }
     pub fn is_stackable(&self, token: Token) -> bool {
         match self.get_outermost_token() {
             Some(t) => {
-                if t.color != token.color && t.size < token.size {
+                // 大棋子可以吃掉任何更小的棋子，包括自己的
+                if t.size < token.size {
                     return true;
                 }
             }
@@ -101,7 +146,7 @@ impl Block {
     // push a token to the block, return true if successful
     pub fn push_token(&mut self, token: Token) -> bool {
         if self.is_stackable(token) {
-            self.tokens.push(token);
+            self.mask |= Self::bit(token);
             true
         } else {
             false
@@ -109,52 +154,27 @@ impl Block {
     }
 }
 
-#[derive(Debug, Default, Clone, Eq, Hash, PartialEq)]
-pub struct Board {
-    pub plate: Vec<Vec<Block>>,
+// 固定大小的棋盘，克隆和哈希都不用分配内存，SIZE 以外的格子一直是空的
+#[derive(Debug, Default, Clone, Copy, Eq, Hash, PartialEq)]
+pub struct Board<V: Variant> {
+    pub plate: [[Block; MAX_SIDE]; MAX_SIDE],
+    variant: PhantomData<V>,
 }
 
-
-impl Board {
+impl<V: Variant> Board<V> {
     pub fn new() -> Self {
-        let mut plate = Vec::with_capacity(GAME_SIZE);
-        for _ in 0..GAME_SIZE {
-            let mut row = Vec::with_capacity(GAME_SIZE);
-            for _ in 0..GAME_SIZE {
-                row.push(Block::default());
-            }
-            plate.push(row);
-        }
-        Board { plate }
-    }
-    pub fn to_features(&self) -> Vec<f32> {
-        let mut features = Vec::new();
-
-        for row in &self.plate {
-            for block in row {
-                if let Some(token) = block.get_outermost_token() {
-                    features.extend(self.token_to_features(token));
-                } else {
-                    features.extend(vec![0.0; 6]);
-                }
-            }
+        Board {
+            plate: Default::default(),
+            variant: PhantomData,
         }
-        features
     }
-
-    fn token_to_features(&self, token: Token) -> Vec<f32> {
-        let mut feature = vec![0.0; 6]; // 两个玩家，三种尺寸
-        let index = match token.color {
-            PlayerId::RED => 0,
-            PlayerId::GREEN => 3,
-        } + token.size.int_value();
-        feature[index] = 1.0;
-        feature
+    // (x, y, block) for every block in use by the variant
+    pub fn blocks(&self) -> impl Iterator<Item = (usize, usize, Block)> + '_ {
+        (0..V::SIZE).flat_map(move |y| (0..V::SIZE).map(move |x| (x, y, self.plate[y][x])))
     }
-
     pub fn display(&self) {
-        for row in self.plate.iter() {
-            for block in row.iter() {
+        for row in self.plate.iter().take(V::SIZE) {
+            for block in row.iter().take(V::SIZE) {
                 match block.get_outermost_token() {
                     Some(token) => {
                         print!("{} ", token.to_string());
@@ -167,67 +187,74 @@ impl Board {
             println!();
         }
     }
-    pub fn is_gameover(&self) -> Option<PlayerId> {
-        // 检查行和列
-        for i in 0..GAME_SIZE {
-            // 检查行
-            let row_positions: Vec<(usize, usize)> = (0..GAME_SIZE).map(|j| (i, j)).collect();
-            if let Some(winner) = self.check_line(&row_positions) {
-                return Some(winner);
-            }
-            // 检查列
-            let col_positions: Vec<(usize, usize)> = (0..GAME_SIZE).map(|j| (j, i)).collect();
-            if let Some(winner) = self.check_line(&col_positions) {
-                return Some(winner);
+    pub(crate) fn check_line(&self, positions: &[(usize, usize)]) -> Option<PlayerId> {
+        let mut owner = None;
+        for &(x, y) in positions {
+            // 如果任何位置为空，则不可能是获胜组合
+            let color = self.plate[y][x].get_outermost_token()?.color;
+            // 检查所有棋子是否属于同一玩家
+            if owner.is_some() && owner != Some(color) {
+                return None;
             }
+            owner = Some(color);
         }
-        // 检查对角线
-        let diag1_positions: Vec<(usize, usize)> = (0..GAME_SIZE).map(|i| (i, i)).collect();
-        if let Some(winner) = self.check_line(&diag1_positions) {
-            return Some(winner);
-        }
-        let diag2_positions: Vec<(usize, usize)> = (0..GAME_SIZE).map(|i| (i, GAME_SIZE - 1 - i)).collect();
-        if let Some(winner) = self.check_line(&diag2_positions) {
-            return Some(winner);
-        }
-        None
+        owner
     }
 
-    fn check_line(&self, positions: &[(usize, usize)]) -> Option<PlayerId> {
-        let mut tokens = Vec::new();
-        for &(x, y) in positions {
-            if let Some(token) = self.plate[y][x].get_outermost_token() {
-                tokens.push(token);
-            } else {
-                return None; // 如果任何位置为空，则不可能是获胜组合
-            }
-        }
-        // 检查所有棋子是否属于同一玩家
-        if tokens.iter().all(|t| t.color == PlayerId::RED) {
-            return Some(PlayerId::RED);
-        }
-        if tokens.iter().all(|t| t.color == PlayerId::GREEN) {
-            return Some(PlayerId::GREEN);
-        }
-        None
+    // whether the outermost token at (x, y) is in a line where its owner shows all but one token
+    pub(crate) fn is_in_near_line(&self, x: usize, y: usize) -> bool {
+        let owner = match self.plate[y][x].get_outermost_token() {
+            Some(token) => token.color,
+            None => return false,
+        };
+        rules::all_lines::<V>()
+            .iter()
+            .filter(|line| line.contains(&(x, y)))
+            .any(|line| {
+                let owned = line.iter().filter(|&&(x, y)| {
+                    self.plate[y][x]
+                        .get_outermost_token()
+                        .map(|token| token.color)
+                        == Some(owner)
+                });
+                owned.count() + 1 >= V::SIZE
+            })
     }
 }
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct Player {
+struct Player<V: Variant> {
     color: PlayerId,
-    inventory: [u8; 3],
+    inventory: [u8; MAX_SIZES],
+    variant: PhantomData<V>,
 }
 
-impl Player {
-    pub fn new(color: PlayerId) -> Player {
+impl<V: Variant> Player<V> {
+    pub fn new(color: PlayerId) -> Player<V> {
+        let mut inventory = [0; MAX_SIZES];
+        for count in inventory.iter_mut().take(V::NUM_SIZES) {
+            *count = V::PIECES_PER_SIZE;
+        }
         Player {
             color,
-            inventory: [2, 2, 2],
+            inventory,
+            variant: PhantomData,
+        }
+    }
+
+    // whether a token of this size can be taken from the reserve
+    pub fn has_token(&self, size: Size) -> bool {
+        let size = size as usize;
+        if size >= V::NUM_SIZES || self.inventory[size] == 0 {
+            return false;
         }
+        // 嵌套的棋堆只能拿最外层的棋子，也就是没有更大棋子盖住的那一堆
+        !V::NESTED_RESERVE
+            || size + 1 == V::NUM_SIZES
+            || self.inventory[size] > self.inventory[size + 1]
     }
 
     pub fn get_token(&mut self, size: Size) -> Option<Token> {
-        if self.inventory[size as usize] > 0 {
+        if self.has_token(size) {
             self.inventory[size as usize] -= 1;
             Some(Token::new(self.color, size))
         } else {
@@ -238,11 +265,11 @@ impl Player {
     pub fn is_valid_place_from_inventory(
         &mut self,
         size: Size,
-        board: &mut Board,
+        board: &mut Board<V>,
         x: usize,
         y: usize,
     ) -> bool {
-        if self.inventory[size as usize] > 0 {
+        if self.has_token(size) {
             if board.plate[y][x].is_stackable(Token::new(self.color, size)) {
                 return true;
             }
@@ -253,7 +280,7 @@ impl Player {
     pub fn place_from_inventory(
         &mut self,
         size: Size,
-        board: &mut Board,
+        board: &mut Board<V>,
         x: usize,
         y: usize,
     ) -> bool {
@@ -268,15 +295,15 @@ impl Player {
 
     pub fn is_valid_swap_from_board(
         &mut self,
-        board: &mut Board,
+        board: &mut Board<V>,
         x: usize,
         y: usize,
         x2: usize,
         y2: usize,
     ) -> bool {
-        if x < GAME_SIZE && y < GAME_SIZE {
-            if board.plate[y][x].get_outermost_token().is_some() {
-                if board.plate[y2][x2].is_stackable(board.plate[y][x].get_outermost_token().unwrap()) {
+        if x < V::SIZE && y < V::SIZE && (x, y) != (x2, y2) {
+            if let Some(token) = board.plate[y][x].get_outermost_token() {
+                if token.color == self.color && board.plate[y2][x2].is_stackable(token) {
                     return true;
                 }
             }
@@ -284,10 +311,9 @@ impl Player {
         false
     }
 
-
     pub fn swap_token_from_board(
         &mut self,
-        board: &mut Board,
+        board: &mut Board<V>,
         x: usize,
         y: usize,
         x2: usize,
@@ -311,83 +337,97 @@ pub enum ActionType {
     FromBoard = 1,
 }
 
-
-const ACTION_ID_MAX: usize = 3 * GAME_SIZE * GAME_SIZE + GAME_SIZE * GAME_SIZE * GAME_SIZE * GAME_SIZE;
-
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
-pub struct Action {
+pub struct Action<V: Variant> {
     pub action_type: ActionType,
     pub player: PlayerId,
     pub from_inventory: Option<Token>,
     pub from_xy: Option<[usize; 2]>,
     pub to_xy: Option<[usize; 2]>,
+    variant: PhantomData<V>,
 }
 
-// 为 Action 实现 Into<usize> 和 From<usize>
-impl Into<usize> for Action {
+impl<V: Variant> Action<V> {
+    pub fn from_inventory(player: PlayerId, size: Size, to_xy: [usize; 2]) -> Self {
+        Action {
+            action_type: ActionType::FromInventory,
+            player,
+            from_inventory: Some(Token::new(player, size)),
+            from_xy: None,
+            to_xy: Some(to_xy),
+            variant: PhantomData,
+        }
+    }
+
+    pub fn from_board(player: PlayerId, from_xy: [usize; 2], to_xy: [usize; 2]) -> Self {
+        Action {
+            action_type: ActionType::FromBoard,
+            player,
+            from_inventory: None,
+            from_xy: Some(from_xy),
+            to_xy: Some(to_xy),
+            variant: PhantomData,
+        }
+    }
+}
+
+// 为 Action 实现 Into<usize>，反过来用 Action::decode
+// 前 NUM_SIZES * SIZE * SIZE 个是从库存落子，之后是棋盘上的移动
+impl<V: Variant> Into<usize> for Action<V> {
     fn into(self) -> usize {
+        let cells = V::SIZE * V::SIZE;
         match self.action_type {
             ActionType::FromInventory => {
                 let size = self.from_inventory.unwrap().size as usize;
                 let x = self.to_xy.unwrap()[0];
                 let y = self.to_xy.unwrap()[1];
-                size * GAME_SIZE * GAME_SIZE + y * GAME_SIZE + x
+                size * cells + y * V::SIZE + x
             }
             ActionType::FromBoard => {
                 let from_x = self.from_xy.unwrap()[0];
                 let from_y = self.from_xy.unwrap()[1];
                 let to_x = self.to_xy.unwrap()[0];
                 let to_y = self.to_xy.unwrap()[1];
-                3 * GAME_SIZE * GAME_SIZE
-                    + ((from_y * GAME_SIZE + from_x) * GAME_SIZE * GAME_SIZE)
-                    + (to_y * GAME_SIZE + to_x)
+                V::NUM_SIZES * cells
+                    + ((from_y * V::SIZE + from_x) * cells)
+                    + (to_y * V::SIZE + to_x)
             }
         }
     }
 }
 
-impl From<usize> for Action {
-    fn from(value: usize) -> Self {
-        if value < 3 * GAME_SIZE * GAME_SIZE {
+impl<V: Variant> Action<V> {
+    // action ids don't store the player, it comes from the game the action is played in
+    pub fn decode(player: PlayerId, value: usize) -> Self {
+        let cells = V::SIZE * V::SIZE;
+        if value < V::NUM_SIZES * cells {
             // FromInventory
-            let size = value / (GAME_SIZE * GAME_SIZE);
-            let idx = value % (GAME_SIZE * GAME_SIZE);
-            let x = idx % GAME_SIZE;
-            let y = idx / GAME_SIZE;
-            Action {
-                action_type: ActionType::FromInventory,
-                player: PlayerId::RED, // 在实际使用中应根据当前玩家设置
-                from_inventory: Some(Token::new(PlayerId::RED, Size::from_int(size).unwrap())),
-                from_xy: None,
-                to_xy: Some([x, y]),
-            }
+            let size = value / cells;
+            let idx = value % cells;
+            let x = idx % V::SIZE;
+            let y = idx / V::SIZE;
+            Action::from_inventory(player, Size::from_int(size).unwrap(), [x, y])
         } else {
             // FromBoard
-            let adjusted_value = value - 3 * GAME_SIZE * GAME_SIZE;
-            let from_idx = adjusted_value / (GAME_SIZE * GAME_SIZE);
-            let to_idx = adjusted_value % (GAME_SIZE * GAME_SIZE);
-            let from_x = from_idx % GAME_SIZE;
-            let from_y = from_idx / GAME_SIZE;
-            let to_x = to_idx % GAME_SIZE;
-            let to_y = to_idx / GAME_SIZE;
-            Action {
-                action_type: ActionType::FromBoard,
-                player: PlayerId::RED, // 在实际使用中应根据当前玩家设置
-                from_inventory: None,
-                from_xy: Some([from_x, from_y]),
-                to_xy: Some([to_x, to_y]),
-            }
+            let adjusted_value = value - V::NUM_SIZES * cells;
+            let from_idx = adjusted_value / cells;
+            let to_idx = adjusted_value % cells;
+            let from_x = from_idx % V::SIZE;
+            let from_y = from_idx / V::SIZE;
+            let to_x = to_idx % V::SIZE;
+            let to_y = to_idx / V::SIZE;
+            Action::from_board(player, [from_x, from_y], [to_x, to_y])
         }
     }
 }
 
-pub struct ValidActions {
-    game: Gobblet,
+pub struct ValidActions<V: Variant> {
+    game: Gobblet<V>,
     action_id: usize,
 }
 
-impl ValidActions {
-    pub fn new(game: &Gobblet) -> Self {
+impl<V: Variant> ValidActions<V> {
+    pub fn new(game: &Gobblet<V>) -> Self {
         ValidActions {
             game: game.clone(),
             action_id: 0,
@@ -395,12 +435,12 @@ impl ValidActions {
     }
 }
 
-impl Iterator for ValidActions {
-    type Item = Action;
+impl<V: Variant> Iterator for ValidActions<V> {
+    type Item = Action<V>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.action_id < ACTION_ID_MAX {
-            let action = Action::from(self.action_id);
+        while self.action_id < V::NUM_ACTIONS {
+            let action = Action::decode(self.game.player, self.action_id);
             self.action_id += 1;
 
             // 检查行动是否合法
@@ -412,34 +452,76 @@ impl Iterator for ValidActions {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Gobblet {
-    uid: String,
-    board: Board,
-    player: PlayerId,
-    players: [Player; 2],
+// zobrist hashes of the positions reached so far, newest first
+// shared between clones so cloning a game never copies it
+#[derive(Debug)]
+struct History {
+    hash: u64,
+    after_drop: bool, // first position or reached by a drop, no earlier position can come back
+    prev: Option<Arc<History>>,
+}
+
+// a position reached this many times is a draw. Training, evaluation and tournaments start every
+// game with `Game::new`, so they always play with this count, `with_repetitions` is for code that
+// builds its own games
+pub const DEFAULT_REPETITIONS: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct Gobblet<V: Variant> {
+    pub(crate) board: Board<V>,
+    pub(crate) player: PlayerId,
+    players: [Player<V>; 2],
     turn_count: usize,
+    winner: Option<PlayerId>,
+    hash: u64, // zobrist hash of the position, updated incrementally by step
+    history: Option<Arc<History>>,
+    repeated: bool,
+    repetitions: usize, // a position reached this many times is a draw
+}
+
+// 两局游戏相等只看局面：棋盘、轮到谁、双方库存
+impl<V: Variant> PartialEq for Gobblet<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+            && self.board == other.board
+            && self.player == other.player
+            && self.players == other.players
+    }
+}
+
+impl<V: Variant> Eq for Gobblet<V> {}
+
+impl<V: Variant> std::hash::Hash for Gobblet<V> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
 }
 
-impl Game<GAME_SIZE> for Gobblet {
-    const NAME: &'static str = "Gobblet";
+impl<V: ActionSpace<N>, const N: usize> Game<N> for Gobblet<V> {
+    const NAME: &'static str = V::NAME;
     const NUM_PLAYERS: usize = 2;
-    const MAX_TURNS: usize = 72; // 根据游戏规则调整
-    const DIMS: &'static [i64] = &[3, 3, 6]; // 根据游戏状态表示
+    const MAX_TURNS: usize = V::MAX_TURNS;
+    const DIMS: &'static [i64] = V::DIMS; // 根据游戏状态表示
 
     type PlayerId = PlayerId;
-    type Action = Action;
-    type ActionIterator = ValidActions;
-    type Features = Vec<f32>;
+    type Action = Action<V>;
+    type ActionIterator = ValidActions<V>;
+    type Features = V::Features;
 
     fn new() -> Self {
-        Self {
-            uid: Uuid::new_v4().to_string(),
+        let mut game = Self {
             board: Board::new(),
             player: PlayerId::RED,
             players: [Player::new(PlayerId::RED), Player::new(PlayerId::GREEN)],
             turn_count: 0,
-        }
+            winner: None,
+            hash: 0,
+            history: None,
+            repeated: false,
+            repetitions: DEFAULT_REPETITIONS,
+        };
+        game.reset_history();
+        game
     }
 
     fn player(&self) -> Self::PlayerId {
@@ -447,11 +529,11 @@ impl Game<GAME_SIZE> for Gobblet {
     }
 
     fn is_over(&self) -> bool {
-        self.board.is_gameover().is_some() || self.turn_count >= Self::MAX_TURNS
+        self.winner.is_some() || self.repeated || self.turn_count >= V::MAX_TURNS
     }
 
     fn reward(&self, player_id: Self::PlayerId) -> f32 {
-        match self.board.is_gameover() {
+        match self.winner {
             Some(winner) => {
                 if winner == player_id {
                     1.0
@@ -467,18 +549,93 @@ impl Game<GAME_SIZE> for Gobblet {
         ValidActions::new(self)
     }
 
-    fn step(&mut self, action: &Self::Action) -> bool {
-        if self.parse_action(*action) {
-            self.player = !self.player;
-            self.turn_count += 1;
-            true
+    fn decode_action(&self, action_id: usize) -> Self::Action {
+        Action::decode(self.player, action_id)
+    }
+
+    fn result(&self) -> Option<GameResult<PlayerId>> {
+        if let Some(winner) = self.winner {
+            Some(GameResult::Win(winner))
+        } else if self.repeated {
+            Some(GameResult::Repetition)
+        } else if self.turn_count >= V::MAX_TURNS {
+            Some(GameResult::TurnLimit)
         } else {
-            false
+            None
         }
     }
 
+    // returns whether the game is over, an illegal action leaves the game untouched and returns false
+    fn step(&mut self, action: &Self::Action) -> bool {
+        if self.is_over() || !self.is_action_valid(action) {
+            return false;
+        }
+        // 抬起棋子时可能揭开对手的连线，必须在落子前检查
+        let revealed = rules::revealed_lines(&self.board, action);
+        let hash_delta = self.hash_delta(action);
+        if !self.parse_action(*action) {
+            return false;
+        }
+        self.winner = rules::judge(&self.board, action, &revealed);
+        self.player = !self.player;
+        self.turn_count += 1;
+        self.hash ^= hash_delta ^ zobrist::GREEN_TO_MOVE;
+
+        // 从库存落子后库存变少，之前的局面不可能再出现
+        let after_drop = action.action_type == ActionType::FromInventory;
+        let seen = if after_drop {
+            0
+        } else {
+            self.history().filter(|&hash| hash == self.hash).count()
+        };
+        self.repeated = seen + 1 >= self.repetitions;
+        // 撤销时要回到之前的历史，所以落子后也保留旧的记录
+        self.history = Some(Arc::new(History {
+            hash: self.hash,
+            after_drop,
+            prev: self.history.take(),
+        }));
+        self.is_over()
+    }
+
+    // planes are ordered [side to move, opponent] so the network always sees the position from the mover's view
     fn features(&self) -> Self::Features {
-        self.board.to_features()
+        let cells = V::SIZE * V::SIZE;
+        let mut planes = vec![0.0f32; V::NUM_PLANES * cells];
+        let owners = [self.player, !self.player];
+
+        // 棋盘：每个玩家每种尺寸一层，被盖住的棋子也要标出来
+        for (x, y, block) in self.board.blocks() {
+            for token in block.tokens() {
+                let owner = if token.color == self.player { 0 } else { 1 };
+                let plane = owner * V::NUM_SIZES + token.size as usize;
+                planes[plane * cells + y * V::SIZE + x] = 1.0;
+            }
+        }
+
+        // 库存：剩余数量除以初始数量，填满整层
+        for (owner, color) in owners.iter().enumerate() {
+            let inventory = &self.players[*color as usize].inventory;
+            for size in 0..V::NUM_SIZES {
+                let plane = (2 + owner) * V::NUM_SIZES + size;
+                let count = inventory[size] as f32 / V::PIECES_PER_SIZE as f32;
+                planes[plane * cells..(plane + 1) * cells].fill(count);
+            }
+        }
+
+        // 轮到红方时最后一层为 1
+        if self.player == PlayerId::RED {
+            planes[(V::NUM_PLANES - 1) * cells..].fill(1.0);
+        }
+
+        let features = V::Features::try_from(&planes[..]);
+        match features {
+            Ok(features) => features,
+            Err(_) => panic!(
+                "{} features must hold NUM_PLANES * SIZE * SIZE floats",
+                V::NAME
+            ),
+        }
     }
 
     fn print(&self) {
@@ -486,8 +643,222 @@ impl Game<GAME_SIZE> for Gobblet {
     }
 }
 
-impl Gobblet {
-    pub fn is_action_valid(&self, action: &Action) -> bool {
+impl<V: ActionSpace<N>, const N: usize> Undo<N> for Gobblet<V> {
+    // the hash is worked back from the action, so undoing a move played before the history was
+    // reset (by map_state for example) doesn't need the positions the history dropped
+    fn undo(&mut self, action: &Self::Action) {
+        debug_assert_eq!(
+            action.player, !self.player,
+            "undo of an action that wasn't just played"
+        );
+        let [to_x, to_y] = action.to_xy.unwrap();
+        let token = self.board.plate[to_y][to_x].pop_outermost_token();
+        match action.action_type {
+            ActionType::FromInventory => {
+                self.players[token.color as usize].inventory[token.size as usize] += 1;
+            }
+            ActionType::FromBoard => {
+                let [from_x, from_y] = action.from_xy.unwrap();
+                self.board.plate[from_y][from_x].push_token(token);
+            }
+        }
+        self.player = !self.player;
+        self.turn_count -= 1;
+        // step only plays on while the game isn't over
+        self.winner = None;
+        self.repeated = false;
+        self.hash ^= self.hash_delta(action) ^ zobrist::GREEN_TO_MOVE;
+        match self.history.as_ref().and_then(|node| node.prev.clone()) {
+            Some(prev) => {
+                debug_assert_eq!(
+                    prev.hash, self.hash,
+                    "undo of an action that wasn't just played"
+                );
+                self.history = Some(prev);
+            }
+            // 撤销到历史重置之前的局面，历史从这里重新开始
+            None => self.reset_history(),
+        }
+    }
+}
+
+// 正方形棋盘的 8 种对称：i >= 4 时先左右翻转，再逆时针转 i % 4 个 90 度
+pub(crate) fn map_xy<V: Variant>(i: usize, [x, y]: [usize; 2]) -> [usize; 2] {
+    let last = V::SIZE - 1;
+    let (mut x, mut y) = if i >= 4 { (last - x, y) } else { (x, y) };
+    for _ in 0..i % 4 {
+        (x, y) = (last - y, x);
+    }
+    [x, y]
+}
+
+impl<V: ActionSpace<N>, const N: usize> Symmetries<N> for Gobblet<V> {
+    const NUM_SYMMETRIES: usize = 8;
+
+    // the image starts a fresh repetition history, hashes of the original positions don't carry over
+    fn map_state(&self, i: usize) -> Self {
+        let mut game = self.clone();
+        for (x, y, block) in self.board.blocks() {
+            let [x2, y2] = map_xy::<V>(i, [x, y]);
+            game.board.plate[y2][x2] = block;
+        }
+        game.reset_history();
+        game
+    }
+
+    fn map_action(i: usize, action_id: usize) -> usize {
+        // 玩家不影响编号
+        let mut action = Action::<V>::decode(PlayerId::RED, action_id);
+        action.from_xy = action.from_xy.map(|xy| map_xy::<V>(i, xy));
+        action.to_xy = action.to_xy.map(|xy| map_xy::<V>(i, xy));
+        action.into()
+    }
+
+    // 翻转之后再旋转仍然是翻转，逆是自己
+    fn inverse(i: usize) -> usize {
+        if i >= 4 {
+            i
+        } else {
+            (4 - i) % 4
+        }
+    }
+}
+
+// 记谱：从库存落子写作 `B@b2`（尺寸 S/M/B/H，@ 目标格），棋盘上移动写作 `a1-b2`
+// 列用字母 a.. 表示 x，行用数字 1.. 表示 y
+impl<V: ActionSpace<N>, const N: usize> Notation<N> for Gobblet<V> {
+    fn notation(&self, action: &Self::Action) -> String {
+        let to = square(action.to_xy.unwrap());
+        match action.action_type {
+            ActionType::FromInventory => {
+                let size = action.from_inventory.unwrap().size;
+                format!("{}@{}", SIZE_LETTERS[size as usize], to)
+            }
+            ActionType::FromBoard => format!("{}-{}", square(action.from_xy.unwrap()), to),
+        }
+    }
+
+    fn parse_notation(&self, text: &str) -> Option<Self::Action> {
+        if let Some((size, to)) = text.split_once('@') {
+            let size = SIZE_LETTERS[..V::NUM_SIZES]
+                .iter()
+                .position(|&letter| letter == size)?;
+            let size = Size::from_int(size).ok()?;
+            Some(Action::from_inventory(self.player, size, parse_square::<V>(to)?))
+        } else {
+            let (from, to) = text.split_once('-')?;
+            Some(Action::from_board(
+                self.player,
+                parse_square::<V>(from)?,
+                parse_square::<V>(to)?,
+            ))
+        }
+    }
+}
+
+const SIZE_LETTERS: [&str; MAX_SIZES] = ["S", "M", "B", "H"];
+
+fn square([x, y]: [usize; 2]) -> String {
+    format!("{}{}", (b'a' + x as u8) as char, y + 1)
+}
+
+fn parse_square<V: Variant>(text: &str) -> Option<[usize; 2]> {
+    let mut chars = text.chars();
+    let file = chars.next()?;
+    if !file.is_ascii_lowercase() {
+        return None;
+    }
+    let x = (file as u8 - b'a') as usize;
+    let y = chars.as_str().parse::<usize>().ok()?.checked_sub(1)?;
+    if x < V::SIZE && y < V::SIZE {
+        Some([x, y])
+    } else {
+        None
+    }
+}
+
+impl<V: Variant> Gobblet<V> {
+    // the same game with a draw once a position is reached `repetitions` times, clones and
+    // symmetric images keep the count. Below 2 every position would already be a draw
+    pub fn with_repetitions(mut self, repetitions: usize) -> Self {
+        assert!(repetitions >= 2, "a draw needs at least 2 repetitions, not {}", repetitions);
+        self.repetitions = repetitions;
+        self
+    }
+
+    // hash of the board, side to move and inventories, used for Hash and to detect repetitions
+    pub fn zobrist(&self) -> u64 {
+        self.hash
+    }
+
+    // recomputes the hash from scratch, step keeps it up to date with hash_delta
+    fn compute_zobrist(&self) -> u64 {
+        let mut hash = 0;
+        for (x, y, block) in self.board.blocks() {
+            for token in block.tokens() {
+                hash ^= zobrist::token(y * V::SIZE + x, token.color, token.size);
+            }
+        }
+        for player in self.players.iter() {
+            for size in 0..V::NUM_SIZES {
+                hash ^= zobrist::inventory(player.color, size, player.inventory[size]);
+            }
+        }
+        if self.player == PlayerId::GREEN {
+            hash ^= zobrist::GREEN_TO_MOVE;
+        }
+        hash
+    }
+
+    // how a valid action changes the hash, apart from the side to move
+    fn hash_delta(&self, action: &Action<V>) -> u64 {
+        let [to_x, to_y] = action.to_xy.unwrap();
+        let to = to_y * V::SIZE + to_x;
+        match action.action_type {
+            ActionType::FromInventory => {
+                let token = action.from_inventory.unwrap();
+                let size = token.size as usize;
+                let count = self.players[token.color as usize].inventory[size];
+                zobrist::token(to, token.color, token.size)
+                    ^ zobrist::inventory(token.color, size, count)
+                    ^ zobrist::inventory(token.color, size, count - 1)
+            }
+            ActionType::FromBoard => {
+                let [from_x, from_y] = action.from_xy.unwrap();
+                let from = from_y * V::SIZE + from_x;
+                let token = self.board.plate[from_y][from_x]
+                    .get_outermost_token()
+                    .unwrap();
+                zobrist::token(from, token.color, token.size)
+                    ^ zobrist::token(to, token.color, token.size)
+            }
+        }
+    }
+
+    // starts a new history holding only the current position
+    fn reset_history(&mut self) {
+        self.hash = self.compute_zobrist();
+        self.history = Some(Arc::new(History {
+            hash: self.hash,
+            after_drop: true,
+            prev: None,
+        }));
+        self.repeated = false;
+    }
+
+    // positions that can still repeat: back to and including the one after the last drop
+    fn history(&self) -> impl Iterator<Item = u64> + '_ {
+        std::iter::successors(self.history.as_deref(), |node| {
+            if node.after_drop {
+                None
+            } else {
+                node.prev.as_deref()
+            }
+        })
+        .map(|node| node.hash)
+    }
+
+    pub fn is_action_valid(&self, action: &Action<V>) -> bool {
         if action.player != self.player {
             return false;
         }
@@ -495,19 +866,30 @@ impl Gobblet {
         match action.action_type {
             ActionType::FromInventory => {
                 if let Some(token) = action.from_inventory {
-                    self.players[action.player as usize].inventory[token.size as usize] > 0
-                        && self.board.plate[action.to_xy.unwrap()[1]][action.to_xy.unwrap()[0]]
-                        .is_stackable(token)
+                    let [x, y] = action.to_xy.unwrap();
+                    let block = &self.board.plate[y][x];
+                    // 经典规则：库存的棋子只能下在空格上，除非吃掉对手快连成线的棋子
+                    let may_gobble = !V::DROP_ON_EMPTY_ONLY
+                        || match block.get_outermost_token() {
+                            None => true,
+                            Some(top) => {
+                                top.color != action.player && self.board.is_in_near_line(x, y)
+                            }
+                        };
+                    self.players[action.player as usize].has_token(token.size)
+                        && block.is_stackable(token)
+                        && may_gobble
                 } else {
                     false
                 }
             }
             ActionType::FromBoard => {
-                let from_block = &self.board.plate[action.from_xy.unwrap()[1]][action.from_xy.unwrap()[0]];
+                let from_block =
+                    &self.board.plate[action.from_xy.unwrap()[1]][action.from_xy.unwrap()[0]];
                 if let Some(token) = from_block.get_outermost_token() {
                     token.color == action.player
                         && self.board.plate[action.to_xy.unwrap()[1]][action.to_xy.unwrap()[0]]
-                        .is_stackable(token)
+                            .is_stackable(token)
                         && !(action.from_xy.unwrap() == action.to_xy.unwrap())
                 } else {
                     false
@@ -515,7 +897,7 @@ impl Gobblet {
             }
         }
     }
-    pub fn parse_action(&mut self, action: Action) -> bool {
+    pub fn parse_action(&mut self, action: Action<V>) -> bool {
         match action.action_type {
             ActionType::FromInventory => {
                 if let Some(token) = action.from_inventory {
@@ -545,10 +927,17 @@ impl Gobblet {
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::variant::{Classic, Gobblers};
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use synthesis::config::{
+        ActionSelection, AlphaBetaConfig, Exploration, Fpu, MCTSConfig, PolicyNoise,
+    };
+    use synthesis::policies::{playout, RolloutPolicy};
+    use synthesis::prelude::{alpha_beta, AlphaBeta, MCTS};
 
     #[test]
     fn test_token_creation() {
@@ -569,15 +958,14 @@ mod tests {
         assert!(token_str.contains("🔴"));
     }
 
-
     #[test]
     fn test_block_creation() {
         let block = Block::default();
-        assert!(block.tokens.is_empty());
+        assert!(block.is_empty());
 
         let token = Token::new(PlayerId::RED, Size::BIG);
         let block_with_token = Block::new(vec![token]);
-        assert_eq!(block_with_token.tokens.len(), 1);
+        assert_eq!(block_with_token.len(), 1);
     }
 
     #[test]
@@ -588,16 +976,16 @@ mod tests {
 
         // 初始状态可以放置任何棋子
         assert!(block.push_token(token_small));
-        assert_eq!(block.tokens.len(), 1);
+        assert_eq!(block.len(), 1);
 
         // 测试堆叠不可堆叠的棋子（尺寸相同或更小）
         let token_small_red = Token::new(PlayerId::GREEN, Size::SMALL);
         assert!(!block.push_token(token_small_red));
-        assert_eq!(block.tokens.len(), 1); // 数量不变
+        assert_eq!(block.len(), 1); // 数量不变
 
         // 测试堆叠更大的棋子
         assert!(block.push_token(token_mid));
-        assert_eq!(block.tokens.len(), 2);
+        assert_eq!(block.len(), 2);
         assert_eq!(block.get_outermost_token().unwrap(), token_mid);
     }
 
@@ -636,7 +1024,7 @@ mod tests {
     fn test_action_conversion() {
         // 测试从 usize 转换为 Action
         let action_id = 0;
-        let action = Action::from(action_id);
+        let action = Action::<Gobblers>::decode(PlayerId::RED, action_id);
         assert_eq!(action.action_type, ActionType::FromInventory);
 
         let action_id_back: usize = action.into();
@@ -645,8 +1033,8 @@ mod tests {
 
     #[test]
     fn test_action_iterator() {
-        let game = Gobblet::new();
-        let actions: Vec<Action> = game.iter_actions().collect();
+        let game = Gobblet::<Gobblers>::new();
+        let actions: Vec<Action<Gobblers>> = game.iter_actions().collect();
 
         // 检查是否生成了合法的行动
         assert!(!actions.is_empty());
@@ -659,10 +1047,10 @@ mod tests {
 
     #[test]
     fn test_valid_actions_after_move() {
-        let mut game = Gobblet::new();
+        let mut game = Gobblet::<Gobblers>::new();
 
         // 获取初始的合法行动数量
-        let initial_actions: Vec<Action> = game.iter_actions().collect();
+        let initial_actions: Vec<Action<Gobblers>> = game.iter_actions().collect();
         let initial_action_count = initial_actions.len();
 
         // 执行一个行动
@@ -670,28 +1058,27 @@ mod tests {
         game.step(&action);
 
         // 获取新的合法行动数量
-        let new_actions: Vec<Action> = game.iter_actions().collect();
+        let new_actions: Vec<Action<Gobblers>> = game.iter_actions().collect();
         let new_action_count = new_actions.len();
 
         // 检查行动数量是否发生变化
         assert!(new_action_count <= initial_action_count);
     }
 
-
     #[test]
     fn test_player_creation() {
-        let player_red = Player::new(PlayerId::RED);
+        let player_red = Player::<Gobblers>::new(PlayerId::RED);
         assert_eq!(player_red.color, PlayerId::RED);
-        assert_eq!(player_red.inventory, [2, 2, 2]); // 每种尺寸2个棋子
+        assert_eq!(player_red.inventory, [2, 2, 2, 0]); // 每种尺寸2个棋子
 
-        let player_green = Player::new(PlayerId::GREEN);
+        let player_green = Player::<Gobblers>::new(PlayerId::GREEN);
         assert_eq!(player_green.color, PlayerId::GREEN);
-        assert_eq!(player_green.inventory, [2, 2, 2]);
+        assert_eq!(player_green.inventory, [2, 2, 2, 0]);
     }
 
     #[test]
     fn test_player_get_token() {
-        let mut player = Player::new(PlayerId::RED);
+        let mut player = Player::<Gobblers>::new(PlayerId::RED);
 
         // 获取一个小尺寸的棋子
         let token = player.get_token(Size::SMALL);
@@ -706,8 +1093,8 @@ mod tests {
 
     #[test]
     fn test_player_place_from_inventory() {
-        let mut player = Player::new(PlayerId::RED);
-        let mut board = Board::new();
+        let mut player = Player::<Gobblers>::new(PlayerId::RED);
+        let mut board = Board::<Gobblers>::new();
 
         // 成功放置棋子
         let success = player.place_from_inventory(Size::SMALL, &mut board, 0, 0);
@@ -719,7 +1106,7 @@ mod tests {
         );
 
         // 在同一位置尝试放置更小或相同尺寸的棋子（应失败）
-        let mut player_green = Player::new(PlayerId::GREEN);
+        let mut player_green = Player::<Gobblers>::new(PlayerId::GREEN);
         let fail = player_green.place_from_inventory(Size::SMALL, &mut board, 0, 0);
         assert!(!fail);
 
@@ -734,8 +1121,8 @@ mod tests {
 
     #[test]
     fn test_player_swap_token_from_board() {
-        let mut player_red = Player::new(PlayerId::RED);
-        let mut board = Board::new();
+        let mut player_red = Player::<Gobblers>::new(PlayerId::RED);
+        let mut board = Board::<Gobblers>::new();
 
         // 玩家在 (0, 0) 放置一个小尺寸的棋子
         player_red.place_from_inventory(Size::SMALL, &mut board, 0, 0);
@@ -750,8 +1137,777 @@ mod tests {
         );
 
         // 尝试移动其他玩家的棋子（应失败）
-        let mut player_green = Player::new(PlayerId::GREEN);
+        let mut player_green = Player::<Gobblers>::new(PlayerId::GREEN);
         let fail = player_green.swap_token_from_board(&mut board, 1, 1, 2, 2);
         assert!(!fail);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_action_conversion_round_trip() {
+        for action_id in 0..Gobblers::NUM_ACTIONS {
+            let action = Action::<Gobblers>::decode(PlayerId::GREEN, action_id);
+            assert_eq!(action.player, PlayerId::GREEN);
+            let action_id_back: usize = action.into();
+            assert_eq!(action_id, action_id_back);
+        }
+        for action_id in 0..Classic::NUM_ACTIONS {
+            let action = Action::<Classic>::decode(PlayerId::RED, action_id);
+            let action_id_back: usize = action.into();
+            assert_eq!(action_id, action_id_back);
+        }
+        assert_eq!(Gobblers::NUM_ACTIONS, 108);
+        assert_eq!(Classic::NUM_ACTIONS, 320);
+    }
+
+    #[test]
+    fn test_classic_player_creation() {
+        let player = Player::<Classic>::new(PlayerId::RED);
+        assert_eq!(player.inventory, [3, 3, 3, 3]);
+        // 只有棋堆最外层的最大棋子可以拿
+        assert!(player.has_token(Size::HUGE));
+        assert!(!player.has_token(Size::BIG));
+        assert!(!player.has_token(Size::MID));
+        assert!(!player.has_token(Size::SMALL));
+    }
+
+    #[test]
+    fn test_classic_nested_reserve() {
+        let mut player = Player::<Classic>::new(PlayerId::RED);
+        let mut board = Board::<Classic>::new();
+
+        // 拿走一个最大的棋子后，这一堆露出了次大的棋子
+        assert!(player.place_from_inventory(Size::HUGE, &mut board, 0, 0));
+        assert!(player.has_token(Size::HUGE));
+        assert!(player.has_token(Size::BIG));
+        assert!(!player.has_token(Size::MID));
+
+        assert!(player.place_from_inventory(Size::BIG, &mut board, 1, 0));
+        assert!(player.has_token(Size::MID));
+        assert!(!player.place_from_inventory(Size::SMALL, &mut board, 2, 0));
+        assert!(player.place_from_inventory(Size::MID, &mut board, 2, 0));
+        assert!(player.place_from_inventory(Size::SMALL, &mut board, 3, 0));
+        assert_eq!(player.inventory, [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_classic_actions() {
+        let mut game = Gobblet::<Classic>::new();
+        let actions: Vec<Action<Classic>> = game.iter_actions().collect();
+        // 开局只能把最大的棋子放到 16 个空格之一
+        assert_eq!(actions.len(), 16);
+        for action in actions.iter() {
+            assert_eq!(action.from_inventory.unwrap().size, Size::HUGE);
+        }
+
+        game.step(&actions[0]);
+        assert_eq!(game.player(), PlayerId::GREEN);
+        assert!(!game.is_over());
+    }
+
+    // 红方两颗超大棋子在左列，绿方在最下面一行有一颗超大和一颗大棋子，轮到红方
+    fn classic_opening() -> Gobblet<Classic> {
+        let mut game = Gobblet::<Classic>::new();
+        for (size, x, y) in [
+            (Size::HUGE, 0, 0),
+            (Size::HUGE, 0, 3),
+            (Size::HUGE, 0, 1),
+            (Size::BIG, 1, 3),
+            (Size::BIG, 2, 0),
+        ] {
+            let action = Action::from_inventory(game.player(), size, [x, y]);
+            assert!(game.is_action_valid(&action));
+            assert!(!game.step(&action));
+        }
+        game
+    }
+
+    #[test]
+    fn test_classic_drops_on_empty_only() {
+        let mut game = classic_opening();
+        let drop_big = Action::from_inventory(PlayerId::GREEN, Size::HUGE, [2, 0]);
+        assert!(!game.is_action_valid(&drop_big));
+        let shift_big = Action::from_board(PlayerId::GREEN, [0, 3], [2, 0]);
+        assert!(game.is_action_valid(&shift_big));
+        assert!(game
+            .iter_actions()
+            .all(|action| action.action_type == ActionType::FromBoard
+                || game.board.plate[action.to_xy.unwrap()[1]][action.to_xy.unwrap()[0]]
+                    .get_outermost_token()
+                    .is_none()));
+
+        // 绿方只有两颗在一行，红方还不能从库存吃掉它们
+        assert!(!game.step(&Action::from_inventory(PlayerId::GREEN, Size::HUGE, [3, 2])));
+        let gobble = Action::from_inventory(PlayerId::RED, Size::HUGE, [1, 3]);
+        assert!(!game.is_action_valid(&gobble));
+        assert!(!game.iter_actions().any(|action| action == gobble));
+    }
+
+    #[test]
+    fn test_classic_drop_gobbles_near_line() {
+        let mut game = classic_opening();
+        assert!(!game.step(&Action::from_inventory(PlayerId::GREEN, Size::HUGE, [2, 3])));
+
+        // 绿方在最下面一行有三颗，红方可以从库存吃掉其中比自己小的，但不能盖住自己的棋子
+        let gobble = Action::from_inventory(PlayerId::RED, Size::HUGE, [1, 3]);
+        assert!(game.is_action_valid(&gobble));
+        assert!(game.iter_actions().any(|action| action == gobble));
+        assert!(!game.is_action_valid(&Action::from_inventory(PlayerId::RED, Size::HUGE, [2, 3])));
+        assert!(!game.is_action_valid(&Action::from_inventory(PlayerId::RED, Size::HUGE, [2, 0])));
+        assert!(!game.step(&gobble));
+        assert!(!game.board.is_in_near_line(1, 3));
+    }
+
+    #[test]
+    fn test_gobblers_allows_any_size() {
+        let game = Gobblet::<Gobblers>::new();
+        let actions: Vec<Action<Gobblers>> = game.iter_actions().collect();
+        assert_eq!(actions.len(), 27);
+    }
+    enum Move {
+        Drop(Size, usize, usize),
+        Shift([usize; 2], [usize; 2]),
+    }
+
+    fn play(game: &mut Gobblet<Gobblers>, moves: &[Move]) {
+        for mv in moves {
+            let action = match *mv {
+                Move::Drop(size, x, y) => Action::from_inventory(game.player(), size, [x, y]),
+                Move::Shift(from, to) => Action::from_board(game.player(), from, to),
+            };
+            assert!(game.is_action_valid(&action));
+            assert!(!game.step(&action));
+        }
+    }
+
+    #[test]
+    fn test_features_shape() {
+        let game = Gobblet::<Gobblers>::new();
+        let features = game.features();
+        assert_eq!(features.as_ref().len(), 13 * 3 * 3);
+        assert_eq!(
+            Gobblers::DIMS.iter().product::<i64>() as usize,
+            features.as_ref().len()
+        );
+
+        let game = Gobblet::<Classic>::new();
+        let features = game.features();
+        assert_eq!(features.as_ref().len(), 17 * 4 * 4);
+        assert_eq!(
+            Classic::DIMS.iter().product::<i64>() as usize,
+            features.as_ref().len()
+        );
+    }
+
+    #[test]
+    fn test_features_buried_tokens() {
+        // 两个局面最上层的棋子完全一样，但大棋子下面盖住的红色棋子不同
+        use Move::Drop;
+        let mut a = Gobblet::<Gobblers>::new();
+        play(
+            &mut a,
+            &[
+                Drop(Size::SMALL, 0, 0),
+                Drop(Size::BIG, 0, 0),
+                Drop(Size::MID, 2, 2),
+                Drop(Size::BIG, 2, 2),
+            ],
+        );
+        let mut b = Gobblet::<Gobblers>::new();
+        play(
+            &mut b,
+            &[
+                Drop(Size::MID, 0, 0),
+                Drop(Size::BIG, 0, 0),
+                Drop(Size::SMALL, 2, 2),
+                Drop(Size::BIG, 2, 2),
+            ],
+        );
+
+        for (row_a, row_b) in a.board.plate.iter().zip(b.board.plate.iter()) {
+            for (block_a, block_b) in row_a.iter().zip(row_b.iter()) {
+                assert_eq!(block_a.get_outermost_token(), block_b.get_outermost_token());
+            }
+        }
+        assert_eq!(a.players, b.players);
+        assert_eq!(a.player(), b.player());
+        assert_ne!(a.features(), b.features());
+    }
+
+    #[test]
+    fn test_features_inventory() {
+        use Move::Drop;
+        let mut a = Gobblet::<Gobblers>::new();
+        play(&mut a, &[Drop(Size::SMALL, 0, 0), Drop(Size::BIG, 0, 0)]);
+        let mut b = Gobblet::<Gobblers>::new();
+        play(&mut b, &[Drop(Size::MID, 0, 0), Drop(Size::BIG, 0, 0)]);
+        assert_ne!(a.features(), b.features());
+
+        // 红方的小棋子还剩一个，位于“自己的库存”那几层
+        let features = a.features();
+        let cells = 9;
+        let plane = 2 * 3 + Size::SMALL as usize;
+        assert!(features[plane * cells..(plane + 1) * cells]
+            .iter()
+            .all(|&v| v == 0.5));
+        let plane = 2 * 3 + Size::MID as usize;
+        assert!(features[plane * cells..(plane + 1) * cells]
+            .iter()
+            .all(|&v| v == 1.0));
+    }
+
+    #[test]
+    fn test_features_side_to_move() {
+        // 红方绕一圈回到原位，棋盘相同但轮到绿方
+        use Move::{Drop, Shift};
+        let mut a = Gobblet::<Gobblers>::new();
+        play(&mut a, &[Drop(Size::SMALL, 0, 0), Drop(Size::SMALL, 2, 2)]);
+        let mut b = a.clone();
+        play(
+            &mut b,
+            &[
+                Shift([0, 0], [0, 1]),
+                Shift([2, 2], [2, 1]),
+                Shift([0, 1], [1, 1]),
+                Shift([2, 1], [2, 2]),
+                Shift([1, 1], [0, 0]),
+            ],
+        );
+        assert_eq!(a.board, b.board);
+        assert_ne!(a.player(), b.player());
+        assert_ne!(a.features(), b.features());
+
+        let cells = 9;
+        let last = Gobblers::NUM_PLANES - 1;
+        assert!(a.features()[last * cells..].iter().all(|&v| v == 1.0));
+        assert!(b.features()[last * cells..].iter().all(|&v| v == 0.0));
+    }
+
+    fn mcts_cfg() -> MCTSConfig {
+        MCTSConfig {
+            exploration: Exploration::PolynomialUct { c: 2.0 },
+            solve: true,
+            fpu: Fpu::Const(1.0),
+            select_solved_nodes: true,
+            correct_values_on_solve: true,
+            auto_extend: true,
+            root_policy_noise: PolicyNoise::None,
+        }
+    }
+
+    #[test]
+    fn test_green_mcts_action_is_legal() {
+        // 红方先走一步，MCTS 给绿方选的动作必须属于绿方并且合法
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut game = Gobblet::<Gobblers>::new();
+        assert!(!game.step(&Action::from_inventory(PlayerId::RED, Size::SMALL, [1, 1])));
+        assert_eq!(game.player(), PlayerId::GREEN);
+        for selection in [ActionSelection::Q, ActionSelection::NumVisits] {
+            let action = MCTS::exploit(200, mcts_cfg(), &mut policy, game.clone(), selection);
+            assert_eq!(action.player, PlayerId::GREEN);
+            assert!(game.is_action_valid(&action));
+        }
+        let action = MCTS::exploit(
+            200,
+            mcts_cfg(),
+            &mut policy,
+            game.clone(),
+            ActionSelection::Q,
+        );
+        game.step(&action);
+        assert_eq!(game.player(), PlayerId::RED);
+
+        let mut game = Gobblet::<Classic>::new();
+        assert!(!game.step(&Action::from_inventory(PlayerId::RED, Size::HUGE, [0, 0])));
+        let selection = ActionSelection::NumVisits;
+        let action = MCTS::exploit(64, mcts_cfg(), &mut policy, game.clone(), selection);
+        assert_eq!(action.player, PlayerId::GREEN);
+        assert!(game.is_action_valid(&action));
+    }
+
+    #[test]
+    fn test_symmetry_actions() {
+        for i in 0..8 {
+            let inverse = <Gobblet<Gobblers> as Symmetries<108>>::inverse(i);
+            let mut seen = [false; 108];
+            for action_id in 0..108 {
+                let mapped = <Gobblet<Gobblers> as Symmetries<108>>::map_action(i, action_id);
+                assert!(!seen[mapped]);
+                seen[mapped] = true;
+                let back = <Gobblet<Gobblers> as Symmetries<108>>::map_action(inverse, mapped);
+                assert_eq!(back, action_id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_symmetry_commutes_with_step() {
+        // 先变换再走对应的动作，和先走再变换，得到同一个局面
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let mut game = Gobblet::<Classic>::new();
+            while !game.is_over() {
+                let actions: Vec<Action<Classic>> = game.iter_actions().collect();
+                let action = actions[rng.gen_range(0..actions.len())];
+                let action_id: usize = action.into();
+                for i in 0..8 {
+                    let mut image = game.map_state(i);
+                    let mapped = image.decode_action(Gobblet::<Classic>::map_action(i, action_id));
+                    assert!(image.is_action_valid(&mapped));
+                    image.step(&mapped);
+                    let mut stepped = game.clone();
+                    stepped.step(&action);
+                    assert_eq!(image, stepped.map_state(i));
+                }
+                game.step(&action);
+            }
+        }
+    }
+
+    #[test]
+    fn test_notation() {
+        let mut game = Gobblet::<Classic>::new();
+        let drop = Action::from_inventory(PlayerId::RED, Size::BIG, [1, 1]);
+        assert_eq!(game.notation(&drop), "B@b2");
+        assert_eq!(game.parse_notation("B@b2"), Some(drop));
+        game.step(&drop);
+        let drop = Action::from_inventory(PlayerId::GREEN, Size::HUGE, [3, 0]);
+        assert_eq!(game.notation(&drop), "H@d1");
+        game.step(&drop);
+        let moved = Action::from_board(PlayerId::RED, [1, 1], [0, 3]);
+        assert_eq!(game.notation(&moved), "b2-a4");
+        assert_eq!(game.parse_notation("b2-a4"), Some(moved));
+
+        // 超出棋盘或者这个变体没有的尺寸
+        assert_eq!(game.parse_notation("e1-a1"), None);
+        assert_eq!(game.parse_notation("a0-a1"), None);
+        assert_eq!(game.parse_notation("X@a1"), None);
+        assert_eq!(Gobblet::<Gobblers>::new().parse_notation("H@a1"), None);
+        assert_eq!(Gobblet::<Gobblers>::new().parse_notation("a1-d1"), None);
+    }
+
+    #[test]
+    fn test_notation_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let mut game = Gobblet::<Classic>::new();
+            while !game.is_over() {
+                for action in game.iter_actions() {
+                    assert_eq!(game.parse_notation(&game.notation(&action)), Some(action));
+                }
+                let actions: Vec<Action<Classic>> = game.iter_actions().collect();
+                game.step(&actions[rng.gen_range(0..actions.len())]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_symmetry_canonical() {
+        let mut game = Gobblet::<Gobblers>::new();
+        play(
+            &mut game,
+            &[Move::Drop(Size::SMALL, 0, 0), Move::Drop(Size::MID, 1, 0)],
+        );
+        let (canonical, i) = game.canonical();
+        assert_eq!(canonical, game.map_state(i));
+        for j in 0..8 {
+            assert_eq!(game.map_state(j).canonical().0, canonical);
+        }
+        let images = game.symmetries(&[0.0; 108]);
+        assert_eq!(images.len(), 8);
+        assert_eq!(images[0].0, game);
+
+        // 只有中间一颗棋子时所有变换都得到同一个局面
+        let mut game = Gobblet::<Gobblers>::new();
+        play(&mut game, &[Move::Drop(Size::SMALL, 1, 1)]);
+        assert_eq!(game.symmetries(&[0.0; 108]).len(), 1);
+    }
+
+    #[test]
+    fn test_threefold_repetition() {
+        use Move::{Drop, Shift};
+        let mut game = Gobblet::<Gobblers>::new();
+        play(
+            &mut game,
+            &[Drop(Size::SMALL, 0, 0), Drop(Size::SMALL, 2, 2)],
+        );
+        let cycle = [
+            Shift([0, 0], [0, 1]),
+            Shift([2, 2], [2, 1]),
+            Shift([0, 1], [0, 0]),
+            Shift([2, 1], [2, 2]),
+        ];
+        // 第二次回到这个局面还没结束
+        play(&mut game, &cycle);
+        assert_eq!(game.result(), None);
+        play(&mut game, &cycle[..3]);
+        let action = Action::from_board(game.player(), [2, 1], [2, 2]);
+        assert!(game.step(&action));
+        assert_eq!(game.result(), Some(GameResult::Repetition));
+        assert_eq!(game.reward(PlayerId::RED), 0.0);
+        assert!(game.iter_actions().next().is_some());
+        assert!(!game.step(&Action::from_board(game.player(), [0, 0], [0, 1])));
+    }
+
+    #[test]
+    fn test_repetitions_can_be_set() {
+        use Move::{Drop, Shift};
+        let cycle = [
+            Shift([0, 0], [0, 1]),
+            Shift([2, 2], [2, 1]),
+            Shift([0, 1], [0, 0]),
+            Shift([2, 1], [2, 2]),
+        ];
+        // 回到开局后的局面几次才和棋
+        let cycles_to_draw = |repetitions| {
+            let mut game = Gobblet::<Gobblers>::new().with_repetitions(repetitions);
+            play(
+                &mut game,
+                &[Drop(Size::SMALL, 0, 0), Drop(Size::SMALL, 2, 2)],
+            );
+            let mut num_cycles = 1;
+            loop {
+                play(&mut game, &cycle[..3]);
+                if game.step(&Action::from_board(game.player(), [2, 1], [2, 2])) {
+                    break;
+                }
+                num_cycles += 1;
+            }
+            assert_eq!(game.result(), Some(GameResult::Repetition));
+            assert_eq!(game.map_state(1).repetitions, repetitions);
+            num_cycles
+        };
+        assert_eq!(cycles_to_draw(DEFAULT_REPETITIONS), 2);
+        assert_eq!(cycles_to_draw(2), 1);
+        assert_eq!(cycles_to_draw(5), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 2 repetitions")]
+    fn test_one_repetition_is_rejected() {
+        Gobblet::<Gobblers>::new().with_repetitions(1);
+    }
+
+    #[test]
+    fn test_drop_resets_repetition() {
+        let mut game = Gobblet::<Gobblers>::new();
+        assert_eq!(game.history().count(), 1);
+        play(
+            &mut game,
+            &[Move::Drop(Size::SMALL, 0, 0), Move::Drop(Size::SMALL, 2, 2)],
+        );
+        assert_eq!(game.history().count(), 1);
+        play(&mut game, &[Move::Shift([0, 0], [0, 1])]);
+        assert_eq!(game.history().count(), 2);
+        play(&mut game, &[Move::Drop(Size::SMALL, 1, 1)]);
+        assert_eq!(game.history().count(), 1);
+    }
+
+    #[test]
+    fn test_turn_limit() {
+        let mut game = Gobblet::<Gobblers>::new();
+        game.turn_count = Gobblers::MAX_TURNS - 1;
+        assert_eq!(game.result(), None);
+        assert!(game.step(&Action::from_inventory(PlayerId::RED, Size::SMALL, [0, 0])));
+        assert_eq!(game.result(), Some(GameResult::TurnLimit));
+        assert_eq!(game.reward(PlayerId::RED), 0.0);
+    }
+
+    #[test]
+    fn test_zobrist_tracks_position() {
+        // 走法顺序不同但局面相同时哈希一样
+        use Move::Drop;
+        let mut a = Gobblet::<Gobblers>::new();
+        play(
+            &mut a,
+            &[
+                Drop(Size::SMALL, 0, 0),
+                Drop(Size::BIG, 2, 2),
+                Drop(Size::MID, 1, 0),
+            ],
+        );
+        let mut b = Gobblet::<Gobblers>::new();
+        play(
+            &mut b,
+            &[
+                Drop(Size::MID, 1, 0),
+                Drop(Size::BIG, 2, 2),
+                Drop(Size::SMALL, 0, 0),
+            ],
+        );
+        assert_eq!(a.zobrist(), b.zobrist());
+        let mut c = Gobblet::<Gobblers>::new();
+        play(
+            &mut c,
+            &[
+                Drop(Size::MID, 1, 0),
+                Drop(Size::BIG, 2, 1),
+                Drop(Size::SMALL, 0, 0),
+            ],
+        );
+        assert_ne!(a.zobrist(), c.zobrist());
+    }
+
+    #[test]
+    fn test_block_tokens_bottom_to_top() {
+        let mut block = Block::default();
+        block.push_token(Token::new(PlayerId::GREEN, Size::SMALL));
+        block.push_token(Token::new(PlayerId::RED, Size::BIG));
+        let tokens: Vec<Token> = block.tokens().collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(PlayerId::GREEN, Size::SMALL),
+                Token::new(PlayerId::RED, Size::BIG)
+            ]
+        );
+        assert_eq!(
+            block.pop_outermost_token(),
+            Token::new(PlayerId::RED, Size::BIG)
+        );
+        assert_eq!(
+            block.get_outermost_token(),
+            Some(Token::new(PlayerId::GREEN, Size::SMALL))
+        );
+    }
+
+    #[test]
+    fn test_incremental_zobrist() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let mut game = Gobblet::<Classic>::new();
+            while !game.is_over() {
+                let actions: Vec<Action<Classic>> = game.iter_actions().collect();
+                game.step(&actions[rng.gen_range(0..actions.len())]);
+                assert_eq!(game.zobrist(), game.compute_zobrist());
+            }
+        }
+    }
+
+    #[test]
+    fn test_equality_ignores_move_order() {
+        // 不同的走法到达同一局面，两局游戏相等，哈希也相等
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+        use Move::{Drop, Shift};
+        let mut a = Gobblet::<Gobblers>::new();
+        play(
+            &mut a,
+            &[
+                Drop(Size::SMALL, 0, 0),
+                Drop(Size::BIG, 2, 2),
+                Drop(Size::MID, 1, 0),
+            ],
+        );
+        let mut b = Gobblet::<Gobblers>::new();
+        play(
+            &mut b,
+            &[
+                Drop(Size::MID, 1, 0),
+                Drop(Size::BIG, 2, 1),
+                Drop(Size::SMALL, 0, 0),
+            ],
+        );
+        assert_ne!(a, b);
+
+        play(
+            &mut a,
+            &[
+                Shift([2, 2], [2, 1]),
+                Shift([1, 0], [1, 1]),
+                Shift([2, 1], [2, 0]),
+            ],
+        );
+        play(
+            &mut b,
+            &[
+                Shift([2, 1], [2, 0]),
+                Shift([1, 0], [1, 2]),
+                Shift([2, 0], [2, 2]),
+                Shift([1, 2], [1, 1]),
+                Shift([2, 2], [2, 0]),
+            ],
+        );
+        assert_ne!(a.turn_count, b.turn_count);
+        assert_eq!(a, b);
+        let hash = |game: &Gobblet<Gobblers>| {
+            let mut hasher = DefaultHasher::new();
+            game.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&a), hash(&b));
+    }
+
+    // everything undo has to put back, including the parts equality ignores
+    #[allow(clippy::type_complexity)]
+    fn snapshot<V: Variant>(
+        game: &Gobblet<V>,
+    ) -> (Gobblet<V>, u64, Vec<u64>, usize, Option<PlayerId>, bool) {
+        let history = game.history().collect();
+        (
+            game.clone(),
+            game.zobrist(),
+            history,
+            game.turn_count,
+            game.winner,
+            game.repeated,
+        )
+    }
+
+    // plays the actions picked by `choices` and takes them back one by one
+    fn check_undo<V: ActionSpace<N>, const N: usize>(
+        choices: &[usize],
+    ) -> Result<(), TestCaseError> {
+        let mut game = Gobblet::<V>::new();
+        let mut played = Vec::new();
+        for &choice in choices {
+            if game.is_over() {
+                break;
+            }
+            let actions: Vec<Action<V>> = game.iter_actions().collect();
+            let action = actions[choice % actions.len()];
+            played.push((snapshot(&game), action));
+            game.step(&action);
+        }
+        for (before, action) in played.into_iter().rev() {
+            game.undo(&action);
+            prop_assert_eq!(snapshot(&game), before);
+        }
+        Ok(())
+    }
+
+    // plays the actions picked by `choices`, then takes them back on the `i`th image of the game,
+    // whose history starts after the last of them
+    fn check_undo_image<V: ActionSpace<N>, const N: usize>(
+        choices: &[usize],
+        i: usize,
+    ) -> Result<(), TestCaseError> {
+        let mut game = Gobblet::<V>::new();
+        let mut played = Vec::new();
+        for &choice in choices {
+            if game.is_over() {
+                break;
+            }
+            let actions: Vec<Action<V>> = game.iter_actions().collect();
+            let action = actions[choice % actions.len()];
+            played.push((game.map_state(i), action));
+            game.step(&action);
+        }
+        let mut image = game.map_state(i);
+        for (before, action) in played.into_iter().rev() {
+            let mapped = Gobblet::<V>::map_action(i, action.into());
+            image.undo(&Action::decode(action.player, mapped));
+            prop_assert_eq!(snapshot(&image), snapshot(&before));
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_step_undo_gobblers(choices in prop::collection::vec(any::<usize>(), 0..100)) {
+            check_undo::<Gobblers, { Gobblers::NUM_ACTIONS }>(&choices)?;
+        }
+
+        #[test]
+        fn test_undo_through_image(
+            choices in prop::collection::vec(any::<usize>(), 0..60),
+            i in 0..8usize,
+        ) {
+            check_undo_image::<Gobblers, { Gobblers::NUM_ACTIONS }>(&choices, i)?;
+        }
+
+        #[test]
+        fn test_step_undo_classic(choices in prop::collection::vec(any::<usize>(), 0..150)) {
+            check_undo::<Classic, { Classic::NUM_ACTIONS }>(&choices)?;
+        }
+    }
+
+    #[test]
+    fn test_undo_repetition() {
+        let mut game = Gobblet::<Gobblers>::new();
+        play(
+            &mut game,
+            &[Move::Drop(Size::SMALL, 0, 0), Move::Drop(Size::SMALL, 2, 2)],
+        );
+        let shuffle = [
+            Move::Shift([0, 0], [0, 1]),
+            Move::Shift([2, 2], [2, 1]),
+            Move::Shift([0, 1], [0, 0]),
+            Move::Shift([2, 1], [2, 2]),
+        ];
+        play(&mut game, &shuffle);
+        play(&mut game, &shuffle[..3]);
+        let last = Action::from_board(game.player(), [2, 1], [2, 2]);
+        assert!(game.step(&last));
+        assert_eq!(game.result(), Some(GameResult::Repetition));
+
+        // 撤销后不再是重复局面，再走一次又是
+        game.undo(&last);
+        assert_eq!(game.result(), None);
+        assert!(game.step(&last));
+        assert_eq!(game.result(), Some(GameResult::Repetition));
+    }
+
+    #[test]
+    fn test_playout_restores_game() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = Gobblet::<Gobblers>::new();
+        play(
+            &mut game,
+            &[Move::Drop(Size::MID, 1, 1), Move::Drop(Size::BIG, 0, 0)],
+        );
+        let before = snapshot(&game);
+        for _ in 0..20 {
+            let reward = playout(&mut game, &mut rng);
+            assert!([-1.0, 0.0, 1.0].contains(&reward));
+            assert_eq!(snapshot(&game), before);
+        }
+    }
+
+    #[test]
+    fn test_alpha_beta_finds_win() {
+        // 红方第一行已有两颗棋子，轮到红方
+        let mut game = Gobblet::<Gobblers>::new();
+        use Move::Drop;
+        play(
+            &mut game,
+            &[
+                Drop(Size::SMALL, 0, 0),
+                Drop(Size::SMALL, 0, 2),
+                Drop(Size::SMALL, 1, 0),
+                Drop(Size::SMALL, 1, 2),
+            ],
+        );
+        let before = snapshot(&game);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut eval = |game: &mut Gobblet<Gobblers>| playout(game, &mut rng);
+        let value = alpha_beta(&mut game, 1, f32::NEG_INFINITY, f32::INFINITY, &mut eval);
+        assert_eq!(value, 1.0);
+        assert_eq!(snapshot(&game), before);
+    }
+
+    #[test]
+    fn test_alpha_beta_node_budget() {
+        // 4x4 开局的分支很多，第二层搜索到一半就会用完节点
+        let game = Gobblet::<Classic>::new();
+        let cfg = AlphaBetaConfig {
+            max_depth: 8,
+            max_nodes: 2000,
+            table_capacity: 1 << 16,
+            order_by_policy: false,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let eval = |policy: &mut RolloutPolicy<StdRng>, game: &mut Gobblet<Classic>| {
+            playout(game, policy.rng)
+        };
+        let mut engine =
+            AlphaBeta::<_, _, _, { Classic::NUM_ACTIONS }>::new(cfg, &mut policy, eval);
+        let (action, _) = engine.search(game.clone());
+        assert!(
+            engine.num_nodes() <= cfg.max_nodes,
+            "{} nodes",
+            engine.num_nodes()
+        );
+        assert!(game.iter_actions().any(|a| a == action));
+    }
+}
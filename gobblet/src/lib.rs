@@ -0,0 +1,6 @@
+pub mod gobblet;
+pub mod policies;
+pub mod rules;
+pub mod solver;
+pub mod variant;
+mod zobrist;
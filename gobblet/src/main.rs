@@ -1,19 +1,20 @@
-mod gobblet;
-
-mod policies;
+use gobblet::gobblet::Gobblet;
+use gobblet::policies::*;
+use gobblet::solver::OutcomeTable;
+use gobblet::variant::{Classic, Gobblers, Variant};
+use synthesis::prelude::*;
 
-use rand::{distributions::Distribution, thread_rng};
-use rand_distr::Normal;
+// written by `cargo run --release -- solve`
+const TABLE_PATH: &str = "./_logs/gobblers.table";
 
-use crate::gobblet::Gobblet;
-use crate::policies::*;
-use synthesis::prelude::*;
+const USAGE: &str = "usage: gobblet [solve | classic] [resnet] [--config run.toml]";
 
-fn learn<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>() -> Result<(), Box<dyn std::error::Error>> {
+fn default_config() -> RunConfig {
     let cfg = LearningConfig {
-        seed: 0,                              // seed for rng & torch
-        logs: train_dir("./_logs", G::NAME)?, // log directory
-        num_iterations: 200,                  // number of training iterations to run
+        seed: 0,                  // seed for rng & torch
+        logs: Default::default(), // log directory, empty for a new one under ./_logs
+        resume: false,            // continue from the checkpoint in logs if there is one
+        num_iterations: 200,      // number of training iterations to run
 
         lr_schedule: vec![(1, 1e-3), (20, 5e-4), (40, 1e-4), (60, 5e-5), (80, 1e-5)], // schedule for lr - first item in tuple is iteration #
         weight_decay: 1e-6, // L2 regularization for Adam optimizer
@@ -21,9 +22,11 @@ fn learn<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>
         batch_size: 32,     // size of batches that epochs are split into
         policy_weight: 1.0, // scalar for policy loss
         value_weight: 1.0,  // scalar for value loss
+        augment_symmetries: true, // train on every symmetric image of each position
 
         games_to_keep: 20000,  // number of games to keep in replay buffer
         games_per_train: 1000, // number of new games to add to replay buffer per training iteration
+        preload_records: Vec::new(), // .games files of earlier runs to start the replay buffer with
 
         rollout_cfg: RolloutConfig {
             num_workers: 1,                     // number of processes to use for running games
@@ -33,6 +36,8 @@ fn learn<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>
             stop_games_when_solved: false,      // end games early if they are solved by MCTS
             value_target: ValueTarget::Q,       // the target for NN value function
             action: ActionSelection::NumVisits, // the value to use for best action
+            reuse_tree: true,                   // keep searching the subtree of the played action
+            eval_batch_size: 8,                 // leaves per forward pass, 1 explores serially
 
             mcts_cfg: MCTSConfig {
                 exploration: Exploration::PolynomialUct { c: 3.0 }, // type of exploration to use (e.g. PUCT or UCT)
@@ -40,18 +45,17 @@ fn learn<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>
                 correct_values_on_solve: true, // if node is solved, adjust previously backprop'd values
                 select_solved_nodes: true,     // select nodes that are solved
                 auto_extend: true,             // visit nodes until a node with > 1 child is reached
-                fpu: Fpu::Func(|| {
-                    // exploit value of un-evaluated nodes
-                    let dist = Normal::new(1.0, 0.1).unwrap();
-                    dist.sample(&mut thread_rng())
-                }),
+                fpu: Fpu::Normal { mean: 1.0, std: 0.1 }, // exploit value of un-evaluated nodes
                 root_policy_noise: PolicyNoise::None,
             },
         },
+        gating: None, // Some(GatingConfig) makes each new model pass an SPRT against the best before self-play uses it
     };
 
     let eval_cfg = EvaluationConfig {
-        logs: cfg.logs.clone(),
+        logs: Default::default(), // always the learning logs
+        resume: false,            // always the learning resume
+        num_workers: 4,           // threads playing evaluation games, each with its own copy of the models
 
         policy_num_explores: cfg.rollout_cfg.num_explores,
         policy_action: ActionSelection::NumVisits,
@@ -69,6 +73,7 @@ fn learn<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>
         num_best_policies: 10,
 
         num_games_against_rollout: 5,
+        num_games_against_oracle: 5,
         rollout_num_explores: vec![800, 1600, 3200, 6400, 12800, 25600, 51200, 102400, 204800],
         rollout_action: ActionSelection::Q,
         rollout_mcts_cfg: MCTSConfig {
@@ -80,17 +85,99 @@ fn learn<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>
             fpu: Fpu::Const(f32::INFINITY),
             root_policy_noise: PolicyNoise::None,
         },
+
+        num_games_against_alpha_beta: 5,
+        alpha_beta_max_nodes: vec![1000, 4000, 16000, 64000],
+        alpha_beta_num_playouts: 1, // leaves are scored by the mean of this many random playouts
+        alpha_beta_cfg: AlphaBetaConfig {
+            max_depth: 64,
+            max_nodes: 0, // set per opponent from alpha_beta_max_nodes
+            table_capacity: 1 << 20,
+            order_by_policy: false, // classical baseline, no net to order by
+        },
     };
 
+    RunConfig {
+        learning: cfg,
+        evaluation: eval_cfg,
+    }
+}
+
+fn learn<G: 'static + Symmetries<N> + Undo<N> + Notation<N>, P: BatchPolicy<G, N> + NNPolicy<G, N>, const N: usize>(
+    config: Option<&str>,
+    oracle: Option<Box<dyn Oracle<G, N>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut run_cfg = match config {
+        Some(path) => RunConfig::load(path)?,
+        None => default_config(),
+    };
+    if run_cfg.learning.logs.as_os_str().is_empty() {
+        run_cfg.learning.logs = train_dir("./_logs", G::NAME)?;
+    }
+    run_cfg.evaluation.logs = run_cfg.learning.logs.clone();
+    run_cfg.evaluation.resume = run_cfg.learning.resume;
+    // before the evaluator starts, it empties results.pgn of a run that doesn't resume
+    check_new_run(&run_cfg.learning.logs, run_cfg.learning.resume)?;
+    std::fs::create_dir_all(&run_cfg.learning.logs)?;
+    run_cfg.save(run_cfg.learning.logs.join("config.toml"))?;
+    let RunConfig {
+        learning: cfg,
+        evaluation: eval_cfg,
+    } = run_cfg;
+
     tch::set_num_threads(1);
     tch::set_num_interop_threads(1);
 
-    let eval_handle = std::thread::spawn(move || evaluator::<G, P, N>(&eval_cfg).unwrap());
+    let eval_handle = std::thread::spawn(move || evaluator::<G, P, N>(&eval_cfg, oracle).unwrap());
     alpha_zero::<G, P, N>(&cfg)?;
     eval_handle.join().unwrap();
     Ok(())
 }
 
+// the solved 3x3 table if it has been written, the evaluator then also plays perfect play
+fn gobblers_oracle() -> Option<Box<dyn Oracle<Gobblet<Gobblers>, { Gobblers::NUM_ACTIONS }>>> {
+    if !std::path::Path::new(TABLE_PATH).exists() {
+        return None;
+    }
+    Some(Box::new(OutcomeTable::<Gobblers>::load(TABLE_PATH).unwrap()))
+}
+
+fn solve() -> std::io::Result<()> {
+    let table = OutcomeTable::<Gobblers>::solve();
+    for (n, len) in table.layer_lens().iter().enumerate().rev() {
+        println!("Solved {} positions with {} tokens", len, n);
+    }
+    println!("Solved {} positions, the first player gets {:?}", table.len(), table.outcome(&Gobblet::new()));
+    std::fs::create_dir_all("./_logs")?;
+    table.save(TABLE_PATH)
+}
+
 fn main() {
-    learn::<Gobblet, GobbletNet, { Gobblet::MAX_NUM_ACTIONS }>().unwrap()
+    // cargo run --release -- classic 训练 4x4 的经典版本
+    // cargo run --release -- gobblers resnet 用 ResNet 代替 MLP
+    // cargo run --release -- solve 求解 3x3 版本，之后的评估会和完美对手下棋
+    // cargo run --release -- classic --config run.toml 从文件读取超参数，不用重新编译
+    let mut args: Vec<String> = std::env::args().collect();
+    let config = take_config_arg(&mut args).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+    let config = config.as_deref();
+    let variant = args.get(1).map(String::as_str);
+    let arch = args.get(2).map(String::as_str);
+    match (variant, arch) {
+        (Some("solve"), _) => solve().unwrap(),
+        (Some("classic"), Some("resnet")) => {
+            learn::<Gobblet<Classic>, GobbletResNet<Classic>, { Classic::NUM_ACTIONS }>(config, None).unwrap()
+        }
+        (Some("classic"), _) => {
+            learn::<Gobblet<Classic>, GobbletNet<Classic>, { Classic::NUM_ACTIONS }>(config, None).unwrap()
+        }
+        (_, Some("resnet")) => {
+            learn::<Gobblet<Gobblers>, GobbletResNet<Gobblers>, { Gobblers::NUM_ACTIONS }>(config, gobblers_oracle())
+                .unwrap()
+        }
+        _ => learn::<Gobblet<Gobblers>, GobbletNet<Gobblers>, { Gobblers::NUM_ACTIONS }>(config, gobblers_oracle())
+            .unwrap(),
+    }
 }
@@ -1,27 +1,33 @@
+use std::marker::PhantomData;
+
 use crate::gobblet::Gobblet;
+use crate::variant::{ActionSpace, Variant};
 use synthesis::prelude::*;
 use tch::{self, nn, Tensor};
 
-pub struct GobbletNet {
+pub struct GobbletNet<V> {
     l_1: nn::Linear,
     l_2: nn::Linear,
     l_3: nn::Linear,
     l_4: nn::Linear,
     l_5: nn::Linear,
+    variant: PhantomData<V>,
 }
 
-impl NNPolicy<Gobblet, { Gobblet::MAX_NUM_ACTIONS }> for GobbletNet {
+impl<V: ActionSpace<N>, const N: usize> NNPolicy<Gobblet<V>, N> for GobbletNet<V> {
     fn new(vs: &nn::VarStore) -> Self {
         let root = &vs.root();
-        let state_dims = Gobblet::DIMS;
-        assert!(state_dims.len() == 3);
-        assert!(&state_dims == &[3,3,6]);
+        let state_dims = V::DIMS;
+        assert!(state_dims.len() == 4);
+        // 输入是展平后的所有特征层，输出是 N 个动作的 logits 加上 3 个结果
+        let num_features = state_dims[1..].iter().product::<i64>();
         Self {
-            l_1: nn::linear(root / "l_1", 18, 128, Default::default()),
+            l_1: nn::linear(root / "l_1", num_features, 128, Default::default()),
             l_2: nn::linear(root / "l_2", 128, 96, Default::default()),
             l_3: nn::linear(root / "l_3", 96, 64, Default::default()),
             l_4: nn::linear(root / "l_4", 64, 48, Default::default()),
-            l_5: nn::linear(root / "l_5", 48, 12, Default::default()),
+            l_5: nn::linear(root / "l_5", 48, N as i64 + 3, Default::default()),
+            variant: PhantomData,
         }
     }
 
@@ -37,20 +43,171 @@ impl NNPolicy<Gobblet, { Gobblet::MAX_NUM_ACTIONS }> for GobbletNet {
             .apply(&self.l_4)
             .relu()
             .apply(&self.l_5);
-        let mut ts = xs.split_with_sizes(&[9, 3], -1);
+        let mut ts = xs.split_with_sizes(&[N as i64, 3], -1);
         let outcome_logits = ts.pop().unwrap();
         let policy_logits = ts.pop().unwrap();
         (policy_logits, outcome_logits)
     }
 }
 
-impl Policy<Gobblet, { Gobblet::MAX_NUM_ACTIONS }> for GobbletNet {
-    fn eval(&mut self, env: &Gobblet) -> ([f32; Gobblet::MAX_NUM_ACTIONS], [f32; 3]) {
+impl<V: ActionSpace<N>, const N: usize> Policy<Gobblet<V>, N> for GobbletNet<V> {
+    fn eval(&mut self, env: &Gobblet<V>) -> ([f32; N], [f32; 3]) {
         let xs = env.features();
-        let t = tensor(&xs, Gobblet::DIMS, tch::Kind::Float);
+        let t = tensor(&xs, V::DIMS, tch::Kind::Float);
+        let (logits, value) = self.forward(&t);
+        let mut policy = [0.0f32; N];
+        logits.copy_data(&mut policy, N);
+        let mut outcomes = [0.0f32; 3];
+        value
+            .softmax(-1, tch::Kind::Float)
+            .copy_data(&mut outcomes, 3);
+        (policy, outcomes)
+    }
+}
+
+impl<V: ActionSpace<N>, const N: usize> BatchPolicy<Gobblet<V>, N> for GobbletNet<V> {
+    fn eval_batch(&mut self, envs: &[Gobblet<V>]) -> Vec<([f32; N], [f32; 3])> {
+        let xs: Vec<_> = envs.iter().map(|env| env.features()).collect();
+        let mut dims = V::DIMS.to_owned();
+        dims[0] = envs.len() as i64;
+        let t = tensor(&xs, &dims, tch::Kind::Float);
         let (logits, value) = self.forward(&t);
-        let mut policy = [0.0f32; Gobblet::MAX_NUM_ACTIONS];
-        logits.copy_data(&mut policy, Gobblet::MAX_NUM_ACTIONS);
+        unbatch(&logits, &value)
+    }
+}
+
+// 3x3 卷积 + batch norm + relu
+struct ConvBlock {
+    conv: nn::Conv2D,
+    bn: nn::BatchNorm,
+}
+
+impl ConvBlock {
+    fn new(path: &nn::Path, c_in: i64, c_out: i64, ksize: i64) -> Self {
+        let cfg = nn::ConvConfig {
+            padding: ksize / 2,
+            bias: false,
+            ..Default::default()
+        };
+        Self {
+            conv: nn::conv2d(path / "conv", c_in, c_out, ksize, cfg),
+            bn: nn::batch_norm2d(path / "bn", c_out, Default::default()),
+        }
+    }
+
+    fn forward_t(&self, xs: &Tensor, train: bool) -> Tensor {
+        xs.apply(&self.conv).apply_t(&self.bn, train).relu()
+    }
+}
+
+// two conv layers with a skip connection around them
+struct ResBlock {
+    conv_1: ConvBlock,
+    conv_2: nn::Conv2D,
+    bn_2: nn::BatchNorm,
+}
+
+impl ResBlock {
+    fn new(path: &nn::Path, channels: i64) -> Self {
+        let cfg = nn::ConvConfig {
+            padding: 1,
+            bias: false,
+            ..Default::default()
+        };
+        Self {
+            conv_1: ConvBlock::new(&(path / "conv_1"), channels, channels, 3),
+            conv_2: nn::conv2d(path / "conv_2", channels, channels, 3, cfg),
+            bn_2: nn::batch_norm2d(path / "bn_2", channels, Default::default()),
+        }
+    }
+
+    fn forward_t(&self, xs: &Tensor, train: bool) -> Tensor {
+        let ys = self
+            .conv_1
+            .forward_t(xs, train)
+            .apply(&self.conv_2)
+            .apply_t(&self.bn_2, train);
+        (xs + ys).relu()
+    }
+}
+
+// ResNet 风格的网络，BLOCKS 个残差块，每层 CHANNELS 个通道，策略头和胜/平/负价值头分开
+// `forward` 用于训练 (batch norm 用当前 batch 的统计量)，`eval` 用于推理
+pub struct GobbletResNet<V, const BLOCKS: usize = 4, const CHANNELS: usize = 64> {
+    stem: ConvBlock,
+    tower: Vec<ResBlock>,
+    policy_conv: ConvBlock,
+    policy_fc: nn::Linear,
+    value_conv: ConvBlock,
+    value_fc_1: nn::Linear,
+    value_fc_2: nn::Linear,
+    variant: PhantomData<V>,
+}
+
+impl<V: Variant, const BLOCKS: usize, const CHANNELS: usize> GobbletResNet<V, BLOCKS, CHANNELS> {
+    fn forward_t(&self, xs: &Tensor, train: bool) -> (Tensor, Tensor) {
+        let mut xs = self.stem.forward_t(xs, train);
+        for block in self.tower.iter() {
+            xs = block.forward_t(&xs, train);
+        }
+        let policy_logits = self
+            .policy_conv
+            .forward_t(&xs, train)
+            .flat_view()
+            .apply(&self.policy_fc);
+        let outcome_logits = self
+            .value_conv
+            .forward_t(&xs, train)
+            .flat_view()
+            .apply(&self.value_fc_1)
+            .relu()
+            .apply(&self.value_fc_2);
+        (policy_logits, outcome_logits)
+    }
+}
+
+impl<V: ActionSpace<N>, const N: usize, const BLOCKS: usize, const CHANNELS: usize>
+    NNPolicy<Gobblet<V>, N> for GobbletResNet<V, BLOCKS, CHANNELS>
+{
+    fn new(vs: &nn::VarStore) -> Self {
+        let root = &vs.root();
+        let state_dims = V::DIMS;
+        assert!(state_dims.len() == 4);
+        let planes = state_dims[1];
+        let cells = state_dims[2] * state_dims[3];
+        let channels = CHANNELS as i64;
+        Self {
+            stem: ConvBlock::new(&(root / "stem"), planes, channels, 3),
+            tower: (0..BLOCKS)
+                .map(|i| ResBlock::new(&(root / format!("block_{}", i)), channels))
+                .collect(),
+            policy_conv: ConvBlock::new(&(root / "policy_conv"), channels, 2, 1),
+            policy_fc: nn::linear(root / "policy_fc", 2 * cells, N as i64, Default::default()),
+            value_conv: ConvBlock::new(&(root / "value_conv"), channels, 1, 1),
+            value_fc_1: nn::linear(root / "value_fc_1", cells, channels, Default::default()),
+            value_fc_2: nn::linear(root / "value_fc_2", channels, 3, Default::default()),
+            variant: PhantomData,
+        }
+    }
+
+    fn forward(&self, xs: &Tensor) -> (Tensor, Tensor) {
+        self.forward_t(xs, true)
+    }
+
+    fn forward_eval(&self, xs: &Tensor) -> (Tensor, Tensor) {
+        self.forward_t(xs, false)
+    }
+}
+
+impl<V: ActionSpace<N>, const N: usize, const BLOCKS: usize, const CHANNELS: usize>
+    Policy<Gobblet<V>, N> for GobbletResNet<V, BLOCKS, CHANNELS>
+{
+    fn eval(&mut self, env: &Gobblet<V>) -> ([f32; N], [f32; 3]) {
+        let xs = env.features();
+        let t = tensor(&xs, V::DIMS, tch::Kind::Float);
+        let (logits, value) = tch::no_grad(|| self.forward_t(&t, false));
+        let mut policy = [0.0f32; N];
+        logits.copy_data(&mut policy, N);
         let mut outcomes = [0.0f32; 3];
         value
             .softmax(-1, tch::Kind::Float)
@@ -58,3 +215,124 @@ impl Policy<Gobblet, { Gobblet::MAX_NUM_ACTIONS }> for GobbletNet {
         (policy, outcomes)
     }
 }
+
+// evaluated in inference mode like `eval`, `forward` would use the batch's own norm statistics
+impl<V: ActionSpace<N>, const N: usize, const BLOCKS: usize, const CHANNELS: usize>
+    BatchPolicy<Gobblet<V>, N> for GobbletResNet<V, BLOCKS, CHANNELS>
+{
+    fn eval_batch(&mut self, envs: &[Gobblet<V>]) -> Vec<([f32; N], [f32; 3])> {
+        let xs: Vec<_> = envs.iter().map(|env| env.features()).collect();
+        let mut dims = V::DIMS.to_owned();
+        dims[0] = envs.len() as i64;
+        let t = tensor(&xs, &dims, tch::Kind::Float);
+        let (logits, value) = tch::no_grad(|| self.forward_t(&t, false));
+        unbatch(&logits, &value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::{Classic, Gobblers};
+    use tch::nn::VarStore;
+
+    #[test]
+    fn test_eval_fresh_game() {
+        let vs = VarStore::new(tch::Device::Cpu);
+        let mut policy = GobbletNet::<Gobblers>::new(&vs);
+        policy.check_shapes().unwrap();
+        let (logits, outcomes) = policy.eval(&Gobblet::<Gobblers>::new());
+        assert_eq!(logits.len(), 108);
+        assert!(logits.iter().all(|l| l.is_finite()));
+        assert!((outcomes.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        let vs = VarStore::new(tch::Device::Cpu);
+        let mut policy = GobbletNet::<Classic>::new(&vs);
+        policy.check_shapes().unwrap();
+        let (logits, outcomes) = policy.eval(&Gobblet::<Classic>::new());
+        assert_eq!(logits.len(), 320);
+        assert!((outcomes.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_resnet_eval_fresh_game() {
+        let vs = VarStore::new(tch::Device::Cpu);
+        let mut policy = GobbletResNet::<Gobblers, 2, 16>::new(&vs);
+        policy.check_shapes().unwrap();
+        let (logits, outcomes) = policy.eval(&Gobblet::<Gobblers>::new());
+        assert_eq!(logits.len(), 108);
+        assert!(logits.iter().all(|l| l.is_finite()));
+        assert!((outcomes.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        let vs = VarStore::new(tch::Device::Cpu);
+        let mut policy = GobbletResNet::<Classic, 2, 16>::new(&vs);
+        policy.check_shapes().unwrap();
+        let (logits, outcomes) = policy.eval(&Gobblet::<Classic>::new());
+        assert_eq!(logits.len(), 320);
+        assert!((outcomes.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_check_shapes_keeps_norm_statistics() {
+        let vs = VarStore::new(tch::Device::Cpu);
+        let policy = GobbletResNet::<Gobblers, 2, 16>::new(&vs);
+        let stats = || {
+            let variables = vs.variables();
+            let mean = variables["stem.bn.running_mean"].copy();
+            let var = variables["stem.bn.running_var"].copy();
+            (mean, var)
+        };
+        let (mean, var) = stats();
+        policy.check_shapes().unwrap();
+        let (mean_after, var_after) = stats();
+        assert!(mean.equal(&mean_after));
+        assert!(var.equal(&var_after));
+    }
+
+    #[test]
+    fn test_eval_batch_matches_eval() {
+        let mut games = vec![Gobblet::<Gobblers>::new()];
+        for action in Gobblet::<Gobblers>::new().iter_actions().take(3) {
+            let mut game = Gobblet::<Gobblers>::new();
+            game.step(&action);
+            games.push(game);
+        }
+
+        let vs = VarStore::new(tch::Device::Cpu);
+        let mut policy = GobbletNet::<Gobblers>::new(&vs);
+        let outputs = policy.eval_batch(&games);
+        assert_eq!(outputs.len(), games.len());
+        for (game, (logits, outcomes)) in games.iter().zip(outputs) {
+            let (expected_logits, expected_outcomes) = policy.eval(game);
+            for (a, b) in logits.iter().zip(expected_logits.iter()) {
+                assert!((a - b).abs() < 1e-5);
+            }
+            for (a, b) in outcomes.iter().zip(expected_outcomes.iter()) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+
+        let vs = VarStore::new(tch::Device::Cpu);
+        let mut policy = GobbletResNet::<Gobblers, 2, 16>::new(&vs);
+        let outputs = policy.eval_batch(&games);
+        for (game, (logits, outcomes)) in games.iter().zip(outputs) {
+            let (expected_logits, expected_outcomes) = policy.eval(game);
+            for (a, b) in logits.iter().zip(expected_logits.iter()) {
+                assert!((a - b).abs() < 1e-5);
+            }
+            for (a, b) in outcomes.iter().zip(expected_outcomes.iter()) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resnet_batch_shapes() {
+        let vs = VarStore::new(tch::Device::Cpu);
+        let policy = GobbletResNet::<Gobblers, 1, 8>::new(&vs);
+        let xs = Tensor::zeros(&[4, 13, 3, 3], (tch::Kind::Float, tch::Device::Cpu));
+        let (logits, value) = policy.forward(&xs);
+        assert_eq!(logits.size(), [4, 108]);
+        assert_eq!(value.size(), [4, 3]);
+    }
+}
@@ -0,0 +1,184 @@
+// Official Gobblet rules for deciding who won after a move.
+//
+// A move from the board happens in two steps: the token is lifted, which may
+// uncover an opponent line underneath, and then it is re-placed on another
+// block. A revealed line wins for the opponent unless the re-placed token
+// covers it. If the mover completes a line of their own while an opponent line
+// stays revealed, the revealed line counts first and the opponent wins.
+use crate::gobblet::{Action, ActionType, Board, PlayerId};
+use crate::variant::Variant;
+
+pub type Line = Vec<(usize, usize)>;
+
+// 所有的行、列与对角线，以 (x, y) 表示
+pub fn all_lines<V: Variant>() -> Vec<Line> {
+    let size = V::SIZE;
+    let mut lines = Vec::with_capacity(2 * size + 2);
+    for i in 0..size {
+        lines.push((0..size).map(|j| (j, i)).collect());
+        lines.push((0..size).map(|j| (i, j)).collect());
+    }
+    lines.push((0..size).map(|i| (i, i)).collect());
+    lines.push((0..size).map(|i| (i, size - 1 - i)).collect());
+    lines
+}
+
+// lines where every outermost token belongs to `player`
+pub fn winning_lines<V: Variant>(board: &Board<V>, player: PlayerId) -> Vec<Line> {
+    all_lines::<V>()
+        .into_iter()
+        .filter(|line| board.check_line(line) == Some(player))
+        .collect()
+}
+
+// opponent lines uncovered by lifting the token an action moves, must be called before the action is applied
+pub fn revealed_lines<V: Variant>(board: &Board<V>, action: &Action<V>) -> Vec<Line> {
+    match action.action_type {
+        ActionType::FromInventory => Vec::new(),
+        ActionType::FromBoard => {
+            let [x, y] = action.from_xy.unwrap();
+            if board.plate[y][x].get_outermost_token().is_none() {
+                return Vec::new();
+            }
+            let mut lifted = board.clone();
+            lifted.plate[y][x].pop_outermost_token();
+            winning_lines(&lifted, !action.player)
+        }
+    }
+}
+
+// decides the winner once `action` has been applied to `board`
+pub fn judge<V: Variant>(board: &Board<V>, action: &Action<V>, revealed: &[Line]) -> Option<PlayerId> {
+    let mover = action.player;
+    let [to_x, to_y] = action.to_xy.unwrap();
+
+    // a revealed line only survives if the lifted token was not re-placed onto it
+    if revealed.iter().any(|line| !line.contains(&(to_x, to_y))) {
+        return Some(!mover);
+    }
+    if !winning_lines(board, mover).is_empty() {
+        return Some(mover);
+    }
+    // covering can only break opponent lines, but stay consistent if one is left
+    if !winning_lines(board, !mover).is_empty() {
+        return Some(!mover);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gobblet::{Gobblet, Size};
+    use crate::variant::{Classic, Gobblers};
+    use synthesis::game::{Game, GameResult};
+
+    fn drop_action(game: &Gobblet<Gobblers>, size: Size, x: usize, y: usize) -> Action<Gobblers> {
+        Action::from_inventory(game.player(), size, [x, y])
+    }
+
+    fn move_action(game: &Gobblet<Gobblers>, from: [usize; 2], to: [usize; 2]) -> Action<Gobblers> {
+        Action::from_board(game.player(), from, to)
+    }
+
+    // 绿方在第一行有两颗棋子，红方的大棋子盖住了中间的绿棋子，轮到红方
+    fn covered_green_row(red_row: usize) -> Gobblet<Gobblers> {
+        let mut game = Gobblet::<Gobblers>::new();
+        let moves = [
+            (Size::SMALL, 0, red_row),
+            (Size::SMALL, 0, 0),
+            (Size::SMALL, 2, red_row),
+            (Size::MID, 1, 0),
+            (Size::BIG, 1, 0),
+            (Size::SMALL, 2, 0),
+        ];
+        for (size, x, y) in moves {
+            let action = drop_action(&game, size, x, y);
+            assert!(game.is_action_valid(&action));
+            assert!(!game.step(&action));
+        }
+        assert_eq!(game.player(), PlayerId::RED);
+        game
+    }
+
+    #[test]
+    fn test_all_lines() {
+        let lines = all_lines::<Gobblers>();
+        assert_eq!(lines.len(), 8);
+        for line in lines.iter() {
+            assert_eq!(line.len(), 3);
+        }
+        let lines = all_lines::<Classic>();
+        assert_eq!(lines.len(), 10);
+        for line in lines.iter() {
+            assert_eq!(line.len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_drop_completes_line() {
+        let mut game = Gobblet::<Gobblers>::new();
+        for (size, x, y) in [
+            (Size::SMALL, 0, 0),
+            (Size::SMALL, 0, 1),
+            (Size::MID, 1, 0),
+            (Size::MID, 1, 1),
+        ] {
+            let action = drop_action(&game, size, x, y);
+            assert!(!game.step(&action));
+        }
+        let action = drop_action(&game, Size::BIG, 2, 0);
+        assert!(game.step(&action));
+        assert_eq!(game.reward(PlayerId::RED), 1.0);
+        assert_eq!(game.reward(PlayerId::GREEN), -1.0);
+        assert_eq!(game.result(), Some(GameResult::Win(PlayerId::RED)));
+    }
+
+    #[test]
+    fn test_revealed_line_loses() {
+        let mut game = covered_green_row(2);
+        let action = move_action(&game, [1, 0], [1, 1]);
+        assert_eq!(revealed_lines(&game.board, &action).len(), 1);
+        assert!(game.step(&action));
+        assert_eq!(game.reward(PlayerId::GREEN), 1.0);
+        assert_eq!(game.reward(PlayerId::RED), -1.0);
+    }
+
+    #[test]
+    fn test_revealed_line_covered_by_lifted_token() {
+        let mut game = covered_green_row(2);
+        let action = move_action(&game, [1, 0], [0, 0]);
+        assert!(game.is_action_valid(&action));
+        assert!(!game.step(&action));
+        assert_eq!(game.reward(PlayerId::GREEN), 0.0);
+        assert_eq!(game.reward(PlayerId::RED), 0.0);
+    }
+
+    #[test]
+    fn test_revealed_line_beats_completed_line() {
+        // 红方移动大棋子同时完成自己的第二行，但绿方的第一行先被揭开
+        let mut game = covered_green_row(1);
+        let action = move_action(&game, [1, 0], [1, 1]);
+        assert!(game.step(&action));
+        assert_eq!(winning_lines(&game.board, PlayerId::RED).len(), 1);
+        assert_eq!(winning_lines(&game.board, PlayerId::GREEN).len(), 1);
+        assert_eq!(game.reward(PlayerId::GREEN), 1.0);
+        assert_eq!(game.reward(PlayerId::RED), -1.0);
+    }
+
+    #[test]
+    fn test_lifted_token_must_move() {
+        let mut game = covered_green_row(2);
+        let action = move_action(&game, [1, 0], [1, 0]);
+        assert!(!game.is_action_valid(&action));
+        assert!(!game.step(&action));
+        assert_eq!(game.player(), PlayerId::RED);
+    }
+
+    #[test]
+    fn test_cannot_move_opponent_token() {
+        let game = covered_green_row(2);
+        let action = move_action(&game, [0, 0], [1, 1]);
+        assert!(!game.is_action_valid(&action));
+    }
+}
@@ -0,0 +1,649 @@
+// Perfect play for 3x3 Gobblet variants by retrograde analysis.
+//
+// Positions are solved in layers by the number of tokens on the board, from the fullest board
+// down: a drop always adds a token, so a layer only depends on itself and the layer above.
+// Inside a layer, pass `d` finds the positions won or lost in exactly `d` plies. Whatever is
+// left once the passes stop changing anything can't be forced either way, and in a game it ends
+// by repetition, so it is a draw. The turn limit is ignored: a win that needs more turns than the
+// game has left is a draw over the board.
+//
+// Outcomes are from the view of the player to move, the same as `synthesis::game::Outcome` in
+// the MCTS solver. Symmetric positions are stored once.
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use synthesis::game::{Oracle, Outcome};
+
+use crate::gobblet::{map_xy, Gobblet};
+use crate::variant::{ActionSpace, Variant};
+
+const SIDE: usize = 3;
+const CELLS: usize = SIDE * SIDE;
+const CELL_BITS: usize = 6; // a block's mask, bit size * 2 + color
+const LINES: [[usize; SIDE]; 8] = [
+    [0, 1, 2],
+    [3, 4, 5],
+    [6, 7, 8],
+    [0, 3, 6],
+    [1, 4, 7],
+    [2, 5, 8],
+    [0, 4, 8],
+    [2, 4, 6],
+];
+
+// table entries are the position's key shifted past the outcome: 2 bits of kind, 7 of distance
+const OUTCOME_BITS: u32 = 9;
+const MAX_DISTANCE: usize = 127;
+const UNSOLVED: u16 = u16::MAX;
+const MAGIC: &[u8; 8] = b"GOBSOLV1";
+
+// bit 0 is set when GREEN is to move, cell y * 3 + x holds its mask at bit 1 + 6 * cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Position(u64);
+
+fn shift(cell: usize) -> usize {
+    1 + CELL_BITS * cell
+}
+
+// (size, color) of the outermost token of a block
+fn top(mask: u8) -> Option<(usize, usize)> {
+    if mask == 0 {
+        None
+    } else {
+        let bit = u8::BITS - 1 - mask.leading_zeros();
+        Some((bit as usize / 2, bit as usize % 2))
+    }
+}
+
+fn stackable(mask: u8, size: usize) -> bool {
+    top(mask).map_or(true, |(top_size, _)| top_size < size)
+}
+
+impl Position {
+    fn from_game<V: Variant>(game: &Gobblet<V>) -> Self {
+        let mut key = game.player as u64;
+        for (x, y, block) in game.board.blocks() {
+            key |= (block.mask() as u64) << shift(y * SIDE + x);
+        }
+        Position(key)
+    }
+
+    fn cell(self, cell: usize) -> u8 {
+        ((self.0 >> shift(cell)) & ((1 << CELL_BITS) - 1)) as u8
+    }
+
+    fn to_move(self) -> usize {
+        (self.0 & 1) as usize
+    }
+
+    fn num_tokens(self) -> usize {
+        (self.0 >> 1).count_ones() as usize
+    }
+
+    fn has_line(self, color: usize) -> bool {
+        LINES.iter().any(|line| {
+            line.iter()
+                .all(|&cell| matches!(top(self.cell(cell)), Some((_, c)) if c == color))
+        })
+    }
+
+    // a position with a line ended the game. If both players have one, the mover lifted a token
+    // off the opponent's line and the opponent wins, see `rules::judge`
+    fn terminal(self) -> Option<Outcome> {
+        if self.has_line(self.to_move()) {
+            Some(Outcome::Win(0))
+        } else if self.has_line(1 - self.to_move()) {
+            Some(Outcome::Lose(0))
+        } else {
+            None
+        }
+    }
+
+    // every position one legal action away, in no particular order
+    fn children<V: Variant>(self, out: &mut Vec<Position>) {
+        let color = self.to_move();
+        let flipped = self.0 ^ 1;
+        for size in 0..V::NUM_SIZES {
+            let bit = 1u64 << (size * 2 + color);
+            let on_board = (0..CELLS)
+                .filter(|&cell| self.0 & (bit << shift(cell)) != 0)
+                .count();
+            if on_board >= V::PIECES_PER_SIZE as usize {
+                continue;
+            }
+            for to in 0..CELLS {
+                if stackable(self.cell(to), size) {
+                    out.push(Position(flipped | bit << shift(to)));
+                }
+            }
+        }
+        for from in 0..CELLS {
+            let size = match top(self.cell(from)) {
+                Some((size, c)) if c == color => size,
+                _ => continue,
+            };
+            let bit = 1u64 << (size * 2 + color);
+            let lifted = flipped & !(bit << shift(from));
+            for to in 0..CELLS {
+                if to != from && stackable(self.cell(to), size) {
+                    out.push(Position(lifted | bit << shift(to)));
+                }
+            }
+        }
+    }
+
+    // the image with the smallest key stands for all 8 symmetries
+    fn canonical(self, symmetries: &[[usize; CELLS]; 8]) -> Self {
+        let mut best = self.0;
+        for cells in symmetries.iter().skip(1) {
+            let mut key = self.0 & 1;
+            for (cell, &image) in cells.iter().enumerate() {
+                key |= (self.cell(cell) as u64) << shift(image);
+            }
+            best = best.min(key);
+        }
+        Position(best)
+    }
+}
+
+fn symmetries<V: Variant>() -> [[usize; CELLS]; 8] {
+    let mut symmetries = [[0; CELLS]; 8];
+    for (i, cells) in symmetries.iter_mut().enumerate() {
+        for (cell, image) in cells.iter_mut().enumerate() {
+            let [x, y] = map_xy::<V>(i, [cell % SIDE, cell / SIDE]);
+            *image = y * SIDE + x;
+        }
+    }
+    symmetries
+}
+
+fn distance(outcome: Outcome) -> usize {
+    match outcome {
+        Outcome::Win(d) | Outcome::Lose(d) | Outcome::Draw(d) => d,
+    }
+}
+
+fn encode(outcome: Outcome) -> u16 {
+    let (kind, d) = match outcome {
+        Outcome::Lose(d) => (0, d),
+        Outcome::Draw(_) => (1, 0),
+        Outcome::Win(d) => (2, d),
+    };
+    assert!(d <= MAX_DISTANCE, "distance {} doesn't fit the table", d);
+    (kind << 7 | d) as u16
+}
+
+fn decode(bits: u16) -> Outcome {
+    let d = (bits & MAX_DISTANCE as u16) as usize;
+    match bits >> 7 {
+        0 => Outcome::Lose(d),
+        1 => Outcome::Draw(d),
+        _ => Outcome::Win(d),
+    }
+}
+
+fn lookup(layer: &[u64], key: u64) -> Option<Outcome> {
+    let i = layer
+        .binary_search_by_key(&key, |entry| entry >> OUTCOME_BITS)
+        .ok()?;
+    Some(decode((layer[i] & ((1 << OUTCOME_BITS) - 1)) as u16))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// Outcome of every non-terminal position reachable from where the solve started.
+pub struct OutcomeTable<V: Variant> {
+    layers: Vec<Vec<u64>>, // sorted entries, indexed by the number of tokens on the board
+    symmetries: [[usize; CELLS]; 8],
+    variant: PhantomData<V>,
+}
+
+impl<V: Variant> OutcomeTable<V> {
+    // solves the whole game. For Gobblers this is a few hundred million positions, so it takes
+    // a while and several GB of memory, run it once and `load` the saved table afterwards
+    pub fn solve() -> Self {
+        Self::solve_positions(Position(0))
+    }
+
+    // solves only what can still happen after `game`, its history doesn't matter
+    pub fn solve_from(game: &Gobblet<V>) -> Self {
+        Self::solve_positions(Position::from_game(game))
+    }
+
+    fn solve_positions(root: Position) -> Self {
+        assert!(
+            V::SIZE == SIDE && V::NUM_SIZES <= 3 && !V::NESTED_RESERVE && !V::DROP_ON_EMPTY_ONLY,
+            "the solver only handles 3x3 variants that play any reserve token anywhere"
+        );
+        let symmetries = symmetries::<V>();
+        let max_tokens = 2 * V::NUM_SIZES * V::PIECES_PER_SIZE as usize;
+        let mut layers = vec![Vec::new(); max_tokens + 1];
+        let mut children = Vec::new();
+
+        // reachable positions, each layer closed under moves before the drops out of it are known
+        let mut next = HashSet::new();
+        if root.terminal().is_none() {
+            next.insert(root.canonical(&symmetries).0);
+        }
+        for n in root.num_tokens()..=max_tokens {
+            let mut seen = std::mem::take(&mut next);
+            let mut stack: Vec<u64> = seen.iter().copied().collect();
+            while let Some(key) = stack.pop() {
+                Position(key).children::<V>(&mut children);
+                for child in children.drain(..) {
+                    if child.terminal().is_some() {
+                        continue;
+                    }
+                    let child = child.canonical(&symmetries);
+                    if child.num_tokens() > n {
+                        next.insert(child.0);
+                    } else if seen.insert(child.0) {
+                        stack.push(child.0);
+                    }
+                }
+            }
+            let mut layer: Vec<u64> = seen.into_iter().collect();
+            layer.sort_unstable();
+            layers[n] = layer;
+        }
+
+        let mut table = vec![Vec::new(); max_tokens + 1];
+        for n in (0..=max_tokens).rev() {
+            let keys = std::mem::take(&mut layers[n]);
+            let above: &[u64] = table.get(n + 1).map_or(&[], |layer: &Vec<u64>| layer);
+            let mut longest = above
+                .iter()
+                .map(|&entry| distance(decode((entry & ((1 << OUTCOME_BITS) - 1)) as u16)))
+                .max()
+                .unwrap_or(0);
+            let mut outcomes = vec![UNSOLVED; keys.len()];
+
+            for d in 1.. {
+                let mut changed = false;
+                for i in 0..keys.len() {
+                    if outcomes[i] != UNSOLVED {
+                        continue;
+                    }
+                    Position(keys[i]).children::<V>(&mut children);
+                    let mut wins = false;
+                    let mut loses = !children.is_empty();
+                    let mut slowest_win = 0;
+                    for child in children.drain(..) {
+                        let outcome = child.terminal().or_else(|| {
+                            let child = child.canonical(&symmetries);
+                            if child.num_tokens() > n {
+                                lookup(above, child.0)
+                            } else {
+                                // only outcomes from earlier passes, this pass finds distance d
+                                let j = keys.binary_search(&child.0).unwrap();
+                                Some(outcomes[j])
+                                    .filter(|&bits| bits != UNSOLVED)
+                                    .map(decode)
+                                    .filter(|&outcome| distance(outcome) < d)
+                            }
+                        });
+                        match outcome {
+                            Some(Outcome::Lose(k)) if k + 1 == d => wins = true,
+                            Some(Outcome::Win(k)) => slowest_win = slowest_win.max(k + 1),
+                            _ => loses = false,
+                        }
+                    }
+                    // losing takes as long as the opponent's slowest win, which can be more than d
+                    if wins {
+                        outcomes[i] = encode(Outcome::Win(d));
+                        longest = longest.max(d);
+                        changed = true;
+                    } else if loses {
+                        outcomes[i] = encode(Outcome::Lose(slowest_win));
+                        longest = longest.max(slowest_win);
+                        changed = true;
+                    }
+                }
+                // nothing known is d plies from the end, so no later pass can find d + 1
+                if !changed && d > longest {
+                    break;
+                }
+            }
+
+            table[n] = keys
+                .iter()
+                .zip(outcomes.iter())
+                .map(|(&key, &bits)| {
+                    let bits = if bits == UNSOLVED {
+                        encode(Outcome::Draw(0))
+                    } else {
+                        bits
+                    };
+                    key << OUTCOME_BITS | bits as u64
+                })
+                .collect();
+        }
+
+        Self {
+            layers: table,
+            symmetries,
+            variant: PhantomData,
+        }
+    }
+
+    // number of positions in the table
+    pub fn len(&self) -> usize {
+        self.layers.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // number of positions in the table by the number of tokens on the board
+    pub fn layer_lens(&self) -> Vec<usize> {
+        self.layers.iter().map(Vec::len).collect()
+    }
+
+    // None for positions the solve never reached
+    pub fn outcome(&self, game: &Gobblet<V>) -> Option<Outcome> {
+        let position = Position::from_game(game);
+        if let Some(outcome) = position.terminal() {
+            return Some(outcome);
+        }
+        let position = position.canonical(&self.symmetries);
+        lookup(self.layers.get(position.num_tokens())?, position.0)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(V::NAME.len() as u64).to_le_bytes())?;
+        writer.write_all(V::NAME.as_bytes())?;
+        writer.write_all(&(self.layers.len() as u64).to_le_bytes())?;
+        for layer in self.layers.iter() {
+            writer.write_all(&(layer.len() as u64).to_le_bytes())?;
+            for entry in layer.iter() {
+                writer.write_all(&entry.to_le_bytes())?;
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "not a Gobblet outcome table",
+            ));
+        }
+        let mut name = vec![0; read_u64(&mut reader)? as usize];
+        reader.read_exact(&mut name)?;
+        if name != V::NAME.as_bytes() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "table was solved for {}, not {}",
+                    String::from_utf8_lossy(&name),
+                    V::NAME
+                ),
+            ));
+        }
+        let num_layers = read_u64(&mut reader)? as usize;
+        if num_layers != 2 * V::NUM_SIZES * V::PIECES_PER_SIZE as usize + 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "table has the wrong number of layers",
+            ));
+        }
+        let mut layers = Vec::with_capacity(num_layers);
+        for _ in 0..num_layers {
+            let len = read_u64(&mut reader)? as usize;
+            let mut layer = Vec::with_capacity(len);
+            for _ in 0..len {
+                layer.push(read_u64(&mut reader)?);
+            }
+            layers.push(layer);
+        }
+        Ok(Self {
+            layers,
+            symmetries: symmetries::<V>(),
+            variant: PhantomData,
+        })
+    }
+}
+
+impl<V: ActionSpace<N>, const N: usize> Oracle<Gobblet<V>, N> for OutcomeTable<V> {
+    fn outcome(&self, game: &Gobblet<V>) -> Option<Outcome> {
+        OutcomeTable::outcome(self, game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gobblet::Action;
+    use crate::variant::Gobblers;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use synthesis::config::AlphaBetaConfig;
+    use synthesis::game::{Game, GameResult};
+    use synthesis::policies::RolloutPolicy;
+    use synthesis::prelude::{alpha_beta, AlphaBeta};
+
+    // small enough to solve in a test: Gobblers with a single token of each size, and with
+    // three tokens of a single size, which can't stack but can win or lose from afar
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Tiny;
+
+    impl Variant for Tiny {
+        const NAME: &'static str = "GobbletTiny";
+        const SIZE: usize = 3;
+        const NUM_SIZES: usize = 3;
+        const PIECES_PER_SIZE: u8 = 1;
+        const NESTED_RESERVE: bool = false;
+        const DROP_ON_EMPTY_ONLY: bool = false;
+        const MAX_TURNS: usize = 72;
+
+        type Features = [f32; 13 * 3 * 3];
+    }
+
+    impl ActionSpace<{ Tiny::NUM_ACTIONS }> for Tiny {}
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct OneSize;
+
+    impl Variant for OneSize {
+        const NAME: &'static str = "GobbletOneSize";
+        const SIZE: usize = 3;
+        const NUM_SIZES: usize = 1;
+        const PIECES_PER_SIZE: u8 = 3;
+        const NESTED_RESERVE: bool = false;
+        const DROP_ON_EMPTY_ONLY: bool = false;
+        const MAX_TURNS: usize = 72;
+
+        type Features = [f32; 5 * 3 * 3];
+    }
+
+    impl ActionSpace<{ OneSize::NUM_ACTIONS }> for OneSize {}
+
+    fn check_children<V: ActionSpace<N>, const N: usize>(seed: u64) {
+        let symmetries = symmetries::<V>();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut game = Gobblet::<V>::new();
+        let mut children = Vec::new();
+        while !game.is_over() {
+            let position = Position::from_game(&game);
+            assert_eq!(position.terminal(), None);
+            position.children::<V>(&mut children);
+            let mut expected: Vec<u64> = children
+                .drain(..)
+                .map(|child| child.canonical(&symmetries).0)
+                .collect();
+            let actions: Vec<Action<V>> = game.iter_actions().collect();
+            let mut found = Vec::new();
+            for action in actions.iter() {
+                let mut child = game.clone();
+                child.step(action);
+                let child_position = Position::from_game(&child);
+                // the table only knows wins by lines, repetitions depend on the history
+                let outcome = match child.result() {
+                    Some(GameResult::Win(_)) => Some(Outcome::from(child.reward(child.player()))),
+                    _ => None,
+                };
+                assert_eq!(child_position.terminal(), outcome);
+                found.push(child_position.canonical(&symmetries).0);
+            }
+            expected.sort_unstable();
+            found.sort_unstable();
+            assert_eq!(expected, found);
+            game.step(&actions[rng.gen_range(0..actions.len())]);
+        }
+    }
+
+    #[test]
+    fn test_children_match_rules() {
+        for seed in 0..20 {
+            check_children::<Gobblers, { Gobblers::NUM_ACTIONS }>(seed);
+            check_children::<Tiny, { Tiny::NUM_ACTIONS }>(seed);
+        }
+    }
+
+    #[test]
+    fn test_canonical_is_symmetric() {
+        let symmetries = symmetries::<Gobblers>();
+        let mut game = Gobblet::<Gobblers>::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..6 {
+            let actions: Vec<Action<Gobblers>> = game.iter_actions().collect();
+            game.step(&actions[rng.gen_range(0..actions.len())]);
+        }
+        let canonical = Position::from_game(&game).canonical(&symmetries);
+        for i in 0..8 {
+            let image = synthesis::game::Symmetries::map_state(&game, i);
+            assert_eq!(
+                Position::from_game(&image).canonical(&symmetries),
+                canonical
+            );
+        }
+    }
+
+    #[test]
+    fn test_solution_is_consistent() {
+        // every stored outcome must follow from the outcomes of its children
+        let table = OutcomeTable::<Tiny>::solve();
+        assert!(!table.is_empty());
+        let mut children = Vec::new();
+        for layer in table.layers.iter() {
+            for &entry in layer.iter() {
+                let position = Position(entry >> OUTCOME_BITS);
+                let outcome = decode((entry & ((1 << OUTCOME_BITS) - 1)) as u16);
+                position.children::<Tiny>(&mut children);
+                let best = children
+                    .drain(..)
+                    .map(|child| {
+                        let child = child.canonical(&table.symmetries);
+                        let outcome = child
+                            .terminal()
+                            .or_else(|| lookup(&table.layers[child.num_tokens()], child.0));
+                        outcome.unwrap().reversed()
+                    })
+                    .max()
+                    .unwrap();
+                match (outcome, best) {
+                    (Outcome::Draw(_), Outcome::Draw(_)) => {}
+                    _ => assert_eq!(outcome, best),
+                }
+            }
+        }
+    }
+
+    // same outcome, draws can't tell how long they last
+    fn assert_same(a: Outcome, b: Outcome) {
+        match (a, b) {
+            (Outcome::Draw(_), Outcome::Draw(_)) => {}
+            _ => assert_eq!(a, b),
+        }
+    }
+
+    #[test]
+    fn test_perfect_play() {
+        let table = OutcomeTable::<OneSize>::solve();
+        let start = Gobblet::<OneSize>::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut num_wins = 0;
+        for _ in 0..20 {
+            // random moves until one side can force a win a few plies out
+            let mut game = start.clone();
+            while !game.is_over() && !matches!(table.outcome(&game), Some(Outcome::Win(3..=5))) {
+                let actions: Vec<Action<OneSize>> = game.iter_actions().collect();
+                game.step(&actions[rng.gen_range(0..actions.len())]);
+            }
+            let mut d = match table.outcome(&game) {
+                Some(Outcome::Win(d)) if !game.is_over() => d,
+                _ => continue,
+            };
+            num_wins += 1;
+
+            // a search exactly that deep finds the win, one ply less doesn't
+            let mut eval = |_: &mut Gobblet<OneSize>| 0.0;
+            let inf = f32::INFINITY;
+            assert_eq!(alpha_beta(&mut game, d, -inf, inf, &mut eval), 1.0);
+            assert!(alpha_beta(&mut game, d - 1, -inf, inf, &mut eval) < 1.0);
+
+            // so does the engine with its table and ordering, and its action keeps the win
+            let cfg = AlphaBetaConfig {
+                max_depth: d,
+                max_nodes: usize::MAX,
+                table_capacity: 1 << 16,
+                order_by_policy: true,
+            };
+            let mut policy_rng = StdRng::seed_from_u64(0);
+            let mut policy = RolloutPolicy {
+                rng: &mut policy_rng,
+            };
+            let eval = |_: &mut RolloutPolicy<StdRng>, _: &mut Gobblet<OneSize>| 0.0;
+            let mut engine =
+                AlphaBeta::<_, _, _, { OneSize::NUM_ACTIONS }>::new(cfg, &mut policy, eval);
+            let (action, value) = engine.search(game.clone());
+            assert_eq!(value, 1.0);
+            let mut child = game.clone();
+            if !child.step(&action) {
+                assert!(matches!(table.outcome(&child), Some(Outcome::Lose(_))));
+            }
+
+            // perfect play on both sides keeps the outcome and wins right on time
+            let player = game.player();
+            while !game.is_over() {
+                let outcome = table.outcome(&game).unwrap();
+                let actions = Oracle::<_, { OneSize::NUM_ACTIONS }>::best_actions(&table, &game);
+                game.step(&actions[rng.gen_range(0..actions.len())]);
+                if !game.is_over() {
+                    assert_same(table.outcome(&game).unwrap().reversed(), outcome);
+                }
+                d -= 1;
+            }
+            assert_eq!(d, 0);
+            assert_eq!(game.reward(player), 1.0);
+        }
+        assert!(num_wins > 0);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let table = OutcomeTable::<OneSize>::solve();
+        let path =
+            std::env::temp_dir().join(format!("gobblet_one_size_{}.table", std::process::id()));
+        table.save(&path).unwrap();
+        let loaded = OutcomeTable::<OneSize>::load(&path).unwrap();
+        assert_eq!(loaded.layers, table.layers);
+        assert_eq!(loaded.layer_lens().iter().sum::<usize>(), table.len());
+        let err = OutcomeTable::<Gobblers>::load(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
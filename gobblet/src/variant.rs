@@ -0,0 +1,68 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+
+// largest number of token sizes any variant uses, inventories are sized by it
+pub const MAX_SIZES: usize = 4;
+// largest board side, boards are fixed MAX_SIDE x MAX_SIDE arrays and smaller variants use a corner
+pub const MAX_SIDE: usize = 4;
+
+// Board size, token sizes and reserve layout of a Gobblet variant.
+pub trait Variant: Clone + Copy + Debug + Default + PartialEq + Eq + PartialOrd + Ord + Hash + Send + 'static {
+    const NAME: &'static str;
+    const SIZE: usize; // width and height of the board
+    const NUM_SIZES: usize; // number of token sizes, at most MAX_SIZES
+    const PIECES_PER_SIZE: u8; // tokens of each size a player starts with
+    const NESTED_RESERVE: bool; // reserve is kept in nested stacks, only the top of a stack can be played
+    const DROP_ON_EMPTY_ONLY: bool; // reserve tokens only gobble in an opponent line of SIZE - 1
+    const MAX_TURNS: usize;
+
+    // feature planes: board tokens and reserve counts per player and size, plus side to move
+    const NUM_PLANES: usize = 4 * Self::NUM_SIZES + 1;
+    const DIMS: &'static [i64] = &[1, Self::NUM_PLANES as i64, Self::SIZE as i64, Self::SIZE as i64];
+
+    // flat NUM_PLANES * SIZE * SIZE array, fixed size so batches of states are contiguous
+    type Features: Copy + PartialEq + Debug + Send + AsRef<[f32]> + for<'a> TryFrom<&'a [f32]>;
+
+    // one drop action per size and block, one move action per pair of blocks
+    const NUM_ACTIONS: usize =
+        Self::NUM_SIZES * Self::SIZE * Self::SIZE + Self::SIZE * Self::SIZE * Self::SIZE * Self::SIZE;
+}
+
+// Ties a variant to the `N` it uses in `Game<N>`, always `Variant::NUM_ACTIONS`.
+pub trait ActionSpace<const N: usize>: Variant {}
+
+// 3x3 Gobblet Gobblers: three sizes, two of each, any token in the reserve can be played
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Gobblers;
+
+impl Variant for Gobblers {
+    const NAME: &'static str = "Gobblet";
+    const SIZE: usize = 3;
+    const NUM_SIZES: usize = 3;
+    const PIECES_PER_SIZE: u8 = 2;
+    const NESTED_RESERVE: bool = false;
+    const DROP_ON_EMPTY_ONLY: bool = false;
+    const MAX_TURNS: usize = 72; // 根据游戏规则调整
+
+    type Features = [f32; 13 * 3 * 3];
+}
+
+impl ActionSpace<{ Gobblers::NUM_ACTIONS }> for Gobblers {}
+
+// 4x4 classic Gobblet: four sizes, three nested external stacks per player
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Classic;
+
+impl Variant for Classic {
+    const NAME: &'static str = "GobbletClassic";
+    const SIZE: usize = 4;
+    const NUM_SIZES: usize = 4;
+    const PIECES_PER_SIZE: u8 = 3;
+    const NESTED_RESERVE: bool = true;
+    const DROP_ON_EMPTY_ONLY: bool = true;
+    const MAX_TURNS: usize = 128;
+
+    type Features = [f32; 17 * 4 * 4];
+}
+
+impl ActionSpace<{ Classic::NUM_ACTIONS }> for Classic {}
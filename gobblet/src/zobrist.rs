@@ -0,0 +1,61 @@
+// Zobrist keys for Gobblet positions, generated at compile time with splitmix64.
+// Tables are sized for the largest variant, smaller boards only use a prefix.
+use crate::gobblet::{PlayerId, Size};
+use crate::variant::{MAX_SIDE, MAX_SIZES};
+
+const MAX_CELLS: usize = MAX_SIDE * MAX_SIDE;
+const MAX_COUNT: usize = 4; // inventory counts 0..=PIECES_PER_SIZE
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+const fn keys<const K: usize>(seed: u64) -> [u64; K] {
+    let mut keys = [0; K];
+    let mut state = seed;
+    let mut i = 0;
+    while i < K {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+}
+
+const TOKEN_KEYS: [u64; MAX_CELLS * 2 * MAX_SIZES] = keys(1);
+const INVENTORY_KEYS: [u64; 2 * MAX_SIZES * MAX_COUNT] = keys(2);
+pub const GREEN_TO_MOVE: u64 = keys::<1>(3)[0];
+
+// 每个格子里同一尺寸最多一颗棋子，所以棋堆的顺序由棋子集合决定
+pub fn token(cell: usize, color: PlayerId, size: Size) -> u64 {
+    TOKEN_KEYS[(cell * 2 + color as usize) * MAX_SIZES + size as usize]
+}
+
+pub fn inventory(color: PlayerId, size: usize, count: u8) -> u64 {
+    INVENTORY_KEYS[(color as usize * MAX_SIZES + size) * MAX_COUNT + count as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_distinct() {
+        let mut all: Vec<u64> = TOKEN_KEYS
+            .iter()
+            .chain(INVENTORY_KEYS.iter())
+            .copied()
+            .collect();
+        all.push(GREEN_TO_MOVE);
+        let n = all.len();
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), n);
+        assert!(all.iter().all(|&k| k != 0));
+    }
+}
@@ -160,6 +160,42 @@ impl Iterator for FreeColumns {
     }
 }
 
+// 左右镜像，第 i 列和第 WIDTH - 1 - i 列互换
+fn mirror(bb: u64) -> u64 {
+    let mut mirrored = 0;
+    for col in 0..WIDTH {
+        let bits = (bb >> (HEIGHT * col)) & FAB_COL;
+        mirrored |= bits << (HEIGHT * (WIDTH - 1 - col));
+    }
+    mirrored
+}
+
+impl Symmetries<WIDTH> for Connect4 {
+    const NUM_SYMMETRIES: usize = 2;
+
+    fn map_state(&self, i: usize) -> Self {
+        if i == 0 {
+            return self.clone();
+        }
+        let mut height = self.height;
+        height.reverse();
+        Self {
+            my_bb: mirror(self.my_bb),
+            op_bb: mirror(self.op_bb),
+            height,
+            player: self.player,
+        }
+    }
+
+    fn map_action(i: usize, action_id: usize) -> usize {
+        if i == 0 {
+            action_id
+        } else {
+            WIDTH - 1 - action_id
+        }
+    }
+}
+
 impl Connect4 {
     fn winner(&self) -> Option<PlayerId> {
         if won(self.op_bb) {
@@ -218,6 +254,10 @@ impl Game<WIDTH> for Connect4 {
         }
     }
 
+    fn decode_action(&self, action_id: usize) -> Self::Action {
+        action_id.into()
+    }
+
     fn step(&mut self, action: &Self::Action) -> bool {
         let col: usize = (*action).into();
 
@@ -292,9 +332,87 @@ impl Game<WIDTH> for Connect4 {
     }
 }
 
+impl Undo<WIDTH> for Connect4 {
+    fn undo(&mut self, action: &Self::Action) {
+        let col: usize = (*action).into();
+
+        std::mem::swap(&mut self.my_bb, &mut self.op_bb);
+        self.player = self.player.prev();
+
+        self.height[col] -= 1;
+        self.my_bb ^= 1 << (self.height[col] + (HEIGHT as u8) * (col as u8));
+    }
+}
+
+// columns are written as letters, a is the leftmost
+impl Notation<WIDTH> for Connect4 {
+    fn notation(&self, action: &Self::Action) -> String {
+        String::from((b'a' + action.0) as char)
+    }
+
+    fn parse_notation(&self, text: &str) -> Option<Self::Action> {
+        match text.as_bytes() {
+            &[c] if c >= b'a' && c < b'a' + WIDTH as u8 => Some(Column(c - b'a')),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_step_undo(choices in prop::collection::vec(0..WIDTH, 0..70)) {
+            let mut game = Connect4::new();
+            let mut played = Vec::new();
+            for choice in choices {
+                if game.is_over() {
+                    break;
+                }
+                let actions: Vec<Column> = game.iter_actions().collect();
+                let action = actions[choice % actions.len()];
+                played.push((game.clone(), action));
+                game.step(&action);
+            }
+            for (before, action) in played.into_iter().rev() {
+                game.undo(&action);
+                prop_assert_eq!(&game, &before);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mirror() {
+        let mut game = Connect4::new();
+        let mut mirrored = game.map_state(1);
+        assert_eq!(game, mirrored);
+        for col in [0, 1, 1, 5, 8, 8, 3] {
+            game.step(&Column(col));
+            let col = Connect4::map_action(1, col as usize);
+            mirrored.step(&Column(col as u8));
+            assert_eq!(game.map_state(1), mirrored);
+            assert_eq!(mirrored.map_state(1), game);
+        }
+        assert_eq!(game.symmetries(&[0.0; WIDTH]).len(), 2);
+        assert_eq!(game.canonical().0, mirrored.canonical().0);
+    }
+
+    #[test]
+    fn test_notation() {
+        let game = Connect4::new();
+        for col in 0..WIDTH as u8 {
+            let text = game.notation(&Column(col));
+            assert_eq!(game.parse_notation(&text), Some(Column(col)));
+        }
+        assert_eq!(game.notation(&Column(0)), "a");
+        assert_eq!(game.notation(&Column(8)), "i");
+        assert_eq!(game.parse_notation("j"), None);
+        assert_eq!(game.parse_notation("ab"), None);
+        assert_eq!(game.parse_notation(""), None);
+    }
 
     #[test]
     fn test_first_wins() {
@@ -58,3 +58,17 @@ impl Policy<Connect4, { Connect4::MAX_NUM_ACTIONS }> for Connect4Net {
         (policy, outcomes)
     }
 }
+
+impl BatchPolicy<Connect4, { Connect4::MAX_NUM_ACTIONS }> for Connect4Net {
+    fn eval_batch(
+        &mut self,
+        envs: &[Connect4],
+    ) -> Vec<([f32; Connect4::MAX_NUM_ACTIONS], [f32; 3])> {
+        let xs: Vec<_> = envs.iter().map(|env| env.features()).collect();
+        let mut dims = Connect4::DIMS.to_owned();
+        dims[0] = envs.len() as i64;
+        let t = tensor(&xs, &dims, tch::Kind::Float);
+        let (logits, value) = self.forward(&t);
+        unbatch(&logits, &value)
+    }
+}
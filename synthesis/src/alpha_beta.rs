@@ -0,0 +1,342 @@
+use crate::config::AlphaBetaConfig;
+use crate::game::Undo;
+use crate::policies::Policy;
+use std::collections::HashMap;
+
+// Negamax with alpha-beta pruning. Scores are from the view of the player to move: the game's
+// reward once it is over, otherwise `eval` of the position `depth` actions deep. `eval` gets the
+// game mutably so it can search further in place, and must leave it as it found it.
+pub fn alpha_beta<G: Undo<N>, E: FnMut(&mut G) -> f32, const N: usize>(
+    game: &mut G,
+    depth: usize,
+    mut alpha: f32,
+    beta: f32,
+    eval: &mut E,
+) -> f32 {
+    if game.is_over() {
+        return game.reward(game.player());
+    }
+    if depth == 0 {
+        return eval(game);
+    }
+    let mut best = f32::NEG_INFINITY;
+    for action in game.iter_actions() {
+        game.step(&action);
+        let value = -alpha_beta(game, depth - 1, -beta, -alpha, eval);
+        game.undo(&action);
+        best = best.max(value);
+        alpha = alpha.max(value);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    Lower, // the search failed high, the value is at least this
+    Upper, // the search failed low, the value is at most this
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry<A> {
+    depth: usize,
+    value: f32,
+    bound: Bound,
+    best_action: Option<A>,
+}
+
+// Iterative deepening on top of negamax alpha-beta, with a transposition table and actions
+// ordered by the previous iteration's best action, then the policy's logits. `eval` scores
+// positions at the depth limit for the player to move and, like `alpha_beta`'s, must leave the
+// game as it found it. It gets the policy too, so it can use the value head.
+// Positions are looked up by the game's `Eq`, history it ignores is shared between transpositions.
+pub struct AlphaBeta<
+    'a,
+    G: Undo<N>,
+    P: Policy<G, N>,
+    E: FnMut(&mut P, &mut G) -> f32,
+    const N: usize,
+> {
+    cfg: AlphaBetaConfig,
+    policy: &'a mut P,
+    eval: E,
+    table: HashMap<G, Entry<G::Action>>,
+    num_nodes: usize,
+    depth: usize,  // of the current iteration
+    aborted: bool, // the current iteration ran out of nodes
+}
+
+impl<'a, G: Undo<N>, P: Policy<G, N>, E: FnMut(&mut P, &mut G) -> f32, const N: usize>
+    AlphaBeta<'a, G, P, E, N>
+{
+    pub fn exploit(cfg: AlphaBetaConfig, policy: &'a mut P, eval: E, game: G) -> G::Action {
+        let mut search = Self::new(cfg, policy, eval);
+        search.search(game).0
+    }
+
+    pub fn new(cfg: AlphaBetaConfig, policy: &'a mut P, eval: E) -> Self {
+        Self {
+            cfg,
+            policy,
+            eval,
+            table: HashMap::new(),
+            num_nodes: 0,
+            depth: 0,
+            aborted: false,
+        }
+    }
+
+    // nodes visited by the last `search`
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes
+    }
+
+    // best action and its value from the deepest finished iteration. An iteration that would
+    // visit more than `max_nodes` nodes is abandoned halfway, only the first one always finishes
+    // so there is an action to return.
+    pub fn search(&mut self, mut game: G) -> (G::Action, f32) {
+        assert!(!game.is_over());
+        self.table.clear();
+        self.num_nodes = 0;
+        self.aborted = false;
+        let mut best = None;
+        for depth in 1..=self.cfg.max_depth.max(1) {
+            self.depth = depth;
+            let value = self.negamax(&mut game, depth, f32::NEG_INFINITY, f32::INFINITY);
+            if self.aborted {
+                break;
+            }
+            let action = self.table[&game].best_action.unwrap();
+            best = Some((action, value));
+            if self.num_nodes >= self.cfg.max_nodes {
+                break;
+            }
+        }
+        best.unwrap()
+    }
+
+    fn negamax(&mut self, game: &mut G, depth: usize, mut alpha: f32, mut beta: f32) -> f32 {
+        if self.depth > 1 && self.num_nodes >= self.cfg.max_nodes {
+            self.aborted = true;
+            return 0.0;
+        }
+        self.num_nodes += 1;
+        if game.is_over() {
+            return game.reward(game.player());
+        }
+        if depth == 0 {
+            return (self.eval)(self.policy, game);
+        }
+
+        let first_alpha = alpha;
+        let mut table_action = None;
+        if let Some(entry) = self.table.get(game) {
+            table_action = entry.best_action;
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return entry.value,
+                    Bound::Lower => alpha = alpha.max(entry.value),
+                    Bound::Upper => beta = beta.min(entry.value),
+                }
+                if alpha >= beta {
+                    return entry.value;
+                }
+            }
+        }
+
+        let mut best = f32::NEG_INFINITY;
+        let mut best_action = None;
+        for action in self.ordered_actions(game, table_action) {
+            game.step(&action);
+            let value = -self.negamax(game, depth - 1, -beta, -alpha);
+            game.undo(&action);
+            if self.aborted {
+                // nothing below here is finished, so none of it goes into the table
+                return 0.0;
+            }
+            if value > best {
+                best = value;
+                best_action = Some(action);
+            }
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best <= first_alpha {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        let entry = Entry {
+            depth,
+            value: best,
+            bound,
+            best_action,
+        };
+        // deeper results are kept, and the root always gets in so its best action can be read back
+        let store = match self.table.get(game) {
+            Some(old) => old.depth <= depth,
+            None => self.table.len() < self.cfg.table_capacity || depth == self.depth,
+        };
+        if store {
+            self.table.insert(game.clone(), entry);
+        }
+        best
+    }
+
+    fn ordered_actions(&mut self, game: &G, first: Option<G::Action>) -> Vec<G::Action> {
+        let mut actions: Vec<G::Action> = game.iter_actions().collect();
+        if self.cfg.order_by_policy {
+            let (logits, _) = self.policy.eval(game);
+            // stable, so equal logits keep the game's order
+            actions.sort_by(|a, b| {
+                let a: usize = (*a).into();
+                let b: usize = (*b).into();
+                logits[b].total_cmp(&logits[a])
+            });
+        }
+        if let Some(first) = first {
+            if let Some(i) = actions.iter().position(|&a| a == first) {
+                actions[..=i].rotate_right(1);
+            }
+        }
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::mcts::tests::TicTacToe;
+    use crate::policies::RolloutPolicy;
+    use rand::prelude::{SeedableRng, StdRng};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    // a fixed score in [-1, 1] per position, so that the depth the search stops at matters
+    fn score(game: &TicTacToe) -> f32 {
+        let mut hasher = DefaultHasher::new();
+        game.hash(&mut hasher);
+        (hasher.finish() % 201) as f32 / 100.0 - 1.0
+    }
+
+    fn eval(_: &mut RolloutPolicy<StdRng>, game: &mut TicTacToe) -> f32 {
+        score(game)
+    }
+
+    fn position(actions: &[usize]) -> TicTacToe {
+        let mut game = TicTacToe::new();
+        for &action in actions {
+            assert!(!game.step(&action.into()));
+        }
+        game
+    }
+
+    fn negamax(game: &TicTacToe, depth: usize) -> f32 {
+        let mut game = game.clone();
+        alpha_beta(
+            &mut game,
+            depth,
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            &mut |game: &mut TicTacToe| score(game),
+        )
+    }
+
+    fn config(max_depth: usize, max_nodes: usize, table_capacity: usize) -> AlphaBetaConfig {
+        AlphaBetaConfig {
+            max_depth,
+            max_nodes,
+            table_capacity,
+            order_by_policy: false,
+        }
+    }
+
+    #[test]
+    fn test_matches_negamax() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        for actions in [&[][..], &[4], &[0, 4], &[0, 4, 8, 2]] {
+            let game = position(actions);
+            for depth in 1..=5 {
+                // a table too small for anything but the root gives the same values
+                for table_capacity in [0, 1 << 16] {
+                    let cfg = config(depth, usize::MAX, table_capacity);
+                    let (action, value) =
+                        AlphaBeta::new(cfg, &mut policy, eval).search(game.clone());
+                    assert_eq!(value, negamax(&game, depth));
+                    let mut child = game.clone();
+                    let value_of_action = if child.step(&action) {
+                        child.reward(game.player())
+                    } else {
+                        -negamax(&child, depth - 1)
+                    };
+                    assert_eq!(value_of_action, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_bounds() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut search = AlphaBeta::new(config(5, usize::MAX, 1 << 16), &mut policy, eval);
+        search.search(position(&[4]));
+        let mut num_bounds = [0; 3];
+        for (game, entry) in search.table.iter() {
+            let value = negamax(game, entry.depth);
+            match entry.bound {
+                Bound::Exact => assert_eq!(entry.value, value),
+                Bound::Lower => assert!(value >= entry.value),
+                Bound::Upper => assert!(value <= entry.value),
+            }
+            num_bounds[entry.bound as usize] += 1;
+        }
+        assert!(num_bounds.iter().all(|&n| n > 0), "{:?}", num_bounds);
+    }
+
+    #[test]
+    fn test_node_budget() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let game = TicTacToe::new();
+        for max_nodes in [0, 1, 10, 100, 1000] {
+            let mut search = AlphaBeta::new(config(9, max_nodes, 1 << 16), &mut policy, eval);
+            let (action, _) = search.search(game.clone());
+            assert!(game.iter_actions().any(|a| a == action));
+            // the first iteration visits the root and its 9 children
+            assert!(search.num_nodes() <= max_nodes.max(10));
+        }
+    }
+
+    #[test]
+    fn test_deepening_keeps_last_finished_depth() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let game = position(&[0]);
+        let mut unlimited = |depth| {
+            let mut search = AlphaBeta::new(config(depth, usize::MAX, 1 << 16), &mut policy, eval);
+            let best = search.search(game.clone());
+            (best, search.num_nodes())
+        };
+        let (best_at_3, num_nodes_to_3) = unlimited(3);
+        let (_, num_nodes_to_4) = unlimited(4);
+        assert!(num_nodes_to_4 > num_nodes_to_3 + 1);
+
+        // the fourth iteration starts but runs out of nodes
+        let cfg = config(9, num_nodes_to_3 + 1, 1 << 16);
+        let mut search = AlphaBeta::new(cfg, &mut policy, eval);
+        let best = search.search(game.clone());
+        assert_eq!(search.num_nodes(), num_nodes_to_3 + 1);
+        assert_eq!(best, best_at_3);
+    }
+}
@@ -1,25 +1,38 @@
-use crate::config::{LearningConfig, RolloutConfig, ValueTarget};
+use crate::config::{LearningConfig, RolloutConfig};
 use crate::data::*;
-use crate::game::{Game, Outcome};
+use crate::game::{Game, Notation, Outcome, Symmetries};
+use crate::gating::{gate, Decision};
 use crate::mcts::MCTS;
-use crate::policies::{NNPolicy, Policy, PolicyWithCache};
+use crate::optim::Adam;
+use crate::policies::{BatchPolicy, NNPolicy, Policy, PolicyWithCache};
+use crate::records::{RecordReader, RecordWriter, SelfPlayRecord};
 use crate::utils::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rand::prelude::*;
 use rand::{distributions::Distribution, distributions::WeightedIndex};
-use std::default::Default;
-use tch::{
-    kind::Kind,
-    nn::{Adam, OptimizerConfig, VarStore},
-};
-
-pub fn alpha_zero<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tch::{kind::Kind, nn::VarStore, Tensor};
+
+pub fn alpha_zero<
+    G: 'static + Symmetries<N> + Notation<N>,
+    P: BatchPolicy<G, N> + NNPolicy<G, N>,
+    const N: usize,
+>(
     cfg: &LearningConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // set up directory structure
     std::fs::create_dir_all(&cfg.logs)?;
+    check_new_run(&cfg.logs, cfg.resume)?;
     let models_dir = cfg.logs.join("models");
-    std::fs::create_dir(&models_dir)?;
+    if cfg.resume {
+        std::fs::create_dir_all(&models_dir)?;
+    } else {
+        std::fs::create_dir(&models_dir)?;
+    }
+    std::fs::create_dir_all(cfg.logs.join("games"))?;
     save_str(&cfg.logs, "env_name", &G::NAME.into())?;
     save_str(&cfg.logs, "git_hash", &git_hash()?)?;
     save_str(&cfg.logs, "git_diff.patch", &git_diff()?)?;
@@ -28,29 +41,58 @@ pub fn alpha_zero<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const
     tch::manual_seed(cfg.seed as i64);
 
     // init policy
-    let vs = VarStore::new(tch::Device::Cpu);
+    let mut vs = VarStore::new(tch::Device::Cpu);
     let policy = P::new(&vs);
-    let mut opt = Adam::default().build(&vs, cfg.lr_schedule[0].1)?;
+    policy.check_shapes()?;
+    let mut opt = Adam::new(&vs, cfg.lr_schedule[0].1);
     if cfg.weight_decay > 0.0 {
         opt.set_weight_decay(cfg.weight_decay);
     }
-    vs.save(models_dir.join(String::from("model_0.ot")))?;
 
-    // init replay buffer
-    let mut buffer = ReplayBuffer::new(256_000);
+    // init replay buffer, or pick up where the checkpoint left off
+    let checkpoint = cfg.logs.join("checkpoint.ot");
+    let (mut buffer, start_iter, mut best_iter) = if cfg.resume && checkpoint.exists() {
+        let named: HashMap<String, Tensor> = Tensor::load_multi(&checkpoint)?.into_iter().collect();
+        let i_iter = named
+            .get("iteration")
+            .ok_or("checkpoint is missing iteration")?
+            .int64_value(&[0]) as usize;
+        vs.load(models_dir.join(format!("model_{}.ot", i_iter)))?;
+        // checkpoints from before gating self-played with the latest model
+        let best_iter = named
+            .get("best")
+            .map_or(i_iter, |best| best.int64_value(&[0]) as usize);
+        opt.load_named_tensors(&named)?;
+        println!("Resuming from iteration {}", i_iter);
+        (
+            ReplayBuffer::from_named_tensors(&named, 256_000)?,
+            i_iter,
+            best_iter,
+        )
+    } else {
+        vs.save(models_dir.join(String::from("model_0.ot")))?;
+        let mut buffer = ReplayBuffer::new(256_000);
+        for path in cfg.preload_records.iter() {
+            let records = RecordReader::open(path)?;
+            let num_games = buffer.add_records(records, cfg.rollout_cfg.value_target)?;
+            println!("Preloaded {} games from {}", num_games, path.display());
+        }
+        (buffer, 0, 0)
+    };
 
     // start learning!
     let mut dims = G::DIMS.to_owned();
     let batch_mean = 1.0 / (cfg.batch_size as f32);
-    for i_iter in 0..cfg.num_iterations {
+    for i_iter in start_iter..cfg.num_iterations {
         // gather data
         {
             let _guard = tch::no_grad_guard();
-            gather_experience::<G, P, N>(cfg, format!("model_{}.ot", i_iter), &mut buffer, i_iter);
+            let best = format!("model_{}.ot", best_iter);
+            gather_experience::<G, P, N>(cfg, best, &mut buffer, i_iter);
         }
 
         // convert buffer data to tensors
-        let dedup = buffer.deduplicate();
+        let dedup = buffer.deduplicate(cfg.augment_symmetries);
         println!("Dedup {} -> {} steps", buffer.vs.len(), dedup.vs.len());
         dims[0] = dedup.vs.len() as i64;
         let states = tensor(&dedup.states, &dims, Kind::Float);
@@ -99,6 +141,34 @@ pub fn alpha_zero<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const
         target_pis.write_npy(cfg.logs.join("latest_pis.npy"))?;
         target_vs.write_npy(cfg.logs.join("latest_vs.npy"))?;
 
+        // the new model only takes over self-play if it is stronger than the best so far
+        match &cfg.gating {
+            Some(gating) => {
+                let _guard = tch::no_grad_guard();
+                let candidate = format!("model_{}.ot", i_iter + 1);
+                let best = format!("model_{}.ot", best_iter);
+                let seed = cfg.seed + (i_iter * gating.max_games) as u64;
+                let sprt = gate::<G, P, N>(gating, &cfg.logs, i_iter + 1, &candidate, &best, seed)?;
+                println!(
+                    "Gating {} vs {}: +{} ={} -{} llr {:.3} [{:.3}, {:.3}] {}",
+                    candidate,
+                    best,
+                    sprt.wdl[0],
+                    sprt.wdl[1],
+                    sprt.wdl[2],
+                    sprt.llr,
+                    sprt.lower,
+                    sprt.upper,
+                    sprt.decision().name()
+                );
+                if sprt.decision() == Decision::Pass {
+                    best_iter = i_iter + 1;
+                }
+            }
+            None => best_iter = i_iter + 1,
+        }
+        save_checkpoint(&checkpoint, i_iter + 1, best_iter, &opt, &buffer)?;
+
         println!("Finished iteration {}", i_iter + 1);
         println!(
             "lifetime: {} steps / {} games ({:.3} steps/game)",
@@ -117,7 +187,38 @@ pub fn alpha_zero<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const
     Ok(())
 }
 
-fn gather_experience<G: 'static + Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
+// everything besides the weights that a resumed run needs. written to a temporary file first so
+// preemption mid-save leaves the previous checkpoint intact
+fn save_checkpoint<G: Game<N>, const N: usize>(
+    path: &PathBuf,
+    i_iter: usize,
+    best_iter: usize,
+    opt: &Adam,
+    buffer: &ReplayBuffer<G, N>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut named = vec![
+        (
+            String::from("iteration"),
+            tensor(&[i_iter as i64], &[1], Kind::Int64),
+        ),
+        (
+            String::from("best"),
+            tensor(&[best_iter as i64], &[1], Kind::Int64),
+        ),
+    ];
+    named.extend(opt.named_tensors());
+    named.extend(buffer.named_tensors());
+    let tmp = path.with_extension("ot.tmp");
+    Tensor::save_multi(&named, &tmp)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn gather_experience<
+    G: 'static + Symmetries<N> + Notation<N>,
+    P: BatchPolicy<G, N> + NNPolicy<G, N>,
+    const N: usize,
+>(
     cfg: &LearningConfig,
     policy_name: String,
     buffer: &mut ReplayBuffer<G, N>,
@@ -178,7 +279,11 @@ fn styled_progress_bar(n: usize) -> ProgressBar {
     bar
 }
 
-fn run_n_games<G: Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
+fn run_n_games<
+    G: Symmetries<N> + Notation<N>,
+    P: BatchPolicy<G, N> + NNPolicy<G, N>,
+    const N: usize,
+>(
     cfg: LearningConfig,
     policy_name: String,
     num_games: usize,
@@ -186,72 +291,79 @@ fn run_n_games<G: Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
     seed: usize,
 ) -> ReplayBuffer<G, N> {
     let mut buffer = ReplayBuffer::new(G::MAX_TURNS * num_games);
-    let mut rng = StdRng::seed_from_u64(seed as u64);
 
     // load the policy weights
     let mut vs = VarStore::new(tch::Device::Cpu);
     let mut policy = P::new(&vs);
+    policy.check_shapes().unwrap();
     vs.load(cfg.logs.join("models").join(&policy_name)).unwrap();
 
     // create a cache for this policy, this speeds things up a lot, but takes memory
     let mut cached_policy =
         PolicyWithCache::with_capacity(G::MAX_TURNS * cfg.games_per_train, &mut policy);
 
+    // save every game so the data can be reused across runs
+    let records_path = cfg.logs.join("games").join(format!(
+        "{}_{}.games",
+        policy_name.trim_end_matches(".ot"),
+        seed
+    ));
+    let mut writer = RecordWriter::create(&records_path, G::NAME).unwrap();
+    // and readable copies of the moves
+    let mut pgn = BufWriter::new(File::create(records_path.with_extension("pgn")).unwrap());
+    let rollout_cfg = format!("{:?}", cfg.rollout_cfg);
+
     // run all the games
-    for _ in 0..num_games {
-        buffer.new_game();
-        run_game(&cfg.rollout_cfg, &mut cached_policy, &mut rng, &mut buffer);
+    for i_game in 0..num_games {
+        // each game gets its own seed, so one game of the file can be found and played again
+        let game_seed = seed_of_game(seed, i_game);
+        let mut rng = StdRng::seed_from_u64(game_seed);
+        let mut record = SelfPlayRecord::new(policy_name.clone(), game_seed);
+        run_game(&cfg.rollout_cfg, &mut cached_policy, &mut rng, &mut record);
+        buffer.add_record(&record, cfg.rollout_cfg.value_target);
+        writer.write(&record).unwrap();
+        let mut game_record = record.to_game_record::<G>();
+        game_record.set_tag("Round", i_game + 1);
+        game_record.set_tag("Config", &rollout_cfg);
+        game_record.write(&mut pgn).unwrap();
         progress_bar.inc(1);
     }
+    writer.flush().unwrap();
+    pgn.flush().unwrap();
     progress_bar.finish();
 
     buffer
 }
 
-struct StateInfo {
-    turn: usize,
-    t: f32,
-    q: [f32; 3],
-    z: [f32; 3],
+// the worker's seed in the high bits and the game's index in the low ones, unique within a run
+fn seed_of_game(worker_seed: usize, i_game: usize) -> u64 {
+    ((worker_seed as u64) << 32) | i_game as u64
 }
 
-impl StateInfo {
-    fn q(turn: usize, q: [f32; 3]) -> Self {
-        Self {
-            turn,
-            t: 0.0,
-            q,
-            z: [0.0; 3],
-        }
-    }
-}
-
-fn run_game<G: Game<N>, P: Policy<G, N>, R: Rng, const N: usize>(
+fn run_game<G: Game<N>, P: BatchPolicy<G, N>, R: Rng, const N: usize>(
     cfg: &RolloutConfig,
     policy: &mut P,
     rng: &mut R,
-    buffer: &mut ReplayBuffer<G, N>,
+    record: &mut SelfPlayRecord<N>,
 ) {
     let mut game = G::new();
     let mut solution = None;
     let mut search_policy = [0.0; N];
     let mut num_turns = 0;
-    let mut state_infos = Vec::with_capacity(G::MAX_TURNS);
 
+    let mut mcts = MCTS::with_capacity(cfg.num_explores + 1, cfg.mcts_cfg, policy, game.clone());
     while solution.is_none() {
-        let mut mcts =
-            MCTS::with_capacity(cfg.num_explores + 1, cfg.mcts_cfg, policy, game.clone());
-
         // explore
-        mcts.explore_n(cfg.num_explores);
+        mcts.explore_n_batched(cfg.num_explores, cfg.eval_batch_size);
 
-        // store in buffer
+        // store in record
         mcts.target_policy(&mut search_policy);
-        buffer.add(&game, &search_policy, [0.0; 3]);
-        state_infos.push(StateInfo::q(num_turns + 1, mcts.target_q()));
+        record.pis.push(search_policy);
+        record.qs.push(mcts.target_q());
 
         // pick action
         let action = sample_action(&cfg, &mut mcts, &game, &search_policy, rng, num_turns);
+        record.actions.push(action.into());
         solution = mcts.solution(&action);
 
         let is_over = game.step(&action);
@@ -261,10 +373,17 @@ fn run_game<G: Game<N>, P: Policy<G, N>, R: Rng, const N: usize>(
             solution = None;
         }
         num_turns += 1;
+
+        if solution.is_none() {
+            if cfg.reuse_tree {
+                mcts.advance(&action);
+            } else {
+                mcts.reset(game.clone());
+            }
+        }
     }
 
-    fill_state_info(&mut state_infos, solution.unwrap().reversed());
-    store_rewards(&cfg, buffer, &state_infos);
+    fill_outcomes(record, solution.unwrap().reversed());
 }
 
 fn sample_action<G: Game<N>, P: Policy<G, N>, R: Rng, const N: usize>(
@@ -278,7 +397,7 @@ fn sample_action<G: Game<N>, P: Policy<G, N>, R: Rng, const N: usize>(
     let best = mcts.best_action(cfg.action);
     let solution = mcts.solution(&best);
     let action = if num_turns < cfg.random_actions_until {
-        let n = rng.gen_range(0..game.iter_actions().count() as u8) as usize;
+        let n = rng.gen_range(0..game.iter_actions().count());
         game.iter_actions().nth(n).unwrap()
     } else if num_turns < cfg.sample_actions_until
         && (solution.is_none() || !cfg.stop_games_when_solved)
@@ -286,53 +405,21 @@ fn sample_action<G: Game<N>, P: Policy<G, N>, R: Rng, const N: usize>(
         let dist = WeightedIndex::new(search_policy).unwrap();
         let choice = dist.sample(rng);
         // assert!(search_policy[choice] > 0.0);
-        G::Action::from(choice)
+        game.decode_action(choice)
     } else {
         best
     };
     action
 }
 
-fn fill_state_info(state_infos: &mut Vec<StateInfo>, mut outcome: Outcome) {
-    let num_turns = state_infos.len();
-    for state_value in state_infos.iter_mut().rev() {
-        state_value.z[match outcome {
+fn fill_outcomes<const N: usize>(record: &mut SelfPlayRecord<N>, mut outcome: Outcome) {
+    record.zs = vec![[0.0; 3]; record.len()];
+    for z in record.zs.iter_mut().rev() {
+        z[match outcome {
             Outcome::Win(_) => 2,
             Outcome::Draw(_) => 1,
             Outcome::Lose(_) => 0,
         }] = 1.0;
-        state_value.t = state_value.turn as f32 / num_turns as f32;
         outcome = outcome.reversed();
     }
 }
-
-fn store_rewards<G: Game<N>, const N: usize>(
-    cfg: &RolloutConfig,
-    buffer: &mut ReplayBuffer<G, N>,
-    state_infos: &Vec<StateInfo>,
-) {
-    let num_turns = state_infos.len();
-    let start_i = buffer.curr_steps() - num_turns;
-    let end_i = buffer.curr_steps();
-    for (buffer_value, state) in buffer.vs[start_i..end_i].iter_mut().zip(state_infos) {
-        *buffer_value = match cfg.value_target {
-            ValueTarget::Q => state.q,
-            ValueTarget::Z => state.z,
-            ValueTarget::QZaverage { p } => {
-                let mut value = [0.0; 3];
-                for i in 0..3 {
-                    value[i] = state.q[i] * p + state.z[i] * (1.0 - p);
-                }
-                value
-            }
-            ValueTarget::QtoZ { from, to } => {
-                let p = (1.0 - state.t) * from + state.t * to;
-                let mut value = [0.0; 3];
-                for i in 0..3 {
-                    value[i] = state.q[i] * (1.0 - p) + state.z[i] * p;
-                }
-                value
-            }
-        };
-    }
-}
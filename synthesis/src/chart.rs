@@ -0,0 +1,277 @@
+use crate::ratings::{Rating, Ratings};
+use std::fmt::Write;
+
+const WIDTH: f64 = 900.0;
+const HEIGHT: f64 = 500.0;
+const LEFT: f64 = 60.0;
+const RIGHT: f64 = 200.0; // room for the baseline labels
+const TOP: f64 = 20.0;
+const BOTTOM: f64 = 40.0;
+
+const MODEL_COLOR: &str = "#1f77b4";
+const BASELINE_COLORS: [&str; 6] = [
+    "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2",
+];
+
+// iteration of a `model_{i}.ot` player
+fn model_iteration(name: &str) -> Option<usize> {
+    name.strip_prefix("model_")?
+        .strip_suffix(".ot")?
+        .parse()
+        .ok()
+}
+
+// 1, 2 or 5 times a power of ten, so there are about `n` ticks over `range`
+fn tick_step(range: f64, n: f64) -> f64 {
+    let raw = range / n;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= raw)
+        .unwrap()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// Elo of every model by iteration with its confidence interval, and every other player, e.g.
+// the VanillaMCTS baselines, as a horizontal line
+pub fn ratings_svg(ratings: &Ratings) -> String {
+    let mut models: Vec<(usize, &Rating)> = ratings
+        .players
+        .iter()
+        .filter_map(|r| model_iteration(&r.name).map(|i| (i, r)))
+        .collect();
+    models.sort_by_key(|&(i, _r)| i);
+    let baselines: Vec<&Rating> = ratings
+        .players
+        .iter()
+        .filter(|r| model_iteration(&r.name).is_none())
+        .collect();
+
+    // axis ranges
+    let max_iter = models.last().map_or(1, |&(i, _r)| i.max(1)) as f64;
+    let mut lo = f64::INFINITY;
+    let mut hi = f64::NEG_INFINITY;
+    for &(_i, r) in models.iter() {
+        lo = lo.min(r.lower);
+        hi = hi.max(r.upper);
+    }
+    for r in baselines.iter() {
+        lo = lo.min(r.elo);
+        hi = hi.max(r.elo);
+    }
+    if !lo.is_finite() || !hi.is_finite() {
+        lo = -100.0;
+        hi = 100.0;
+    }
+    if hi - lo < 1.0 {
+        lo -= 50.0;
+        hi += 50.0;
+    }
+    let pad = 0.05 * (hi - lo);
+    let (lo, hi) = (lo - pad, hi + pad);
+
+    let plot_w = WIDTH - LEFT - RIGHT;
+    let plot_h = HEIGHT - TOP - BOTTOM;
+    let x = |i: f64| LEFT + plot_w * i / max_iter;
+    let y = |elo: f64| TOP + plot_h * (hi - elo) / (hi - lo);
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">",
+        WIDTH, HEIGHT
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+        WIDTH, HEIGHT
+    )
+    .unwrap();
+
+    // grid and ticks
+    let step = tick_step(hi - lo, 8.0);
+    let mut tick = (lo / step).ceil() * step;
+    while tick <= hi {
+        writeln!(
+            svg,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#e0e0e0\"/>",
+            LEFT,
+            y(tick),
+            LEFT + plot_w,
+            y(tick)
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" dominant-baseline=\"middle\">{}</text>",
+            LEFT - 6.0,
+            y(tick),
+            tick.round()
+        )
+        .unwrap();
+        tick += step;
+    }
+    let step = tick_step(max_iter, 10.0).max(1.0);
+    let mut tick = 0.0;
+    while tick <= max_iter {
+        writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            x(tick),
+            TOP + plot_h + 16.0,
+            tick
+        )
+        .unwrap();
+        tick += step;
+    }
+    writeln!(
+        svg,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>",
+        LEFT, TOP, plot_w, plot_h
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">iteration</text>",
+        LEFT + plot_w / 2.0,
+        HEIGHT - 6.0
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        "<text transform=\"translate(14 {:.1}) rotate(-90)\" text-anchor=\"middle\">Elo</text>",
+        TOP + plot_h / 2.0
+    )
+    .unwrap();
+
+    // baselines
+    for (k, r) in baselines.iter().enumerate() {
+        let color = BASELINE_COLORS[k % BASELINE_COLORS.len()];
+        writeln!(
+            svg,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-dasharray=\"6 4\"/>",
+            LEFT,
+            y(r.elo),
+            LEFT + plot_w,
+            y(r.elo),
+            color
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\" dominant-baseline=\"middle\">{} ({:.0})</text>",
+            LEFT + plot_w + 6.0,
+            y(r.elo),
+            color,
+            escape(&r.name),
+            r.elo
+        )
+        .unwrap();
+    }
+
+    // models, error bars under the curve
+    for &(i, r) in models.iter() {
+        writeln!(
+            svg,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-opacity=\"0.3\"/>",
+            x(i as f64),
+            y(r.lower),
+            x(i as f64),
+            y(r.upper),
+            MODEL_COLOR
+        )
+        .unwrap();
+    }
+    if !models.is_empty() {
+        let points: Vec<String> = models
+            .iter()
+            .map(|&(i, r)| format!("{:.1},{:.1}", x(i as f64), y(r.elo)))
+            .collect();
+        writeln!(
+            svg,
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+            points.join(" "),
+            MODEL_COLOR
+        )
+        .unwrap();
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(name: &str, elo: f64) -> Rating {
+        Rating {
+            name: String::from(name),
+            elo,
+            lower: elo - 20.0,
+            upper: elo + 20.0,
+            games: 10,
+            score: 0.5,
+        }
+    }
+
+    fn ratings(players: Vec<Rating>) -> Ratings {
+        Ratings {
+            elo_advantage: 32.8,
+            elo_draw: 97.3,
+            prior_draws: 2.0,
+            players,
+        }
+    }
+
+    #[test]
+    fn test_models_and_baselines() {
+        let svg = ratings_svg(&ratings(vec![
+            rating("model_2.ot", 150.0),
+            rating("VanillaMCTS800", 50.0),
+            rating("model_0.ot", -100.0),
+            rating("model_1.ot", 0.0),
+        ]));
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<polyline").count(), 1);
+        let points = svg.split("points=\"").nth(1).unwrap();
+        let points = &points[..points.find('"').unwrap()];
+        assert_eq!(points.split(' ').count(), 3);
+        assert!(svg.contains("VanillaMCTS800 (50)"));
+    }
+
+    #[test]
+    fn test_model_curve_is_in_iteration_order() {
+        let svg = ratings_svg(&ratings(vec![
+            rating("model_10.ot", 0.0),
+            rating("model_2.ot", 0.0),
+        ]));
+        let points = svg.split("points=\"").nth(1).unwrap();
+        let xs: Vec<f64> = points[..points.find('"').unwrap()]
+            .split(' ')
+            .map(|p| p.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert!(xs[0] < xs[1]);
+    }
+
+    #[test]
+    fn test_empty() {
+        let svg = ratings_svg(&ratings(Vec::new()));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(!svg.contains("<polyline"));
+    }
+
+    #[test]
+    fn test_escapes_names() {
+        let svg = ratings_svg(&ratings(vec![rating("A<B>&C", 0.0)]));
+        assert!(svg.contains("A&lt;B&gt;&amp;C"));
+    }
+}
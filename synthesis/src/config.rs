@@ -1,4 +1,9 @@
-#[derive(Debug, Clone, Copy)]
+use rand::{distributions::Distribution, thread_rng, Rng};
+use rand_distr::Normal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ValueTarget {
     Z,                           // Outcome of game {-1, 0, 1}
     Q,                           // Avg Value found while searching
@@ -6,26 +11,93 @@ pub enum ValueTarget {
     QtoZ { from: f32, to: f32 }, // interpolate from Q to Z based on turns
 }
 
-#[derive(Debug, Clone, Copy)]
+impl ValueTarget {
+    // t is how far into the game the position is, turn / number of turns
+    pub fn value(&self, q: &[f32; 3], z: &[f32; 3], t: f32) -> [f32; 3] {
+        match *self {
+            Self::Q => *q,
+            Self::Z => *z,
+            Self::QZaverage { p } => {
+                let mut value = [0.0; 3];
+                for i in 0..3 {
+                    value[i] = q[i] * p + z[i] * (1.0 - p);
+                }
+                value
+            }
+            Self::QtoZ { from, to } => {
+                let p = (1.0 - t) * from + t * to;
+                let mut value = [0.0; 3];
+                for i in 0..3 {
+                    value[i] = q[i] * (1.0 - p) + z[i] * p;
+                }
+                value
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Exploration {
-    Uct { c: f32 },
-    PolynomialUct { c: f32 },
+    Uct {
+        c: f32,
+    },
+    PolynomialUct {
+        c: f32,
+    },
+    // Gumbel-Top-k with Sequential Halving at the root, the improved policy below it
+    Gumbel {
+        num_considered: usize, // root actions sampled without replacement before halving
+        c_visit: f32,          // sigma(q) = (c_visit + max visits) * c_scale * q
+        c_scale: f32,
+    },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ActionSelection {
     Q,         // avg value
     NumVisits, // num visits
+    Gumbel,    // last action left by Sequential Halving, needs Exploration::Gumbel
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Fpu {
     Const(f32),
     ParentQ,
-    Func(fn() -> f32),
+    Normal { mean: f32, std: f32 }, // sampled again every time
+    Uniform { low: f32, high: f32 },
+}
+
+impl Fpu {
+    pub fn value(&self, parent_q: f32) -> f32 {
+        match *self {
+            Self::Const(value) => value,
+            Self::ParentQ => parent_q,
+            Self::Normal { mean, std } => Normal::new(mean, std).unwrap().sample(&mut thread_rng()),
+            Self::Uniform { low, high } => thread_rng().gen_range(low..high),
+        }
+    }
+
+    // parameters `value` would panic on
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            Self::Normal { mean, std } if !mean.is_finite() || !std.is_finite() || std < 0.0 => {
+                Err(format!(
+                    "Fpu::Normal needs a finite mean and std >= 0, not {:?}",
+                    self
+                ))
+            }
+            Self::Uniform { low, high } if !low.is_finite() || !high.is_finite() || low >= high => {
+                Err(format!(
+                    "Fpu::Uniform needs finite low < high, not {:?}",
+                    self
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct MCTSConfig {
     pub exploration: Exploration,
     pub solve: bool,
@@ -36,14 +108,74 @@ pub struct MCTSConfig {
     pub root_policy_noise: PolicyNoise,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl MCTSConfig {
+    // combinations `MCTS` can't search or pick actions with
+    pub fn check(&self, action: ActionSelection) -> Result<(), String> {
+        self.fpu.check()?;
+        let gumbel_exploration = matches!(self.exploration, Exploration::Gumbel { .. });
+        if matches!(action, ActionSelection::Gumbel) && !gumbel_exploration {
+            return Err(String::from(
+                "ActionSelection::Gumbel needs Exploration::Gumbel",
+            ));
+        }
+        Ok(())
+    }
+
+    // `ParallelMCTS` has no Gumbel search
+    pub fn check_parallel(&self, action: ActionSelection) -> Result<(), String> {
+        self.fpu.check()?;
+        if matches!(self.exploration, Exploration::Gumbel { .. }) {
+            return Err(String::from(
+                "parallel MCTS doesn't support Exploration::Gumbel",
+            ));
+        }
+        if matches!(action, ActionSelection::Gumbel) {
+            return Err(String::from(
+                "parallel MCTS doesn't support ActionSelection::Gumbel",
+            ));
+        }
+        Ok(())
+    }
+
+    // the rollout baselines only search with UCT and a constant first play urgency
+    pub fn check_frozen(&self, action: ActionSelection) -> Result<(), String> {
+        if !matches!(self.exploration, Exploration::Uct { .. }) {
+            return Err(format!(
+                "rollout MCTS only supports Exploration::Uct, not {:?}",
+                self.exploration
+            ));
+        }
+        if !matches!(self.fpu, Fpu::Const(_)) {
+            return Err(format!(
+                "rollout MCTS only supports Fpu::Const, not {:?}",
+                self.fpu
+            ));
+        }
+        if matches!(action, ActionSelection::Gumbel) {
+            return Err(String::from(
+                "rollout MCTS doesn't support ActionSelection::Gumbel",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum PolicyNoise {
     None,
     Equal { weight: f32 },
     Dirichlet { alpha: f32, weight: f32 },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AlphaBetaConfig {
+    pub max_depth: usize,      // last iteration of iterative deepening
+    pub max_nodes: usize,      // the search stops once this many nodes were searched
+    pub table_capacity: usize, // transposition table stops storing new positions when full
+    pub order_by_policy: bool, // try actions in order of the policy's logits
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct RolloutConfig {
     pub num_workers: usize,
     pub num_explores: usize,
@@ -52,12 +184,59 @@ pub struct RolloutConfig {
     pub stop_games_when_solved: bool,
     pub value_target: ValueTarget,
     pub action: ActionSelection,
+    pub reuse_tree: bool, // keep the subtree of the played action for the next turn
+    pub eval_batch_size: usize, // leaves evaluated together in one batch per MCTS step
+    pub mcts_cfg: MCTSConfig,
+}
+
+// A sequential probability ratio test every new model plays against the current best, self-play
+// only moves on to models that pass it. Elo is on the BayesElo scale of ratings.json
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GatingConfig {
+    pub elo0: f64,        // the candidate is at most this much better than the best...
+    pub elo1: f64,        // ...or at least this much
+    pub alpha: f64,       // chance of passing a candidate that is only `elo0` better
+    pub beta: f64,        // chance of failing one that is `elo1` better
+    pub max_games: usize, // a candidate still undecided after this many games fails
+    pub num_workers: usize,
+
+    pub num_explores: usize,
+    pub random_actions_until: usize, // random opening moves, both colors play each opening
+    pub action: ActionSelection,
     pub mcts_cfg: MCTSConfig,
 }
 
-#[derive(Debug, Clone)]
+impl GatingConfig {
+    // bounds that would come out NaN and tests that could never decide
+    pub fn check(&self) -> Result<(), String> {
+        for (field, p) in [("alpha", self.alpha), ("beta", self.beta)] {
+            if p.is_nan() || p <= 0.0 || p >= 1.0 {
+                return Err(format!("{} must be in (0, 1), not {}", field, p));
+            }
+        }
+        if !self.elo0.is_finite() || !self.elo1.is_finite() || self.elo0 >= self.elo1 {
+            return Err(format!(
+                "elo0 must be finite and below elo1, not {} and {}",
+                self.elo0, self.elo1
+            ));
+        }
+        if self.max_games == 0 {
+            return Err(String::from("max_games must be at least 1"));
+        }
+        if self.num_explores == 0 {
+            return Err(String::from("num_explores must be at least 1"));
+        }
+        self.mcts_cfg.check(self.action)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EvaluationConfig {
+    #[serde(default)]
     pub logs: std::path::PathBuf,
+    #[serde(default)]
+    pub resume: bool, // keep the results of every model in evaluated.csv and go on after them
+    pub num_workers: usize, // threads playing the games, games against an oracle stay on one
 
     pub policy_num_explores: usize,
     pub policy_action: ActionSelection,
@@ -70,13 +249,22 @@ pub struct EvaluationConfig {
     pub rollout_num_explores: Vec<usize>,
     pub rollout_mcts_cfg: MCTSConfig,
     pub num_games_against_rollout: usize,
+
+    pub num_games_against_oracle: usize, // per color, only played when the game has an oracle
+
+    pub alpha_beta_cfg: AlphaBetaConfig,
+    pub alpha_beta_max_nodes: Vec<usize>, // one opponent per node budget, overrides `max_nodes`
+    pub alpha_beta_num_playouts: usize,   // random playouts averaged to evaluate a leaf
+    pub num_games_against_alpha_beta: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LearningConfig {
     // general params
     pub seed: u64,
+    #[serde(default)]
     pub logs: std::path::PathBuf,
+    pub resume: bool,
 
     // training params
     pub lr_schedule: Vec<(usize, f64)>,
@@ -86,9 +274,445 @@ pub struct LearningConfig {
     pub batch_size: i64,
     pub policy_weight: f32,
     pub value_weight: f32,
+    pub augment_symmetries: bool,
 
     pub games_to_keep: usize,
     pub games_per_train: usize,
+    #[serde(default)]
+    pub preload_records: Vec<PathBuf>, // .games files of earlier runs, fill the buffer of a new run
 
     pub rollout_cfg: RolloutConfig,
+    #[serde(default)]
+    pub gating: Option<GatingConfig>, // None self-plays with every new model
+}
+
+// Everything a training run is configured with, the layout of the files binaries take with
+// `--config`. An empty `logs` is left for the binary to fill in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunConfig {
+    pub learning: LearningConfig,
+    pub evaluation: EvaluationConfig,
+}
+
+impl RunConfig {
+    // .json files are read as JSON, anything else as TOML
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg: Self = load(path.as_ref())?;
+        cfg.check()?;
+        Ok(cfg)
+    }
+
+    // settings that parse but would fail or hang in the middle of a run
+    pub fn check(&self) -> Result<(), String> {
+        let rollout_cfg = &self.learning.rollout_cfg;
+        if rollout_cfg.eval_batch_size == 0 {
+            return Err(String::from(
+                "learning.rollout_cfg.eval_batch_size must be at least 1",
+            ));
+        }
+        let within = |field: &'static str| move |e: String| format!("{}: {}", field, e);
+        for path in self.learning.preload_records.iter() {
+            if !path.is_file() {
+                return Err(format!(
+                    "learning.preload_records: {} is not a file",
+                    path.display()
+                ));
+            }
+        }
+        rollout_cfg
+            .mcts_cfg
+            .check(rollout_cfg.action)
+            .map_err(within("learning.rollout_cfg"))?;
+        if let Some(gating) = &self.learning.gating {
+            gating.check().map_err(within("learning.gating"))?;
+        }
+        let eval = &self.evaluation;
+        eval.policy_mcts_cfg
+            .check(eval.policy_action)
+            .map_err(within("evaluation.policy_mcts_cfg"))?;
+        eval.rollout_mcts_cfg
+            .check_frozen(eval.rollout_action)
+            .map_err(within("evaluation.rollout_mcts_cfg"))?;
+        Ok(())
+    }
+
+    // always TOML, JSON has no infinity for `Fpu::Const(f32::INFINITY)`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        save(self, path.as_ref())
+    }
+}
+
+// One entrant of a tournament, ratings are keyed by the names so they must be unique
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum PlayerConfig {
+    // network weights from any run, searched like the evaluator searches its models
+    Model {
+        name: String,
+        path: PathBuf,
+        num_explores: usize,
+        action: ActionSelection,
+        mcts_cfg: MCTSConfig,
+        #[serde(default)]
+        num_threads: usize, // more than 1 searches one tree with `ParallelMCTS`
+    },
+    // MCTS that scores leaves with a random playout, the evaluator's VanillaMCTS players
+    Rollout {
+        name: String,
+        num_explores: usize,
+        action: ActionSelection,
+        mcts_cfg: MCTSConfig,
+    },
+    // leaves are scored by the mean of `num_playouts` random playouts
+    AlphaBeta {
+        name: String,
+        cfg: AlphaBetaConfig,
+        num_playouts: usize,
+    },
+    Random {
+        name: String,
+    },
+}
+
+impl PlayerConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Model { name, .. }
+            | Self::Rollout { name, .. }
+            | Self::AlphaBeta { name, .. }
+            | Self::Random { name } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pairing {
+    RoundRobin, // every player against every other
+    Gauntlet,   // the first player against each of the others
+}
+
+// The layout of the files the tournament binaries take with `--config`. An empty `logs` is left
+// for the binary to fill in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TournamentConfig {
+    #[serde(default)]
+    pub logs: PathBuf,
+    pub seed: u64, // game i of every pairing is played with seed + i, once with each color
+    pub pairing: Pairing,
+    pub games_per_color: usize,
+    pub players: Vec<PlayerConfig>,
+}
+
+impl TournamentConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg: Self = load(path.as_ref())?;
+        cfg.check()?;
+        Ok(cfg)
+    }
+
+    // players whose settings would fail in the middle of the tournament
+    pub fn check(&self) -> Result<(), String> {
+        for player in self.players.iter() {
+            let checked = match player {
+                PlayerConfig::Model {
+                    action,
+                    mcts_cfg,
+                    num_threads,
+                    ..
+                } if *num_threads > 1 => mcts_cfg.check_parallel(*action),
+                PlayerConfig::Model {
+                    action, mcts_cfg, ..
+                } => mcts_cfg.check(*action),
+                PlayerConfig::Rollout {
+                    action, mcts_cfg, ..
+                } => mcts_cfg.check_frozen(*action),
+                PlayerConfig::AlphaBeta { .. } | PlayerConfig::Random { .. } => Ok(()),
+            };
+            checked.map_err(|e| format!("player {}: {}", player.name(), e))?;
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        save(self, path.as_ref())
+    }
+}
+
+fn load<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        Ok(serde_json::from_str(&contents)?)
+    } else {
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+fn save<T: Serialize>(config: &T, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::write(path, toml::to_string(config)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mcts_cfg(exploration: Exploration, fpu: Fpu) -> MCTSConfig {
+        MCTSConfig {
+            exploration,
+            solve: true,
+            correct_values_on_solve: true,
+            select_solved_nodes: true,
+            auto_extend: true,
+            fpu,
+            root_policy_noise: PolicyNoise::None,
+        }
+    }
+
+    fn run_config() -> RunConfig {
+        let policy_mcts_cfg = mcts_cfg(
+            Exploration::PolynomialUct { c: 3.0 },
+            Fpu::Normal {
+                mean: 1.0,
+                std: 0.1,
+            },
+        );
+        let rollout_mcts_cfg = mcts_cfg(Exploration::Uct { c: 2.0 }, Fpu::Const(f32::INFINITY));
+        RunConfig {
+            learning: LearningConfig {
+                seed: 3,
+                logs: PathBuf::from("_logs/test"),
+                resume: true,
+                lr_schedule: vec![(1, 1e-3), (20, 5e-4)],
+                weight_decay: 1e-6,
+                num_iterations: 2,
+                num_epochs: 2,
+                batch_size: 32,
+                policy_weight: 1.0,
+                value_weight: 0.5,
+                augment_symmetries: true,
+                games_to_keep: 10,
+                games_per_train: 5,
+                preload_records: Vec::new(),
+                rollout_cfg: RolloutConfig {
+                    num_workers: 1,
+                    num_explores: 10,
+                    random_actions_until: 1,
+                    sample_actions_until: 3,
+                    stop_games_when_solved: false,
+                    value_target: ValueTarget::QtoZ { from: 0.0, to: 1.0 },
+                    action: ActionSelection::NumVisits,
+                    reuse_tree: true,
+                    eval_batch_size: 8,
+                    mcts_cfg: policy_mcts_cfg,
+                },
+                gating: Some(GatingConfig {
+                    elo0: 0.0,
+                    elo1: 35.0,
+                    alpha: 0.05,
+                    beta: 0.05,
+                    max_games: 400,
+                    num_workers: 2,
+                    num_explores: 10,
+                    random_actions_until: 2,
+                    action: ActionSelection::NumVisits,
+                    mcts_cfg: policy_mcts_cfg,
+                }),
+            },
+            evaluation: EvaluationConfig {
+                logs: PathBuf::from("_logs/test"),
+                resume: true,
+                num_workers: 2,
+                policy_num_explores: 10,
+                policy_action: ActionSelection::Q,
+                policy_mcts_cfg,
+                num_best_policies: 1,
+                num_games_against_best_policies: 1,
+                rollout_action: ActionSelection::Q,
+                rollout_num_explores: vec![1, 2],
+                rollout_mcts_cfg,
+                num_games_against_rollout: 1,
+                num_games_against_oracle: 0,
+                alpha_beta_cfg: AlphaBetaConfig {
+                    max_depth: 64,
+                    max_nodes: 0,
+                    table_capacity: 1 << 20,
+                    order_by_policy: false,
+                },
+                alpha_beta_max_nodes: vec![1000],
+                alpha_beta_num_playouts: 1,
+                num_games_against_alpha_beta: 1,
+            },
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("synthesis_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        // the rollout baselines' first play urgency is infinite
+        let cfg = run_config();
+        let path = temp_path("run.toml");
+        cfg.save(&path).unwrap();
+        let loaded = RunConfig::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", cfg));
+        match loaded.evaluation.rollout_mcts_cfg.fpu {
+            Fpu::Const(value) => assert_eq!(value, f32::INFINITY),
+            fpu => panic!("{:?}", fpu),
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut cfg = run_config();
+        cfg.evaluation.rollout_mcts_cfg.fpu = Fpu::Const(1.0);
+        let path = temp_path("run.json");
+        std::fs::write(&path, serde_json::to_string_pretty(&cfg).unwrap()).unwrap();
+        let loaded = RunConfig::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", cfg));
+    }
+
+    #[test]
+    fn test_load_checks_fpu() {
+        let mut cfg = run_config();
+        cfg.learning.rollout_cfg.mcts_cfg.fpu = Fpu::Uniform {
+            low: 1.0,
+            high: 1.0,
+        };
+        let path = temp_path("bad_fpu.toml");
+        cfg.save(&path).unwrap();
+        let loaded = RunConfig::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+
+        assert!(Fpu::Normal {
+            mean: 0.0,
+            std: -0.1
+        }
+        .check()
+        .is_err());
+        assert!(Fpu::Normal {
+            mean: f32::NAN,
+            std: 0.1
+        }
+        .check()
+        .is_err());
+        assert!(Fpu::Uniform {
+            low: 0.0,
+            high: f32::INFINITY
+        }
+        .check()
+        .is_err());
+        assert!(Fpu::Uniform {
+            low: 0.5,
+            high: 1.0
+        }
+        .check()
+        .is_ok());
+        assert!(Fpu::Const(f32::INFINITY).check().is_ok());
+    }
+
+    #[test]
+    fn test_check_gating() {
+        let mut cfg = run_config();
+        assert!(cfg.check().is_ok());
+        let gating = cfg.learning.gating.unwrap();
+        let broken: [fn(&mut GatingConfig); 7] = [
+            |g| g.alpha = 0.0,
+            |g| g.beta = 1.0,
+            |g| g.alpha = f64::NAN,
+            |g| g.elo0 = g.elo1,
+            |g| g.max_games = 0,
+            |g| g.num_explores = 0,
+            |g| g.action = ActionSelection::Gumbel,
+        ];
+        for breaking in broken.iter() {
+            let mut broken_gating = gating;
+            breaking(&mut broken_gating);
+            cfg.learning.gating = Some(broken_gating);
+            let checked = cfg.check();
+            assert!(checked.unwrap_err().starts_with("learning.gating: "));
+        }
+    }
+
+    #[test]
+    fn test_check_preload_records() {
+        let mut cfg = run_config();
+        let path = temp_path("preload.games");
+        cfg.learning.preload_records = vec![path.clone()];
+        assert!(cfg.check().is_err());
+        std::fs::write(&path, b"").unwrap();
+        let checked = cfg.check();
+        std::fs::remove_file(&path).unwrap();
+        assert!(checked.is_ok());
+    }
+
+    #[test]
+    fn test_check_gumbel() {
+        let gumbel = Exploration::Gumbel {
+            num_considered: 16,
+            c_visit: 50.0,
+            c_scale: 1.0,
+        };
+        let puct = Exploration::PolynomialUct { c: 3.0 };
+        assert!(mcts_cfg(gumbel, Fpu::ParentQ)
+            .check(ActionSelection::Gumbel)
+            .is_ok());
+        assert!(mcts_cfg(gumbel, Fpu::ParentQ)
+            .check(ActionSelection::NumVisits)
+            .is_ok());
+        assert!(mcts_cfg(puct, Fpu::ParentQ)
+            .check(ActionSelection::Gumbel)
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_frozen() {
+        let uct = Exploration::Uct { c: 2.0 };
+        let frozen = mcts_cfg(uct, Fpu::Const(f32::INFINITY));
+        assert!(frozen.check_frozen(ActionSelection::Q).is_ok());
+        assert!(frozen.check_frozen(ActionSelection::Gumbel).is_err());
+        assert!(mcts_cfg(uct, Fpu::ParentQ)
+            .check_frozen(ActionSelection::Q)
+            .is_err());
+        assert!(
+            mcts_cfg(Exploration::PolynomialUct { c: 3.0 }, Fpu::Const(1.0))
+                .check_frozen(ActionSelection::Q)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_check_parallel() {
+        let gumbel = Exploration::Gumbel {
+            num_considered: 16,
+            c_visit: 50.0,
+            c_scale: 1.0,
+        };
+        let model = |num_threads| PlayerConfig::Model {
+            name: String::from("model"),
+            path: PathBuf::from("model_0.ot"),
+            num_explores: 100,
+            action: ActionSelection::NumVisits,
+            mcts_cfg: mcts_cfg(gumbel, Fpu::ParentQ),
+            num_threads,
+        };
+        let tournament = |num_threads| TournamentConfig {
+            logs: PathBuf::new(),
+            seed: 0,
+            pairing: Pairing::RoundRobin,
+            games_per_color: 1,
+            players: vec![model(num_threads)],
+        };
+        assert!(tournament(0).check().is_ok());
+        assert!(tournament(1).check().is_ok());
+        assert!(tournament(4).check().is_err());
+
+        let puct = mcts_cfg(Exploration::PolynomialUct { c: 3.0 }, Fpu::ParentQ);
+        assert!(puct.check_parallel(ActionSelection::Q).is_ok());
+        assert!(puct.check_parallel(ActionSelection::Gumbel).is_err());
+    }
 }
@@ -1,4 +1,7 @@
-use crate::game::Game;
+use crate::config::ValueTarget;
+use crate::game::{Game, Symmetries};
+use crate::records::{RecordReader, SelfPlayRecord};
+use std::io::Read;
 use std::{collections::HashMap, ffi::c_void};
 use tch::{Kind, Tensor};
 use torch_sys::at_tensor_of_data;
@@ -89,6 +92,31 @@ pub fn tensor<T>(data: &[T], dims: &[i64], kind: tch::Kind) -> Tensor {
     unsafe { Tensor::from_ptr(at_tensor_of_data(data, dims, ndims, dsize, dtype)) }
 }
 
+// splits a batch of policy logits and outcome logits into one `Policy::eval` output per row
+pub fn unbatch<const N: usize>(
+    logits: &Tensor,
+    outcome_logits: &Tensor,
+) -> Vec<([f32; N], [f32; 3])> {
+    let batch_size = logits.size()[0] as usize;
+    let mut policies = vec![0.0f32; batch_size * N];
+    logits.copy_data(&mut policies, batch_size * N);
+    let mut outcomes = vec![0.0f32; batch_size * 3];
+    outcome_logits
+        .softmax(-1, Kind::Float)
+        .copy_data(&mut outcomes, batch_size * 3);
+    policies
+        .chunks_exact(N)
+        .zip(outcomes.chunks_exact(3))
+        .map(|(policy, outcome_probs)| {
+            let mut pi = [0.0f32; N];
+            pi.copy_from_slice(policy);
+            let mut v = [0.0f32; 3];
+            v.copy_from_slice(outcome_probs);
+            (pi, v)
+        })
+        .collect()
+}
+
 pub struct FlatBatch<G: Game<N>, const N: usize> {
     pub states: Vec<G::Features>,
     pub pis: Vec<[f32; N]>,
@@ -107,6 +135,7 @@ pub struct ReplayBuffer<G: Game<N>, const N: usize> {
     game_id: usize,
     steps: usize,
     game_ids: Vec<usize>,
+    actions: Vec<Option<usize>>, // id of the action from the previous position, None first
     pub games: Vec<G>,
     pub states: Vec<G::Features>,
     pub pis: Vec<[f32; N]>,
@@ -119,6 +148,7 @@ impl<G: Game<N>, const N: usize> ReplayBuffer<G, N> {
             game_id: 0,
             steps: 0,
             game_ids: Vec::with_capacity(n),
+            actions: Vec::with_capacity(n),
             games: Vec::with_capacity(n),
             states: Vec::with_capacity(n),
             pis: Vec::with_capacity(n),
@@ -148,8 +178,10 @@ impl<G: Game<N>, const N: usize> ReplayBuffer<G, N> {
         self.vs.len()
     }
 
-    pub fn add(&mut self, game: &G, pi: &[f32; N], v: [f32; 3]) {
+    // `action_id` led to `game` from the position added before it, None for a game's first
+    pub fn add(&mut self, game: &G, action_id: Option<usize>, pi: &[f32; N], v: [f32; 3]) {
         self.game_ids.push(self.game_id);
+        self.actions.push(action_id);
         self.steps += 1;
         self.games.push(game.clone());
         self.states.push(game.features());
@@ -157,12 +189,45 @@ impl<G: Game<N>, const N: usize> ReplayBuffer<G, N> {
         self.vs.push(v);
     }
 
+    // adds a saved game as a new game, computing value targets the way self-play does
+    pub fn add_record(&mut self, record: &SelfPlayRecord<N>, value_target: ValueTarget) {
+        self.new_game();
+        let num_turns = record.len();
+        for (i, game) in record.replay::<G>().iter().enumerate() {
+            let t = (i + 1) as f32 / num_turns as f32;
+            let v = value_target.value(&record.qs[i], &record.zs[i], t);
+            let action_id = i.checked_sub(1).map(|j| record.actions[j]);
+            self.add(game, action_id, &record.pis[i], v);
+        }
+    }
+
+    // streams every record of a file into the buffer, returns the number of games added
+    pub fn add_records<R: Read>(
+        &mut self,
+        records: RecordReader<R, N>,
+        value_target: ValueTarget,
+    ) -> std::io::Result<usize> {
+        if records.game_name() != G::NAME {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("records are for {}, not {}", records.game_name(), G::NAME),
+            ));
+        }
+        let mut num_games = 0;
+        for record in records {
+            self.add_record(&record?, value_target);
+            num_games += 1;
+        }
+        Ok(num_games)
+    }
+
     pub fn extend(&mut self, other: &mut Self) {
         self.steps += other.steps;
         let start = self.game_id;
         self.game_ids
             .extend(other.game_ids.iter().map(|&g| g + start));
         self.game_id += other.game_id;
+        self.actions.extend(other.actions.drain(..));
         self.games.extend(other.games.drain(..));
         self.states.extend(other.states.drain(..));
         self.pis.extend(other.pis.drain(..));
@@ -185,6 +250,7 @@ impl<G: Game<N>, const N: usize> ReplayBuffer<G, N> {
         }
         if let Some(max_ind) = max_ind_to_remove {
             drop(self.game_ids.drain(0..=max_ind));
+            drop(self.actions.drain(0..=max_ind));
             drop(self.games.drain(0..=max_ind));
             drop(self.states.drain(0..=max_ind));
             drop(self.pis.drain(0..=max_ind));
@@ -193,20 +259,113 @@ impl<G: Game<N>, const N: usize> ReplayBuffer<G, N> {
         }
     }
 
-    pub fn deduplicate(&self) -> FlatBatch<G, N> {
+    // games aren't serializable, but every game starts at G::new() and its positions are one
+    // action apart, so only those actions are saved and the games are replayed on load.
+    // -1 marks the first position of a game
+    pub fn named_tensors(&self) -> Vec<(String, Tensor)> {
+        let n = self.vs.len();
+        let actions: Vec<i64> = self
+            .actions
+            .iter()
+            .map(|action_id| action_id.map_or(-1, |id| id as i64))
+            .collect();
+        let game_ids: Vec<i64> = self.game_ids.iter().map(|&g| g as i64).collect();
+        let counters = [self.game_id as i64, self.steps as i64];
+        vec![
+            (
+                String::from("buffer.counters"),
+                tensor(&counters, &[2], Kind::Int64),
+            ),
+            (
+                String::from("buffer.game_ids"),
+                tensor(&game_ids, &[n as i64], Kind::Int64),
+            ),
+            (
+                String::from("buffer.actions"),
+                tensor(&actions, &[n as i64], Kind::Int64),
+            ),
+            (
+                String::from("buffer.pis"),
+                tensor(&self.pis, &[n as i64, N as i64], Kind::Float),
+            ),
+            (
+                String::from("buffer.vs"),
+                tensor(&self.vs, &[n as i64, 3], Kind::Float),
+            ),
+        ]
+    }
+
+    pub fn from_named_tensors(
+        named: &HashMap<String, Tensor>,
+        capacity: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let get = |key: &str| {
+            named
+                .get(key)
+                .ok_or_else(|| format!("checkpoint is missing {}", key))
+        };
+        let game_ids_t = get("buffer.game_ids")?;
+        let n = game_ids_t.size()[0] as usize;
+
+        let mut counters = [0i64; 2];
+        get("buffer.counters")?.copy_data(&mut counters, 2);
+        let mut game_ids = vec![0i64; n];
+        game_ids_t.copy_data(&mut game_ids, n);
+        let mut actions = vec![0i64; n];
+        get("buffer.actions")?.copy_data(&mut actions, n);
+        let mut pis = vec![0.0f32; n * N];
+        get("buffer.pis")?.copy_data(&mut pis, n * N);
+        let mut vs = vec![0.0f32; n * 3];
+        get("buffer.vs")?.copy_data(&mut vs, n * 3);
+
+        let mut buffer = Self::new(capacity.max(n));
+        let mut game = G::new();
+        for i in 0..n {
+            let action_id = if actions[i] < 0 {
+                None
+            } else {
+                Some(actions[i] as usize)
+            };
+            match action_id {
+                Some(action_id) => {
+                    let action = game.decode_action(action_id);
+                    game.step(&action);
+                }
+                None => game = G::new(),
+            }
+            let mut pi = [0.0f32; N];
+            pi.copy_from_slice(&pis[i * N..(i + 1) * N]);
+            let mut v = [0.0f32; 3];
+            v.copy_from_slice(&vs[i * 3..(i + 1) * 3]);
+            buffer.add(&game, action_id, &pi, v);
+            buffer.game_ids[i] = game_ids[i] as usize;
+        }
+        buffer.game_id = counters[0] as usize;
+        buffer.steps = counters[1] as usize;
+        Ok(buffer)
+    }
+}
+
+impl<G: Symmetries<N>, const N: usize> ReplayBuffer<G, N> {
+    // merges symmetric positions under their canonical image, with `augment` every
+    // distinct image of a merged position is emitted as its own sample
+    pub fn deduplicate(&self, augment: bool) -> FlatBatch<G, N> {
         let mut statistics: HashMap<G, StateStatistics<G, N>> =
             HashMap::with_capacity(self.game_ids.len());
         for i in 0..self.game_ids.len() {
-            let stats = statistics
-                .entry(self.games[i].clone())
-                .or_insert(StateStatistics {
-                    state: self.states[i].clone(),
-                    sum_pi: [0.0; N],
-                    sum_v: [0.0; 3],
-                    num: 0,
-                });
+            let (canonical, sym) = self.games[i].canonical();
+            let pi = G::map_policy(sym, &self.pis[i]);
+            let stats =
+                statistics
+                    .entry(canonical)
+                    .or_insert_with_key(|canonical| StateStatistics {
+                        state: canonical.features(),
+                        sum_pi: [0.0; N],
+                        sum_v: [0.0; 3],
+                        num: 0,
+                    });
             for j in 0..N {
-                stats.sum_pi[j] += self.pis[i][j];
+                stats.sum_pi[j] += pi[j];
             }
             for j in 0..3 {
                 stats.sum_v[j] += self.vs[i][j];
@@ -217,7 +376,7 @@ impl<G: Game<N>, const N: usize> ReplayBuffer<G, N> {
         let mut states = Vec::with_capacity(statistics.len());
         let mut pis = Vec::with_capacity(statistics.len());
         let mut vs = Vec::with_capacity(statistics.len());
-        for (_, stats) in statistics.iter() {
+        for (game, stats) in statistics.iter() {
             let mut avg_pi = [0.0; N];
             for i in 0..N {
                 avg_pi[i] = stats.sum_pi[i] / stats.num as f32;
@@ -226,11 +385,106 @@ impl<G: Game<N>, const N: usize> ReplayBuffer<G, N> {
             for i in 0..3 {
                 avg_v[i] = stats.sum_v[i] / stats.num as f32;
             }
-            states.push(stats.state.clone());
-            pis.push(avg_pi);
-            vs.push(avg_v);
+            if augment {
+                for (image, image_pi) in game.symmetries(&avg_pi) {
+                    states.push(image.features());
+                    pis.push(image_pi);
+                    vs.push(avg_v);
+                }
+            } else {
+                states.push(stats.state.clone());
+                pis.push(avg_pi);
+                vs.push(avg_v);
+            }
         }
 
         FlatBatch { states, pis, vs }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcts::tests::TicTacToe;
+    use crate::records::tests::record;
+    use crate::records::RecordWriter;
+
+    fn filled() -> ReplayBuffer<TicTacToe, 9> {
+        let mut buffer = ReplayBuffer::new(16);
+        buffer.add_record(&record("model_0.ot", 3, &[4, 0, 8]), ValueTarget::Z);
+        buffer.add_record(&record("model_1.ot", 8, &[0, 1, 2, 3]), ValueTarget::Q);
+        buffer
+    }
+
+    fn assert_same(a: &ReplayBuffer<TicTacToe, 9>, b: &ReplayBuffer<TicTacToe, 9>) {
+        assert_eq!(a.game_id, b.game_id);
+        assert_eq!(a.steps, b.steps);
+        assert_eq!(a.game_ids, b.game_ids);
+        assert_eq!(a.actions, b.actions);
+        assert_eq!(a.games, b.games);
+        assert_eq!(a.states, b.states);
+        assert_eq!(a.pis, b.pis);
+        assert_eq!(a.vs, b.vs);
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let buffer = filled();
+        assert_eq!(buffer.curr_steps(), 7);
+        let named: HashMap<String, Tensor> = buffer.named_tensors().into_iter().collect();
+        let restored = ReplayBuffer::from_named_tensors(&named, 16).unwrap();
+        assert_same(&restored, &buffer);
+
+        let mut game = TicTacToe::new();
+        game.step(&game.decode_action(4));
+        game.step(&game.decode_action(0));
+        assert_eq!(restored.games[2], game);
+        assert_eq!(restored.games[3], TicTacToe::new());
+    }
+
+    #[test]
+    fn test_add_records_from_file() {
+        let records = [
+            record("model_0.ot", 3, &[4, 0, 8]),
+            record("model_1.ot", 8, &[0, 1, 2, 3]),
+        ];
+        let mut bytes = Vec::new();
+        let mut writer = RecordWriter::new(&mut bytes, TicTacToe::NAME).unwrap();
+        for record in records.iter() {
+            writer.write(record).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let mut buffer = ReplayBuffer::<TicTacToe, 9>::new(16);
+        let reader = RecordReader::new(bytes.as_slice()).unwrap();
+        let value_target = ValueTarget::QtoZ { from: 0.0, to: 1.0 };
+        assert_eq!(buffer.add_records(reader, value_target).unwrap(), 2);
+
+        let mut expected = ReplayBuffer::new(16);
+        for record in records.iter() {
+            expected.add_record(record, value_target);
+        }
+        assert_same(&buffer, &expected);
+
+        // positions replay the actions, targets interpolate from Q to Z over the game
+        let positions = [
+            records[0].replay::<TicTacToe>(),
+            records[1].replay::<TicTacToe>(),
+        ];
+        assert_eq!(buffer.games, positions.concat());
+        assert_eq!(buffer.pis[4], records[1].pis[1]);
+        let q = records[1].qs[1];
+        let z = records[1].zs[1];
+        let t = 0.5;
+        for i in 0..3 {
+            assert!((buffer.vs[4][i] - (q[i] * (1.0 - t) + z[i] * t)).abs() < 1e-6);
+        }
+
+        let mut other_bytes = Vec::new();
+        let mut writer = RecordWriter::<_, 9>::new(&mut other_bytes, "Other").unwrap();
+        writer.write(&records[0]).unwrap();
+        writer.flush().unwrap();
+        let reader = RecordReader::new(other_bytes.as_slice()).unwrap();
+        assert!(buffer.add_records(reader, value_target).is_err());
+    }
+}
@@ -1,45 +1,99 @@
+use crate::alpha_beta::AlphaBeta;
 use crate::config::*;
 use crate::game::*;
 use crate::mcts::MCTS;
+use crate::pgn::GameRecord;
 use crate::policies::*;
 use crate::utils::*;
 use rand::prelude::{Rng, SeedableRng, StdRng};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 use tch::nn::VarStore;
 
-pub fn evaluator<G: Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
+pub fn evaluator<G: Undo<N> + Notation<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
     cfg: &EvaluationConfig,
+    oracle: Option<Box<dyn Oracle<G, N>>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     std::thread::sleep(std::time::Duration::from_secs(1));
 
     let models_dir = cfg.logs.join("models");
     let pgn_path = cfg.logs.join("results.pgn");
-    let mut pgn = std::fs::File::create(&pgn_path)?;
+    let evaluated_path = cfg.logs.join("evaluated.csv");
+    // a resumed run keeps the results of every model it finished and plays the rest again
+    let resumed = match std::fs::read_to_string(&evaluated_path) {
+        Ok(evaluated) if cfg.resume => last_evaluated(&evaluated),
+        _ => None,
+    };
+    let (mut pgn, start_iter) = match resumed {
+        Some((start_iter, pgn_len)) => {
+            let pgn = OpenOptions::new().append(true).open(&pgn_path)?;
+            // drops the games of a model whose evaluation was cut off
+            pgn.set_len(pgn_len)?;
+            calculate_ratings(&cfg.logs)?;
+            println!("Resuming evaluation from model_{}.ot", start_iter);
+            (pgn, start_iter)
+        }
+        None => {
+            File::create(&evaluated_path)?;
+            (File::create(&pgn_path)?, 0)
+        }
+    };
     let _guard = tch::no_grad_guard();
     let first_player = G::new().player();
 
-    let mut best_k = Vec::with_capacity(cfg.num_best_policies);
+    // settings of each kind of player, for the WhiteConfig and BlackConfig tags
+    let policy_cfg = format!(
+        "{} explores, {:?}, {:?}",
+        cfg.policy_num_explores, cfg.policy_action, cfg.policy_mcts_cfg
+    );
+    let rollout_cfg = |explores: usize| {
+        format!(
+            "{} explores, {:?}, {:?}",
+            explores, cfg.rollout_action, cfg.rollout_mcts_cfg
+        )
+    };
+    let alpha_beta_cfg = |max_nodes: usize| {
+        let mut alpha_beta_cfg = cfg.alpha_beta_cfg;
+        alpha_beta_cfg.max_nodes = max_nodes;
+        format!(
+            "{:?}, {} playouts",
+            alpha_beta_cfg,
+            cfg.alpha_beta_num_playouts.max(1)
+        )
+    };
+
+    let mut best_k: Vec<String> = Vec::with_capacity(cfg.num_best_policies);
+    if start_iter > 0 {
+        best_k.extend(rankings(&cfg.logs)?.into_iter().take(cfg.num_best_policies));
+    }
 
-    for i_iter in 0.. {
+    for i_iter in start_iter.. {
         // add new games for baselines so they don't fall behind
         {
             let i = i_iter % cfg.rollout_num_explores.len();
+            let mut jobs = Vec::new();
             for j in 0..cfg.rollout_num_explores.len() {
                 if i == j {
                     continue;
                 }
-                let seed = i_iter;
-                add_pgn_result(
-                    &mut pgn,
-                    &format!("VanillaMCTS{}", cfg.rollout_num_explores[i]),
-                    &format!("VanillaMCTS{}", cfg.rollout_num_explores[j]),
-                    mcts_vs_mcts::<G, N>(
-                        &cfg,
-                        first_player,
-                        cfg.rollout_num_explores[i],
-                        cfg.rollout_num_explores[j],
-                        seed as u64,
-                    ),
-                )?;
+                let seed = i_iter as u64;
+                let explores = [cfg.rollout_num_explores[i], cfg.rollout_num_explores[j]];
+                let record = new_record::<G, N>(
+                    [
+                        &format!("VanillaMCTS{}", explores[0]),
+                        &format!("VanillaMCTS{}", explores[1]),
+                    ],
+                    [&rollout_cfg(explores[0]), &rollout_cfg(explores[1])],
+                    Some(seed),
+                );
+                jobs.push((Job::Baselines { explores, seed }, record));
+            }
+            for (reward, termination, mut record) in play_jobs::<G, P, N>(cfg, jobs) {
+                add_pgn_record(&mut pgn, &mut record, reward, termination)?;
             }
             calculate_ratings(&cfg.logs)?;
             plot_ratings(&cfg.logs)?;
@@ -54,52 +108,135 @@ pub fn evaluator<G: Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
         // wait an extra second to be sure data is there
         std::thread::sleep(std::time::Duration::from_secs(1));
 
-        // load model
-        let mut vs = VarStore::new(tch::Device::Cpu);
-        let mut policy = P::new(&vs);
-        vs.load(models_dir.join(&name))?;
-
         // evaluate against rollout mcts
+        let mut jobs = Vec::new();
         for &explores in cfg.rollout_num_explores.iter() {
             let op_name = format!("VanillaMCTS{}", explores);
-            for seed in 0..cfg.num_games_against_rollout {
-                let result = eval_against_rollout_mcts(
-                    &cfg,
+            let op_cfg = rollout_cfg(explores);
+            for seed in 0..cfg.num_games_against_rollout as u64 {
+                for model_first in [true, false] {
+                    let record = if model_first {
+                        new_record::<G, N>([&name, &op_name], [&policy_cfg, &op_cfg], Some(seed))
+                    } else {
+                        new_record::<G, N>([&op_name, &name], [&op_cfg, &policy_cfg], Some(seed))
+                    };
+                    let job = Job::Rollout {
+                        model: name.clone(),
+                        model_first,
+                        explores,
+                        seed,
+                    };
+                    jobs.push((job, record));
+                }
+            }
+        }
+        for (reward, termination, mut record) in play_jobs::<G, P, N>(cfg, jobs) {
+            add_pgn_record(&mut pgn, &mut record, reward, termination)?;
+        }
+        calculate_ratings(&cfg.logs)?;
+        plot_ratings(&cfg.logs)?;
+
+        // evaluate against alpha-beta search
+        let mut jobs = Vec::new();
+        for &max_nodes in cfg.alpha_beta_max_nodes.iter() {
+            let op_name = format!("AlphaBeta{}", max_nodes);
+            let op_cfg = alpha_beta_cfg(max_nodes);
+            for seed in 0..cfg.num_games_against_alpha_beta as u64 {
+                for model_first in [true, false] {
+                    let record = if model_first {
+                        new_record::<G, N>([&name, &op_name], [&policy_cfg, &op_cfg], Some(seed))
+                    } else {
+                        new_record::<G, N>([&op_name, &name], [&op_cfg, &policy_cfg], Some(seed))
+                    };
+                    let job = Job::AlphaBeta {
+                        model: name.clone(),
+                        model_first,
+                        max_nodes,
+                        seed,
+                    };
+                    jobs.push((job, record));
+                }
+            }
+        }
+        for (reward, termination, mut record) in play_jobs::<G, P, N>(cfg, jobs) {
+            add_pgn_record(&mut pgn, &mut record, reward, termination)?;
+        }
+        calculate_ratings(&cfg.logs)?;
+        plot_ratings(&cfg.logs)?;
+
+        // evaluate against perfect play, and check the value head against the true outcomes.
+        // Oracles aren't Sync, so these games stay on this thread
+        if let Some(oracle) = oracle.as_deref() {
+            let mut policy = load_policy::<G, P, N>(cfg, &name);
+            let op_name = String::from("Perfect");
+            let op_cfg = String::from("oracle, ties broken by the seed");
+            let mut num_correct = 0;
+            let mut num_positions = 0;
+            for seed in 0..cfg.num_games_against_oracle {
+                let mut record = new_record::<G, N>(
+                    [&name, &op_name],
+                    [&policy_cfg, &op_cfg],
+                    Some(seed as u64),
+                );
+                let (reward, result, [correct, positions]) = eval_against_oracle(
+                    cfg,
                     &mut policy,
+                    oracle,
                     first_player,
-                    explores,
                     seed as u64,
+                    &mut record,
                 );
-                add_pgn_result(&mut pgn, &name, &op_name, result)?;
-                let result = eval_against_rollout_mcts(
-                    &cfg,
+                add_pgn_record(&mut pgn, &mut record, reward, result.termination())?;
+                num_correct += correct;
+                num_positions += positions;
+                let mut record = new_record::<G, N>(
+                    [&op_name, &name],
+                    [&op_cfg, &policy_cfg],
+                    Some(seed as u64),
+                );
+                let (reward, result, [correct, positions]) = eval_against_oracle(
+                    cfg,
                     &mut policy,
+                    oracle,
                     first_player.next(),
-                    explores,
                     seed as u64,
+                    &mut record,
                 );
-                add_pgn_result(&mut pgn, &op_name, &name, result)?;
+                add_pgn_record(&mut pgn, &mut record, reward, result.termination())?;
+                num_correct += correct;
+                num_positions += positions;
             }
+            add_value_accuracy(&cfg.logs, &name, num_correct, num_positions)?;
             calculate_ratings(&cfg.logs)?;
             plot_ratings(&cfg.logs)?;
         }
 
-        // evaluate against best old policies
-        for (prev_name, prev_p) in best_k.iter_mut() {
-            let result = eval_against_old(&cfg, &mut policy, prev_p);
-            add_pgn_result(&mut pgn, &name, &prev_name, result)?;
-
-            let result = eval_against_old(&cfg, prev_p, &mut policy);
-            add_pgn_result(&mut pgn, &prev_name, &name, result)?;
+        // evaluate against best old policies. Both models search without a rollout policy, the
+        // seed only numbers the pair of games like those of the other jobs
+        let mut jobs = Vec::new();
+        for (seed, prev_name) in best_k.iter().enumerate() {
+            let seed = seed as u64;
+            let cfgs = [policy_cfg.as_str(), policy_cfg.as_str()];
+            let record = new_record::<G, N>([&name, prev_name], cfgs, Some(seed));
+            let models = [name.clone(), prev_name.clone()];
+            jobs.push((Job::Old { models }, record));
+
+            let record = new_record::<G, N>([prev_name, &name], cfgs, Some(seed));
+            let models = [prev_name.clone(), name.clone()];
+            jobs.push((Job::Old { models }, record));
+        }
+        for (reward, termination, mut record) in play_jobs::<G, P, N>(cfg, jobs) {
+            add_pgn_record(&mut pgn, &mut record, reward, termination)?;
         }
 
         // update results
         calculate_ratings(&cfg.logs)?;
         plot_ratings(&cfg.logs)?;
+        mark_evaluated(&evaluated_path, &name, pgn.metadata()?.len())?;
 
         // update top k
         if best_k.len() < cfg.num_best_policies {
-            best_k.push((name, policy));
+            best_k.push(name);
         } else {
             let ranks = rankings(&cfg.logs)?;
             if ranks
@@ -108,8 +245,8 @@ pub fn evaluator<G: Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
                 .position(|n| n == &name)
                 .is_some()
             {
-                best_k.push((name, policy));
-                match best_k.iter().position(|(n, _p)| {
+                best_k.push(name);
+                match best_k.iter().position(|n| {
                     ranks
                         .iter()
                         .take(cfg.num_best_policies)
@@ -128,11 +265,189 @@ pub fn evaluator<G: Game<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
     Ok(())
 }
 
-fn eval_against_old<G: Game<N>, P: Policy<G, N>, const N: usize>(
+// appends that every game of model `name` is in results.pgn, which is `pgn_len` bytes long then
+fn mark_evaluated(path: &Path, name: &str, pgn_len: u64) -> std::io::Result<()> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(f, "{},{}", name, pgn_len)
+}
+
+// the iteration after the last model in evaluated.csv and how long results.pgn was when it was
+// done, None if no model was. A line cut off while it was written doesn't count
+fn last_evaluated(evaluated: &str) -> Option<(usize, u64)> {
+    evaluated.split_inclusive('\n').rev().find_map(|line| {
+        let (name, pgn_len) = line.strip_suffix('\n')?.split_once(',')?;
+        let i_iter: usize = name
+            .strip_prefix("model_")?
+            .strip_suffix(".ot")?
+            .parse()
+            .ok()?;
+        Some((i_iter + 1, pgn_len.parse().ok()?))
+    })
+}
+
+// One evaluation game. Models are named by their file in logs/models, workers load them
+// themselves so policies never cross threads
+enum Job {
+    Baselines {
+        explores: [usize; 2], // white first
+        seed: u64,
+    },
+    Rollout {
+        model: String,
+        model_first: bool,
+        explores: usize,
+        seed: u64,
+    },
+    AlphaBeta {
+        model: String,
+        model_first: bool,
+        max_nodes: usize,
+        seed: u64,
+    },
+    Old {
+        models: [String; 2], // white first
+    },
+}
+
+// plays `jobs` on `cfg.num_workers` threads and returns the white reward, termination and record
+// of every game in the order of `jobs`, whichever order they finish in
+fn play_jobs<G: Undo<N> + Notation<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
+    cfg: &EvaluationConfig,
+    jobs: Vec<(Job, GameRecord)>,
+) -> Vec<(f32, &'static str, GameRecord)> {
+    let next_job = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..cfg.num_workers.max(1).min(jobs.len()) {
+            let sender = sender.clone();
+            let (next_job, jobs) = (&next_job, &jobs);
+            scope.spawn(move || {
+                let _guard = tch::no_grad_guard();
+                let mut policies = HashMap::new();
+                loop {
+                    let i = next_job.fetch_add(1, Ordering::SeqCst);
+                    if i >= jobs.len() {
+                        break;
+                    }
+                    let (job, record) = &jobs[i];
+                    let mut record = record.clone();
+                    let (reward, termination) =
+                        play_job::<G, P, N>(cfg, job, &mut policies, &mut record);
+                    sender.send((i, (reward, termination, record))).unwrap();
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut results: Vec<_> = jobs.iter().map(|_| None).collect();
+    for (i, result) in receiver {
+        results[i] = Some(result);
+    }
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+// `policies` caches the models this worker has loaded
+fn play_job<G: Undo<N> + Notation<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
+    cfg: &EvaluationConfig,
+    job: &Job,
+    policies: &mut HashMap<String, P>,
+    record: &mut GameRecord,
+) -> (f32, &'static str) {
+    let first_player = G::new().player();
+    let model_player = |model_first: bool| {
+        if model_first {
+            first_player
+        } else {
+            first_player.next()
+        }
+    };
+    let (reward, result) = match job {
+        Job::Baselines { explores, seed } => {
+            mcts_vs_mcts::<G, N>(cfg, first_player, explores[0], explores[1], *seed, record)
+        }
+        Job::Rollout {
+            model,
+            model_first,
+            explores,
+            seed,
+        } => eval_against_rollout_mcts(
+            cfg,
+            cached_policy::<G, P, N>(cfg, policies, model),
+            model_player(*model_first),
+            *explores,
+            *seed,
+            record,
+        ),
+        Job::AlphaBeta {
+            model,
+            model_first,
+            max_nodes,
+            seed,
+        } => eval_against_alpha_beta(
+            cfg,
+            cached_policy::<G, P, N>(cfg, policies, model),
+            model_player(*model_first),
+            *max_nodes,
+            *seed,
+            record,
+        ),
+        Job::Old { models } => {
+            cached_policy::<G, P, N>(cfg, policies, &models[0]);
+            cached_policy::<G, P, N>(cfg, policies, &models[1]);
+            let mut p1 = policies.remove(&models[0]).unwrap();
+            let mut p2 = policies.remove(&models[1]).unwrap();
+            let outcome = eval_against_old(cfg, &mut p1, &mut p2, record);
+            policies.insert(models[0].clone(), p1);
+            policies.insert(models[1].clone(), p2);
+            outcome
+        }
+    };
+    (reward, result.termination())
+}
+
+fn cached_policy<'a, G: Game<N>, P: NNPolicy<G, N>, const N: usize>(
+    cfg: &EvaluationConfig,
+    policies: &'a mut HashMap<String, P>,
+    name: &str,
+) -> &'a mut P {
+    policies
+        .entry(String::from(name))
+        .or_insert_with(|| load_policy::<G, P, N>(cfg, name))
+}
+
+fn load_policy<G: Game<N>, P: NNPolicy<G, N>, const N: usize>(
+    cfg: &EvaluationConfig,
+    name: &str,
+) -> P {
+    let mut vs = VarStore::new(tch::Device::Cpu);
+    let policy = P::new(&vs);
+    policy.check_shapes().unwrap();
+    vs.load(cfg.logs.join("models").join(name)).unwrap();
+    policy
+}
+
+// a record of a game between `names[0]` as white and `names[1]` as black, played with `cfgs`
+fn new_record<G: Game<N>, const N: usize>(
+    names: [&str; 2],
+    cfgs: [&str; 2],
+    seed: Option<u64>,
+) -> GameRecord {
+    let mut record = GameRecord::new(G::NAME, names[0], names[1]);
+    if let Some(seed) = seed {
+        record.set_tag("Seed", seed);
+    }
+    record.set_tag("WhiteConfig", cfgs[0]);
+    record.set_tag("BlackConfig", cfgs[1]);
+    record
+}
+
+fn eval_against_old<G: Notation<N>, P: Policy<G, N>, const N: usize>(
     cfg: &EvaluationConfig,
     p1: &mut P,
     p2: &mut P,
-) -> f32 {
+    record: &mut GameRecord,
+) -> (f32, GameResult<G::PlayerId>) {
     let mut game = G::new();
     let first_player = game.player();
     loop {
@@ -153,20 +468,22 @@ fn eval_against_old<G: Game<N>, P: Policy<G, N>, const N: usize>(
                 cfg.policy_action,
             )
         };
+        record.push(&game, &action);
         if game.step(&action) {
             break;
         }
     }
-    game.reward(first_player)
+    (game.reward(first_player), game.result().unwrap())
 }
 
-fn eval_against_rollout_mcts<G: Game<N>, P: Policy<G, N>, const N: usize>(
+fn eval_against_rollout_mcts<G: Notation<N>, P: Policy<G, N>, const N: usize>(
     cfg: &EvaluationConfig,
     policy: &mut P,
     player: G::PlayerId,
     opponent_explores: usize,
     seed: u64,
-) -> f32 {
+    record: &mut GameRecord,
+) -> (f32, GameResult<G::PlayerId>) {
     let mut game = G::new();
     let first_player = game.player();
     let mut rng = StdRng::seed_from_u64(seed);
@@ -190,20 +507,119 @@ fn eval_against_rollout_mcts<G: Game<N>, P: Policy<G, N>, const N: usize>(
             )
         };
 
+        record.push(&game, &action);
+        if game.step(&action) {
+            break;
+        }
+    }
+    (game.reward(first_player), game.result().unwrap())
+}
+
+// the opponent is classical search: no net, leaves scored by the mean of random playouts
+fn eval_against_alpha_beta<G: Undo<N> + Notation<N>, P: Policy<G, N>, const N: usize>(
+    cfg: &EvaluationConfig,
+    policy: &mut P,
+    player: G::PlayerId,
+    opponent_max_nodes: usize,
+    seed: u64,
+    record: &mut GameRecord,
+) -> (f32, GameResult<G::PlayerId>) {
+    let mut game = G::new();
+    let first_player = game.player();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut rollout_policy = RolloutPolicy { rng: &mut rng };
+    let mut alpha_beta_cfg = cfg.alpha_beta_cfg;
+    alpha_beta_cfg.max_nodes = opponent_max_nodes;
+    let num_playouts = cfg.alpha_beta_num_playouts.max(1);
+    loop {
+        let action = if game.player() == player {
+            MCTS::exploit(
+                cfg.policy_num_explores,
+                cfg.policy_mcts_cfg,
+                policy,
+                game.clone(),
+                cfg.policy_action,
+            )
+        } else {
+            let eval = |rollout_policy: &mut RolloutPolicy<StdRng>, game: &mut G| {
+                let total: f32 = (0..num_playouts)
+                    .map(|_| playout::<G, StdRng, N>(game, rollout_policy.rng))
+                    .sum();
+                total / num_playouts as f32
+            };
+            AlphaBeta::exploit(alpha_beta_cfg, &mut rollout_policy, eval, game.clone())
+        };
+
+        record.push(&game, &action);
+        if game.step(&action) {
+            break;
+        }
+    }
+    (game.reward(first_player), game.result().unwrap())
+}
+
+// the oracle breaks ties between equally good actions with the seed. Also returns how many of
+// the policy's positions its value head put the most weight on the true outcome, and out of how many
+fn eval_against_oracle<G: Notation<N>, P: Policy<G, N>, const N: usize>(
+    cfg: &EvaluationConfig,
+    policy: &mut P,
+    oracle: &dyn Oracle<G, N>,
+    player: G::PlayerId,
+    seed: u64,
+    record: &mut GameRecord,
+) -> (f32, GameResult<G::PlayerId>, [usize; 2]) {
+    let mut game = G::new();
+    let first_player = game.player();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut predictions = [0, 0];
+    loop {
+        let action = if game.player() == player {
+            if let Some(outcome) = oracle.outcome(&game) {
+                let (_, outcome_probs) = policy.eval(&game);
+                let mut predicted = 0;
+                for i in 1..3 {
+                    if outcome_probs[i] > outcome_probs[predicted] {
+                        predicted = i;
+                    }
+                }
+                if predicted == Into::<usize>::into(outcome) {
+                    predictions[0] += 1;
+                }
+                predictions[1] += 1;
+            }
+            MCTS::exploit(
+                cfg.policy_num_explores,
+                cfg.policy_mcts_cfg,
+                policy,
+                game.clone(),
+                cfg.policy_action,
+            )
+        } else {
+            let actions = oracle.best_actions(&game);
+            assert!(!actions.is_empty(), "oracle has no outcome for {:?}", game);
+            actions[rng.gen_range(0..actions.len())]
+        };
+
+        record.push(&game, &action);
         if game.step(&action) {
             break;
         }
     }
-    game.reward(first_player)
+    (
+        game.reward(first_player),
+        game.result().unwrap(),
+        predictions,
+    )
 }
 
-fn mcts_vs_mcts<G: Game<N>, const N: usize>(
+fn mcts_vs_mcts<G: Notation<N>, const N: usize>(
     cfg: &EvaluationConfig,
     player: G::PlayerId,
     p1_explores: usize,
     p2_explores: usize,
     seed: u64,
-) -> f32 {
+    record: &mut GameRecord,
+) -> (f32, GameResult<G::PlayerId>) {
     let mut rng = StdRng::seed_from_u64(seed);
     let mut rollout_policy = RolloutPolicy { rng: &mut rng };
     let mut game = G::new();
@@ -220,24 +636,25 @@ fn mcts_vs_mcts<G: Game<N>, const N: usize>(
             game.clone(),
             cfg.rollout_action,
         );
+        record.push(&game, &action);
         if game.step(&action) {
             break;
         }
     }
-    game.reward(first_player)
+    (game.reward(first_player), game.result().unwrap())
 }
 
 type NodeId = u32;
-type ActionId = u8;
+type ActionId = u16;
 
 #[derive(Debug)]
 struct Node<G: Game<N>, const N: usize> {
     parent: NodeId,            // 4 bytes
     first_child: NodeId,       // 4 bytes
-    num_children: u8,          // 1 byte
+    num_children: u16,         // 2 bytes
     game: G,                   // ? bytes
     solution: Option<Outcome>, // 1 byte
-    action: ActionId,          // 1 byte
+    action: ActionId,          // 2 bytes
     action_prob: f32,          // 4 bytes
     cum_value: f32,            // 4 bytes
     num_visits: f32,           // 4 bytes
@@ -248,7 +665,7 @@ impl<G: Game<N>, const N: usize> Node<G, N> {
         parent: NodeId,
         game: G,
         solution: Option<Outcome>,
-        action: u8,
+        action: ActionId,
         action_prob: f32,
     ) -> Self {
         Self {
@@ -285,7 +702,7 @@ impl<G: Game<N>, const N: usize> Node<G, N> {
     }
 
     #[inline]
-    fn mark_visited(&mut self, first_child: NodeId, num_children: u8) {
+    fn mark_visited(&mut self, first_child: NodeId, num_children: u16) {
         self.first_child = first_child;
         self.num_children = num_children;
     }
@@ -369,11 +786,12 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> FrozenMCTS<'a, G, P, N> {
                 None => match action_selection {
                     ActionSelection::Q => -child.cum_value / child.num_visits,
                     ActionSelection::NumVisits => child.num_visits,
+                    ActionSelection::Gumbel => panic!("Not supported in frozen mcts"),
                 },
             };
             if best_action.is_none() || value > best_value {
                 best_value = value;
-                best_action = Some((child.action as usize).into());
+                best_action = Some(root.game.decode_action(child.action as usize));
             }
         }
         best_action.unwrap()
@@ -454,7 +872,7 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> FrozenMCTS<'a, G, P, N> {
             let action: usize = action.into();
             let logit = logits[action];
             max_logit = max_logit.max(logit);
-            let child = Node::unvisited(node_id, child_game, solution, action as u8, logit);
+            let child = Node::unvisited(node_id, child_game, solution, action as ActionId, logit);
             self.nodes.push(child);
             num_children += 1;
         }
@@ -532,3 +950,29 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> FrozenMCTS<'a, G, P, N> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_evaluated() {
+        assert_eq!(last_evaluated(""), None);
+        assert_eq!(
+            last_evaluated("model_0.ot,120\nmodel_1.ot,4096\n"),
+            Some((2, 4096))
+        );
+        assert_eq!(
+            last_evaluated("model_0.ot,120\nmodel_1.ot,40"),
+            Some((1, 120))
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("synthesis_{}_evaluated.csv", std::process::id()));
+        mark_evaluated(&path, "model_0.ot", 120).unwrap();
+        mark_evaluated(&path, "model_1.ot", 4096).unwrap();
+        let evaluated = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(last_evaluated(&evaluated), Some((2, 4096)));
+    }
+}
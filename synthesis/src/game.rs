@@ -1,5 +1,6 @@
 use std::cmp::Ordering;
-use std::hash::Hash;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 pub trait HasTurnOrder: Eq + Clone + Copy + std::fmt::Debug {
     fn prev(&self) -> Self;
@@ -65,9 +66,29 @@ impl PartialOrd for Outcome {
     }
 }
 
+// How a finished game ended. Finer grained than `reward` so logs can tell draws apart.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GameResult<P> {
+    Win(P),
+    Draw,
+    Repetition,
+    TurnLimit,
+}
+
+impl<P> GameResult<P> {
+    // value for the PGN Termination tag
+    pub fn termination(&self) -> &'static str {
+        match self {
+            Self::Win(_) | Self::Draw => "normal",
+            Self::Repetition => "repetition",
+            Self::TurnLimit => "turn limit",
+        }
+    }
+}
+
 pub trait Game<const N: usize>: Eq + Hash + Clone + std::fmt::Debug + Send {
     type PlayerId: HasTurnOrder;
-    type Action: Eq + Clone + Copy + std::fmt::Debug + Into<usize> + From<usize>;
+    type Action: Eq + Clone + Copy + std::fmt::Debug + Into<usize>;
     type ActionIterator: Iterator<Item = Self::Action>;
     type Features: PartialEq + Clone + std::fmt::Debug + Send;
 
@@ -82,11 +103,144 @@ pub trait Game<const N: usize>: Eq + Hash + Clone + std::fmt::Debug + Send {
     fn is_over(&self) -> bool;
     fn reward(&self, player_id: Self::PlayerId) -> f32;
     fn iter_actions(&self) -> Self::ActionIterator;
+    // inverse of `Into<usize>`, decoded for the player to move in this state
+    fn decode_action(&self, action_id: usize) -> Self::Action;
+    // why the game ended, None while it is still going. The default only tells wins from draws
+    fn result(&self) -> Option<GameResult<Self::PlayerId>> {
+        if !self.is_over() {
+            return None;
+        }
+        let player = self.player();
+        let reward = self.reward(player);
+        Some(if reward > 0.0 {
+            GameResult::Win(player)
+        } else if reward < 0.0 {
+            GameResult::Win(player.next())
+        } else {
+            GameResult::Draw
+        })
+    }
     fn step(&mut self, action: &Self::Action) -> bool;
     fn features(&self) -> Self::Features;
     fn print(&self);
 }
 
+// Games that can take back actions, so playouts and alpha-beta search them in place instead of
+// cloning every child.
+pub trait Undo<const N: usize>: Game<N> {
+    // takes back `action`, which must be the last action `step` applied to this game. An action
+    // `step` refused as illegal changed nothing, undoing it corrupts the game
+    fn undo(&mut self, action: &Self::Action);
+}
+
+// How actions are written in game records. The defaults write action ids, so a game without a
+// notation of its own can implement this with an empty body.
+pub trait Notation<const N: usize>: Game<N> {
+    // `action` as played in this state
+    fn notation(&self, action: &Self::Action) -> String {
+        Into::<usize>::into(*action).to_string()
+    }
+
+    // inverse of `notation`, None for text that isn't an action. Legality isn't checked
+    fn parse_notation(&self, text: &str) -> Option<Self::Action> {
+        let action_id: usize = text.parse().ok()?;
+        if action_id < N {
+            Some(self.decode_action(action_id))
+        } else {
+            None
+        }
+    }
+}
+
+// Optional board symmetries of a game. The defaults only know the identity, so a game
+// without symmetries can implement this with an empty body.
+pub trait Symmetries<const N: usize>: Game<N> {
+    const NUM_SYMMETRIES: usize = 1;
+
+    // image of the state under symmetry `i`, 0 is always the identity
+    fn map_state(&self, _i: usize) -> Self {
+        self.clone()
+    }
+
+    // image of an action id under symmetry `i`, must be a permutation of 0..N
+    fn map_action(_i: usize, action_id: usize) -> usize {
+        action_id
+    }
+
+    // the symmetry that undoes `i`
+    fn inverse(i: usize) -> usize {
+        i
+    }
+
+    fn map_policy(i: usize, policy: &[f32; N]) -> [f32; N] {
+        let mut mapped = [0.0; N];
+        for (action_id, &p) in policy.iter().enumerate() {
+            mapped[Self::map_action(i, action_id)] = p;
+        }
+        mapped
+    }
+
+    // every distinct image of the state along with the matching policy, identity first
+    fn symmetries(&self, policy: &[f32; N]) -> Vec<(Self, [f32; N])> {
+        let mut images: Vec<(Self, [f32; N])> = Vec::with_capacity(Self::NUM_SYMMETRIES);
+        for i in 0..Self::NUM_SYMMETRIES {
+            let state = self.map_state(i);
+            if images.iter().all(|(image, _)| *image != state) {
+                images.push((state, Self::map_policy(i, policy)));
+            }
+        }
+        images
+    }
+
+    // the image with the smallest hash stands for all of them, returned with the symmetry that produced it
+    fn canonical(&self) -> (Self, usize) {
+        if Self::NUM_SYMMETRIES == 1 {
+            return (self.clone(), 0);
+        }
+        (0..Self::NUM_SYMMETRIES)
+            .map(|i| (self.map_state(i), i))
+            .min_by_key(|(state, _)| {
+                // DefaultHasher::new always uses the same keys, so this is stable across caches
+                let mut hasher = DefaultHasher::new();
+                state.hash(&mut hasher);
+                hasher.finish()
+            })
+            .unwrap()
+    }
+}
+
+// Perfect knowledge of a game, e.g. a table from a solver. Outcomes are from the view of the
+// player to move, None for positions it doesn't know.
+pub trait Oracle<G: Game<N>, const N: usize>: Send {
+    fn outcome(&self, game: &G) -> Option<Outcome>;
+
+    // every action that leads to the best known outcome for the player to move
+    fn best_actions(&self, game: &G) -> Vec<G::Action> {
+        let mut best = None;
+        let mut actions = Vec::new();
+        for action in game.iter_actions() {
+            let mut child = game.clone();
+            let outcome = if child.step(&action) {
+                Some(Outcome::from(child.reward(child.player())))
+            } else {
+                self.outcome(&child)
+            };
+            let outcome = match outcome {
+                Some(outcome) => Some(outcome.reversed()),
+                None => continue,
+            };
+            if outcome > best {
+                best = outcome;
+                actions.clear();
+            }
+            if outcome == best {
+                actions.push(action);
+            }
+        }
+        actions
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,312 @@
+use crate::config::GatingConfig;
+use crate::game::{Game, HasTurnOrder, Notation};
+use crate::mcts::MCTS;
+use crate::pgn::GameRecord;
+use crate::policies::{NNPolicy, Policy};
+use crate::ratings::outcome_terms;
+use rand::prelude::{Rng, SeedableRng, StdRng};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use tch::nn::VarStore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Pass,
+    Fail,
+    Undecided,
+}
+
+impl Decision {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::Undecided => "undecided",
+        }
+    }
+}
+
+// Wald's sequential probability ratio test of H0: the candidate is `elo0` better than its
+// opponent against H1: it is `elo1` better, with game outcomes modelled as in `Ratings`
+#[derive(Debug, Clone)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub lower: f64, // H0 is accepted below this log likelihood ratio
+    pub upper: f64, // and H1 above this one
+    pub llr: f64,
+    pub wdl: [usize; 3], // the candidate's wins, draws and losses
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64, alpha: f64, beta: f64) -> Self {
+        Self {
+            elo0,
+            elo1,
+            lower: (beta / (1.0 - alpha)).ln(),
+            upper: ((1.0 - beta) / alpha).ln(),
+            llr: 0.0,
+            wdl: [0; 3],
+        }
+    }
+
+    // `reward` is the candidate's
+    pub fn add(&mut self, reward: f32, candidate_first: bool) {
+        // outcomes index like value targets, 0 the candidate lost
+        let outcome = if reward > 0.0 {
+            2
+        } else if reward < 0.0 {
+            0
+        } else {
+            1
+        };
+        // the model is from the first player's view
+        let (sign, first_outcome) = if candidate_first {
+            (1.0, outcome)
+        } else {
+            (-1.0, 2 - outcome)
+        };
+        self.llr += outcome_terms(sign * self.elo1, first_outcome).0
+            - outcome_terms(sign * self.elo0, first_outcome).0;
+        self.wdl[2 - outcome] += 1;
+    }
+
+    pub fn num_games(&self) -> usize {
+        self.wdl.iter().sum()
+    }
+
+    pub fn decision(&self) -> Decision {
+        if self.llr >= self.upper {
+            Decision::Pass
+        } else if self.llr <= self.lower {
+            Decision::Fail
+        } else {
+            Decision::Undecided
+        }
+    }
+}
+
+// Plays `candidate` against `best`, both named by their file in logs/models, until the SPRT of
+// `cfg` decides or `max_games` are played. Games come in pairs that share a random opening, one
+// with each color. Pairs are played on `num_workers` threads but counted in order, so the
+// openings are fixed by `seed`, though not the decision: root policy noise and random first play
+// urgency in `cfg.mcts_cfg` are unseeded. Every game counted is appended to gating.pgn and the
+// outcome to gating.csv.
+pub fn gate<G: Notation<N>, P: Policy<G, N> + NNPolicy<G, N>, const N: usize>(
+    cfg: &GatingConfig,
+    logs: &Path,
+    i_iter: usize,
+    candidate: &str,
+    best: &str,
+    seed: u64,
+) -> Result<Sprt, Box<dyn std::error::Error>> {
+    let mut sprt = Sprt::new(cfg.elo0, cfg.elo1, cfg.alpha, cfg.beta);
+    let mut records = Vec::new();
+    let num_pairs = cfg.max_games.div_ceil(2);
+    let is_done =
+        |sprt: &Sprt| sprt.decision() != Decision::Undecided || sprt.num_games() >= cfg.max_games;
+
+    let next_pair = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..cfg.num_workers.max(1).min(num_pairs) {
+            let sender = sender.clone();
+            let (next_pair, stop) = (&next_pair, &stop);
+            scope.spawn(move || {
+                let _guard = tch::no_grad_guard();
+                let mut policies = [
+                    load_policy::<G, P, N>(logs, candidate),
+                    load_policy::<G, P, N>(logs, best),
+                ];
+                while !stop.load(Ordering::SeqCst) {
+                    let i = next_pair.fetch_add(1, Ordering::SeqCst);
+                    if i >= num_pairs {
+                        break;
+                    }
+                    let names = [candidate, best];
+                    let games = [true, false].map(|candidate_first| {
+                        play_game(cfg, &mut policies, names, candidate_first, seed + i as u64)
+                    });
+                    sender.send((i, games)).unwrap();
+                }
+            });
+        }
+        drop(sender);
+
+        // pairs can finish out of order, hold them back until the ones before are counted
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (i, games) in receiver.iter() {
+            pending.insert(i, games);
+            while let Some(games) = pending.remove(&next) {
+                next += 1;
+                for (reward, candidate_first, record) in games {
+                    if !is_done(&sprt) {
+                        sprt.add(reward, candidate_first);
+                        records.push(record);
+                    }
+                }
+            }
+            if is_done(&sprt) {
+                stop.store(true, Ordering::SeqCst);
+                break;
+            }
+        }
+    });
+
+    let mut pgn = BufWriter::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(logs.join("gating.pgn"))?,
+    );
+    for mut record in records {
+        record.set_tag("Round", i_iter);
+        record.write(&mut pgn)?;
+    }
+    pgn.flush()?;
+
+    let csv = logs.join("gating.csv");
+    let write_header = !csv.exists();
+    let mut csv = OpenOptions::new().create(true).append(true).open(csv)?;
+    if write_header {
+        writeln!(
+            csv,
+            "iteration,candidate,best,wins,draws,losses,llr,lower,upper,decision"
+        )?;
+    }
+    writeln!(
+        csv,
+        "{},{},{},{},{},{},{:.4},{:.4},{:.4},{}",
+        i_iter,
+        candidate,
+        best,
+        sprt.wdl[0],
+        sprt.wdl[1],
+        sprt.wdl[2],
+        sprt.llr,
+        sprt.lower,
+        sprt.upper,
+        sprt.decision().name()
+    )?;
+    Ok(sprt)
+}
+
+fn load_policy<G: Game<N>, P: NNPolicy<G, N>, const N: usize>(logs: &Path, name: &str) -> P {
+    let mut vs = VarStore::new(tch::Device::Cpu);
+    let policy = P::new(&vs);
+    policy.check_shapes().unwrap();
+    vs.load(logs.join("models").join(name)).unwrap();
+    policy
+}
+
+// the candidate's reward, whether it moved first and the record of one game. `policies` are the
+// candidate's and the best's, the opening is the same for both colors of a seed
+fn play_game<G: Notation<N>, P: Policy<G, N>, const N: usize>(
+    cfg: &GatingConfig,
+    policies: &mut [P; 2],
+    names: [&str; 2],
+    candidate_first: bool,
+    seed: u64,
+) -> (f32, bool, GameRecord) {
+    let sides = if candidate_first { [0, 1] } else { [1, 0] };
+    let mut record = GameRecord::new(G::NAME, names[sides[0]], names[sides[1]]);
+    record.set_tag("Seed", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut game = G::new();
+    let first_player = game.player();
+    let mut num_turns = 0;
+    loop {
+        let action = if num_turns < cfg.random_actions_until {
+            let actions: Vec<G::Action> = game.iter_actions().collect();
+            actions[rng.gen_range(0..actions.len())]
+        } else {
+            let side = sides[(game.player() != first_player) as usize];
+            MCTS::exploit(
+                cfg.num_explores,
+                cfg.mcts_cfg,
+                &mut policies[side],
+                game.clone(),
+                cfg.action,
+            )
+        };
+        record.push(&game, &action);
+        num_turns += 1;
+        if game.step(&action) {
+            break;
+        }
+    }
+    let reward = game.reward(first_player);
+    let termination = game.result().unwrap().termination();
+    record.finish(reward, termination);
+    let candidate_player = if candidate_first {
+        first_player
+    } else {
+        first_player.next()
+    };
+    (game.reward(candidate_player), candidate_first, record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(sprt: &mut Sprt, wdl: [usize; 3]) {
+        for (outcome, &n) in wdl.iter().enumerate() {
+            for i in 0..n {
+                sprt.add(1.0 - outcome as f32, i % 2 == 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bounds() {
+        let sprt = Sprt::new(0.0, 10.0, 0.05, 0.05);
+        assert!((sprt.upper - (0.95f64 / 0.05).ln()).abs() < 1e-12);
+        assert!((sprt.lower + sprt.upper).abs() < 1e-12);
+        assert_eq!(sprt.decision(), Decision::Undecided);
+    }
+
+    #[test]
+    fn test_symmetric_draws_say_nothing() {
+        // with bounds either side of 0, a draw with each color is as likely under both
+        let mut sprt = Sprt::new(-10.0, 10.0, 0.05, 0.05);
+        sprt.add(0.0, true);
+        sprt.add(0.0, false);
+        assert!(sprt.llr.abs() < 1e-9);
+        assert_eq!(sprt.wdl, [0, 2, 0]);
+    }
+
+    #[test]
+    fn test_decisions() {
+        let mut strong = Sprt::new(0.0, 35.0, 0.05, 0.05);
+        run(&mut strong, [60, 30, 10]);
+        assert_eq!(strong.decision(), Decision::Pass);
+        assert_eq!(strong.wdl, [60, 30, 10]);
+
+        let mut weak = Sprt::new(0.0, 35.0, 0.05, 0.05);
+        run(&mut weak, [10, 30, 60]);
+        assert_eq!(weak.decision(), Decision::Fail);
+
+        let mut even = Sprt::new(0.0, 35.0, 0.05, 0.05);
+        run(&mut even, [5, 10, 5]);
+        assert_eq!(even.decision(), Decision::Undecided);
+        assert!(even.llr < 0.0);
+    }
+
+    #[test]
+    fn test_first_player_advantage() {
+        let mut white = Sprt::new(0.0, 35.0, 0.05, 0.05);
+        let mut black = white.clone();
+        white.add(1.0, true);
+        black.add(1.0, false);
+        // a win as the second player is more evidence, the first player has an advantage
+        assert!(black.llr > white.llr);
+        assert!(white.llr > 0.0);
+    }
+}
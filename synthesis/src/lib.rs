@@ -1,9 +1,18 @@
+mod alpha_beta;
 mod alpha_zero;
+mod chart;
 pub mod config;
 mod data;
 mod evaluator;
 pub mod game;
+mod gating;
 mod mcts;
+mod optim;
+mod parallel_mcts;
+mod pgn;
 pub mod policies;
 pub mod prelude;
+mod ratings;
+mod records;
+mod tournament;
 mod utils;
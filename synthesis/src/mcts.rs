@@ -1,11 +1,17 @@
-use crate::config::{ActionSelection, Exploration, Fpu, MCTSConfig, PolicyNoise};
+use crate::config::{ActionSelection, Exploration, MCTSConfig, PolicyNoise};
 use crate::game::{Game, Outcome};
-use crate::policies::Policy;
+use crate::policies::{BatchPolicy, Policy};
 use rand::{distributions::Distribution, thread_rng, Rng};
-use rand_distr::Dirichlet;
+use rand_distr::{Dirichlet, Gumbel};
 
 type NodeId = u32;
-type ActionId = u8;
+type ActionId = u16;
+
+enum Selection {
+    Solved(NodeId, Outcome), // backed up right away, nothing to evaluate
+    Leaf(NodeId),            // expanded and waiting on its evaluation
+    Collision,               // ran into a leaf that is already waiting
+}
 
 impl Into<usize> for Outcome {
     fn into(self) -> usize {
@@ -29,25 +35,32 @@ impl Into<[f32; 3]> for Outcome {
 struct Node<G: Game<N>, const N: usize> {
     parent: NodeId,            // 4 bytes
     first_child: NodeId,       // 4 bytes
-    num_children: u8,          // 1 byte
+    num_children: u16,         // 2 bytes
     game: G,                   // ? bytes
     solution: Option<Outcome>, // 1 byte
-    action: ActionId,          // 1 byte
+    action: ActionId,          // 2 bytes
     action_prob: f32,          // 4 bytes
     outcome_probs: [f32; 3],
-    num_visits: f32, // 4 bytes
+    num_visits: f32,     // 4 bytes
+    virtual_losses: f32, // 4 bytes, leaves below waiting on a batched eval
 }
 
 impl<G: Game<N>, const N: usize> Node<G, N> {
+    // a pending leaf counts as a visit the player to move here won, so the parent looks elsewhere
     fn q(&self) -> f32 {
-        (self.outcome_probs[2] - self.outcome_probs[0]) / self.num_visits
+        (self.outcome_probs[2] + self.virtual_losses - self.outcome_probs[0]) / self.visits()
+    }
+
+    #[inline]
+    fn visits(&self) -> f32 {
+        self.num_visits + self.virtual_losses
     }
 
     fn unvisited(
         parent: NodeId,
         game: G,
         solution: Option<Outcome>,
-        action: u8,
+        action: ActionId,
         action_prob: f32,
     ) -> Self {
         Self {
@@ -60,13 +73,10 @@ impl<G: Game<N>, const N: usize> Node<G, N> {
             action_prob,
             outcome_probs: [0.0; 3],
             num_visits: 0.0,
+            virtual_losses: 0.0,
         }
     }
 
-    fn action(&self) -> G::Action {
-        (self.action as usize).into()
-    }
-
     #[inline]
     fn is_unvisited(&self) -> bool {
         self.num_children == 0 && self.solution.is_none()
@@ -88,7 +98,7 @@ impl<G: Game<N>, const N: usize> Node<G, N> {
     }
 
     #[inline]
-    fn mark_visited(&mut self, first_child: NodeId, num_children: u8) {
+    fn mark_visited(&mut self, first_child: NodeId, num_children: u16) {
         self.first_child = first_child;
         self.num_children = num_children;
     }
@@ -105,6 +115,8 @@ pub struct MCTS<'a, G: Game<N>, P: Policy<G, N>, const N: usize> {
     nodes: Vec<Node<G, N>>,
     policy: &'a mut P,
     cfg: MCTSConfig,
+    gumbel: Vec<f32>, // noise of each root child, sampled by Sequential Halving
+    considered: Vec<NodeId>, // root children Sequential Halving kept, best first
 }
 
 impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
@@ -121,22 +133,91 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     }
 
     pub fn with_capacity(capacity: usize, cfg: MCTSConfig, policy: &'a mut P, game: G) -> Self {
-        let mut nodes = Vec::with_capacity(capacity);
-        nodes.push(Node::unvisited(0, game, None, 0, 0.0));
         let mut mcts = Self {
             root: 0,
             offset: 0,
-            nodes,
+            nodes: Vec::with_capacity(capacity),
             policy,
             cfg,
+            gumbel: Vec::new(),
+            considered: Vec::new(),
         };
-        let (node_id, outcome_probs, any_solved) = mcts.visit(mcts.root);
-        mcts.backprop(node_id, outcome_probs, any_solved);
-        mcts.add_root_noise();
+        mcts.reset(game);
         mcts
     }
 
+    // throws the whole tree away and starts over from `game`
+    pub fn reset(&mut self, game: G) {
+        self.nodes.clear();
+        self.nodes.push(Node::unvisited(0, game, None, 0, 0.0));
+        self.root = 0;
+        self.offset = 0;
+        self.gumbel.clear();
+        self.considered.clear();
+        let (node_id, outcome_probs, any_solved) = self.visit(self.root);
+        self.backprop(node_id, outcome_probs, any_solved);
+        self.add_root_noise();
+    }
+
+    // Makes the child reached by `action` the root, keeping the statistics of its subtree and
+    // dropping everything else. Call it for every action played, including the opponent's reply.
+    // Starts over when the child was never visited or is already solved.
+    pub fn advance(&mut self, action: &G::Action) {
+        let action: usize = (*action).into();
+        let root = self.node(self.root);
+        let child_id = (root.first_child..root.last_child())
+            .find(|&child_id| self.node(child_id).action as usize == action)
+            .expect("advanced by an action that isn't legal at the root");
+        let child = self.node(child_id);
+        if child.num_children == 0 || child.solution.is_some() {
+            let game = child.game.clone();
+            self.reset(game);
+            return;
+        }
+
+        // copy the subtree breadth first so every node's children stay contiguous
+        let offset = self.offset;
+        let mut old: Vec<Option<Node<G, N>>> = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut take = |node_id: NodeId| old[(node_id - offset) as usize].take().unwrap();
+        let mut root = take(child_id);
+        root.parent = 0;
+        self.nodes.push(root);
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &self.nodes[i];
+            if node.num_children > 0 {
+                let (first_child, last_child) = (node.first_child, node.last_child());
+                self.nodes[i].first_child = self.nodes.len() as NodeId;
+                for child_id in first_child..last_child {
+                    let mut child = take(child_id);
+                    child.parent = i as NodeId;
+                    self.nodes.push(child);
+                }
+            }
+            i += 1;
+        }
+        self.root = 0;
+        self.offset = 0;
+        self.gumbel.clear();
+        self.considered.clear();
+        self.add_root_noise();
+    }
+
     pub fn explore_n(&mut self, n: usize) {
+        if let Exploration::Gumbel { .. } = self.cfg.exploration {
+            self.sequential_halving(n, |mcts, schedule| {
+                for &child_id in schedule {
+                    if mcts.node(mcts.root).solution.is_some() {
+                        break;
+                    }
+                    mcts.explore_from(child_id);
+                }
+            });
+            return;
+        }
         for _ in 0..n {
             // NOTE this is important for value extraction because if root is solved then children might not have any visits
             if self.node(self.root).solution.is_some() {
@@ -147,6 +228,100 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     }
 }
 
+impl<'a, G: Game<N>, P: BatchPolicy<G, N>, const N: usize> MCTS<'a, G, P, N> {
+    // Like `explore_n`, but selects up to `batch_size` leaves and evaluates them with one
+    // `eval_batch` call. Waiting leaves put a virtual loss on their path so the next selections
+    // spread out, and a selection that runs into one cuts the batch short.
+    // A batch size of 1 explores exactly like `explore_n`, as does 0.
+    pub fn explore_n_batched(&mut self, n: usize, batch_size: usize) {
+        let batch_size = batch_size.max(1);
+        if let Exploration::Gumbel { .. } = self.cfg.exploration {
+            self.sequential_halving(n, |mcts, schedule| {
+                mcts.explore_batched(schedule, batch_size)
+            });
+        } else {
+            let starts = vec![self.root; n];
+            self.explore_batched(&starts, batch_size);
+        }
+    }
+
+    // one explore from each of `starts`, in order
+    fn explore_batched(&mut self, starts: &[NodeId], batch_size: usize) {
+        let n = starts.len();
+        let mut leaves = Vec::with_capacity(batch_size);
+        let mut games = Vec::with_capacity(batch_size);
+        let mut num_explores = 0;
+        while num_explores < n {
+            // NOTE see `explore_n`, a solved root stops exploring
+            if self.node(self.root).solution.is_some() {
+                break;
+            }
+            while leaves.len() < batch_size
+                && num_explores < n
+                && self.node(self.root).solution.is_none()
+            {
+                match self.select_leaf(starts[num_explores]) {
+                    Selection::Solved(node_id, outcome) => {
+                        self.backprop(node_id, outcome.into(), true);
+                    }
+                    Selection::Leaf(node_id) => {
+                        self.add_virtual_loss(node_id, 1.0);
+                        games.push(self.node(node_id).game.clone());
+                        leaves.push(node_id);
+                    }
+                    Selection::Collision => break,
+                }
+                num_explores += 1;
+            }
+            if leaves.is_empty() {
+                continue;
+            }
+
+            let outputs = self.policy.eval_batch(&games);
+            for (node_id, (logits, outcome_probs)) in leaves.drain(..).zip(outputs) {
+                self.add_virtual_loss(node_id, -1.0);
+                let any_solved = self.set_action_probs(node_id, &logits);
+                self.backprop(node_id, outcome_probs, any_solved);
+            }
+            games.clear();
+        }
+    }
+
+    fn select_leaf(&mut self, start_node_id: NodeId) -> Selection {
+        let mut node_id = start_node_id;
+        loop {
+            let node = self.node(node_id);
+            if let Some(outcome) = node.solution {
+                return Selection::Solved(node_id, outcome);
+            } else if node.num_visits == 0.0 && node.virtual_losses > 0.0 {
+                return Selection::Collision;
+            } else if node.is_unvisited() {
+                let (first_child, num_children) = self.expand(node_id);
+                if self.cfg.auto_extend && num_children == 1 {
+                    node_id = first_child;
+                } else {
+                    return Selection::Leaf(node_id);
+                }
+            } else {
+                node_id = self.select_best_child(node);
+            }
+        }
+    }
+
+    fn add_virtual_loss(&mut self, leaf_node_id: NodeId, virtual_loss: f32) {
+        let mut node_id = leaf_node_id;
+        loop {
+            let root = self.root;
+            let node = self.mut_node(node_id);
+            node.virtual_losses += virtual_loss;
+            if node_id == root {
+                break;
+            }
+            node_id = node.parent;
+        }
+    }
+}
+
 impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     fn next_node_id(&self) -> NodeId {
         self.nodes.len() as NodeId + self.offset
@@ -196,6 +371,15 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
                     }
                 }
             }
+        } else if let Exploration::Gumbel {
+            c_visit, c_scale, ..
+        } = self.cfg.exploration
+        {
+            let improved = self.improved_policy(root, c_visit, c_scale);
+            for (child, v) in self.children_of(root).iter().zip(improved) {
+                search_policy[child.action as usize] = v;
+                total += v;
+            }
         } else {
             // assert!(root.num_visits > 1.0);
             for child in self.children_of(root) {
@@ -272,22 +456,42 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
 impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     pub fn best_action(&self, action_selection: ActionSelection) -> G::Action {
         let root = self.node(self.root);
+        let gumbel_scores = match (action_selection, self.cfg.exploration) {
+            (
+                ActionSelection::Gumbel,
+                Exploration::Gumbel {
+                    c_visit, c_scale, ..
+                },
+            ) => self.gumbel_scores(c_visit, c_scale),
+            (ActionSelection::Gumbel, _) => {
+                panic!("ActionSelection::Gumbel needs Exploration::Gumbel")
+            }
+            _ => Vec::new(),
+        };
 
         let mut best_action = None;
         let mut best_value = None;
-        for child in self.children_of(root) {
+        for (i, child) in self.children_of(root).iter().enumerate() {
+            let child_id = root.first_child + i as NodeId;
             let value = match child.solution {
                 Some(Outcome::Win(turns)) => Some((0.0, turns as f32)),
                 None => match action_selection {
                     ActionSelection::Q => Some((1.0, -child.q())),
                     ActionSelection::NumVisits => Some((1.0, child.num_visits)),
+                    // only the children Sequential Halving kept, all of them before any search
+                    ActionSelection::Gumbel
+                        if self.considered.is_empty() || self.considered.contains(&child_id) =>
+                    {
+                        Some((1.0, gumbel_scores[i]))
+                    }
+                    ActionSelection::Gumbel => Some((1.0, f32::NEG_INFINITY)),
                 },
                 Some(Outcome::Draw(turns)) => Some((2.0, -(turns as f32))),
                 Some(Outcome::Lose(turns)) => Some((3.0, -(turns as f32))),
             };
             if value > best_value {
                 best_value = value;
-                best_action = Some(child.action());
+                best_action = Some(root.game.decode_action(child.action as usize));
             }
         }
         best_action.unwrap()
@@ -295,7 +499,7 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
 
     pub fn solution(&self, action: &G::Action) -> Option<Outcome> {
         let action: usize = (*action).into();
-        let action = action as u8;
+        let action = action as ActionId;
         let root = self.node(self.root);
         for child in self.children_of(root) {
             if child.action == action {
@@ -308,7 +512,11 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
 
 impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     fn explore(&mut self) {
-        let mut node_id = self.root;
+        self.explore_from(self.root);
+    }
+
+    fn explore_from(&mut self, start_node_id: NodeId) {
+        let mut node_id = start_node_id;
         loop {
             let node = self.node(node_id);
             if let Some(outcome) = node.solution {
@@ -325,6 +533,12 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     }
 
     fn select_best_child(&self, parent: &Node<G, N>) -> NodeId {
+        if let Exploration::Gumbel {
+            c_visit, c_scale, ..
+        } = self.cfg.exploration
+        {
+            return self.select_improved_child(parent, c_visit, c_scale);
+        }
         let mut best_child_id = None;
         let mut best_value = None;
         for child_id in parent.first_child..parent.last_child() {
@@ -348,11 +562,7 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
                 f32::NEG_INFINITY
             }
         } else if child.num_children == 0 {
-            match self.cfg.fpu {
-                Fpu::Const(value) => value,
-                Fpu::ParentQ => parent.q(),
-                Fpu::Func(fpu_fn) => (fpu_fn)(),
-            }
+            self.cfg.fpu.value(parent.q())
         } else {
             -child.q()
         }
@@ -361,69 +571,82 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     fn explore_value(&self, parent: &Node<G, N>, child: &Node<G, N>) -> f32 {
         match self.cfg.exploration {
             Exploration::Uct { c } => {
-                let visits = (c * parent.num_visits.ln()).sqrt();
-                visits / child.num_visits.sqrt()
+                let visits = (c * parent.visits().ln()).sqrt();
+                visits / child.visits().sqrt()
             }
             Exploration::PolynomialUct { c } => {
-                let visits = parent.num_visits.sqrt();
-                c * child.action_prob * visits / (1.0 + child.num_visits)
+                let visits = parent.visits().sqrt();
+                c * child.action_prob * visits / (1.0 + child.visits())
             }
+            Exploration::Gumbel { .. } => unreachable!("Gumbel selects with the improved policy"),
         }
     }
 
     fn visit(&mut self, node_id: NodeId) -> (NodeId, [f32; 3], bool) {
-        let first_child = self.next_node_id();
         let node = self.node(node_id);
         if let Some(outcome) = node.solution {
             return (node_id, outcome.into(), true);
         }
 
-        let game = node.game.clone();
+        let (first_child, num_children) = self.expand(node_id);
+        if self.cfg.auto_extend && num_children == 1 {
+            return self.visit(first_child);
+        } else {
+            let game = self.node(node_id).game.clone();
+            let (logits, outcome_probs) = self.policy.eval(&game);
+            let any_solved = self.set_action_probs(node_id, &logits);
+            (node_id, outcome_probs, any_solved)
+        }
+    }
+
+    // adds the children of an unvisited node, their action probs are set once it is evaluated
+    fn expand(&mut self, node_id: NodeId) -> (NodeId, u16) {
+        let first_child = self.next_node_id();
+        let game = self.node(node_id).game.clone();
         let mut num_children = 0;
-        let mut any_solved = false;
         for action in game.iter_actions() {
             let mut child_game = game.clone();
             let is_over = child_game.step(&action);
             let solution = if is_over {
-                any_solved = true;
                 Some(child_game.reward(child_game.player()).into())
             } else {
                 None
             };
             let action: usize = action.into();
-            let child = Node::unvisited(node_id, child_game, solution, action as u8, 1.0);
+            let child = Node::unvisited(node_id, child_game, solution, action as ActionId, 1.0);
             self.nodes.push(child);
             num_children += 1;
         }
 
-        let node = self.mut_node(node_id);
-        node.mark_visited(first_child, num_children);
+        self.mut_node(node_id)
+            .mark_visited(first_child, num_children);
+        (first_child, num_children)
+    }
+
+    // softmax of the policy's logits over the node's children, returns whether any child is solved
+    fn set_action_probs(&mut self, node_id: NodeId, logits: &[f32; N]) -> bool {
+        let node = self.node(node_id);
         let first_child = node.first_child;
         let last_child = node.last_child();
 
-        if self.cfg.auto_extend && num_children == 1 {
-            return self.visit(first_child);
-        } else {
-            let (logits, outcome_probs) = self.policy.eval(&game);
-
-            // stable softmax
-            let mut max_logit = f32::NEG_INFINITY;
-            for child in self.mut_nodes(first_child, last_child) {
-                let logit = logits[child.action as usize];
-                max_logit = max_logit.max(logit);
-                child.action_prob = logit;
-            }
-            let mut total = 0.0;
-            for child in self.mut_nodes(first_child, last_child) {
-                child.action_prob = (child.action_prob - max_logit).exp();
-                total += child.action_prob;
-            }
-            for child in self.mut_nodes(first_child, last_child) {
-                child.action_prob /= total;
-            }
-
-            (node_id, outcome_probs, any_solved)
+        // stable softmax
+        let mut max_logit = f32::NEG_INFINITY;
+        for child in self.mut_nodes(first_child, last_child) {
+            let logit = logits[child.action as usize];
+            max_logit = max_logit.max(logit);
+            child.action_prob = logit;
+        }
+        let mut total = 0.0;
+        for child in self.mut_nodes(first_child, last_child) {
+            child.action_prob = (child.action_prob - max_logit).exp();
+            total += child.action_prob;
         }
+        let mut any_solved = false;
+        for child in self.mut_nodes(first_child, last_child) {
+            child.action_prob /= total;
+            any_solved |= child.solution.is_some();
+        }
+        any_solved
     }
 
     fn backprop(&mut self, leaf_node_id: NodeId, mut outcome_probs: [f32; 3], mut solved: bool) {
@@ -488,12 +711,162 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
     }
 }
 
+impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> MCTS<'a, G, P, N> {
+    // Gumbel-Top-k picks `num_considered` root children, then each phase spreads its share of the
+    // `n` explores evenly over the ones left and keeps the better half. The last two share
+    // whatever is left of the budget.
+    fn sequential_halving(
+        &mut self,
+        n: usize,
+        mut explore_phase: impl FnMut(&mut Self, &[NodeId]),
+    ) {
+        let (num_considered, c_visit, c_scale) = match self.cfg.exploration {
+            Exploration::Gumbel {
+                num_considered,
+                c_visit,
+                c_scale,
+            } => (num_considered, c_visit, c_scale),
+            _ => unreachable!(),
+        };
+        let root = self.node(self.root);
+        let (first_child, last_child) = (root.first_child, root.last_child());
+        let dist = Gumbel::new(0.0, 1.0).unwrap();
+        let mut rng = thread_rng();
+        self.gumbel = (first_child..last_child)
+            .map(|_| dist.sample(&mut rng))
+            .collect();
+        let root = self.node(self.root);
+        let scores: Vec<f32> = self
+            .gumbel
+            .iter()
+            .zip(self.children_of(root))
+            .map(|(gumbel, child)| gumbel + child.action_prob.ln())
+            .collect();
+        let mut considered: Vec<NodeId> = (first_child..last_child).collect();
+        sort_by_score(&mut considered, first_child, &scores);
+        considered.truncate(num_considered.max(1));
+
+        let num_phases = (considered.len() as f32).log2().ceil().max(1.0) as usize;
+        let mut budget = n;
+        let mut schedule = Vec::with_capacity(n);
+        while budget > 0 && !considered.is_empty() && self.node(self.root).solution.is_none() {
+            let visits = if considered.len() <= 2 {
+                budget.div_ceil(considered.len())
+            } else {
+                (n / (num_phases * considered.len())).max(1)
+            };
+            schedule.clear();
+            for _ in 0..visits {
+                schedule.extend_from_slice(&considered);
+            }
+            schedule.truncate(budget);
+            budget -= schedule.len();
+            explore_phase(self, &schedule);
+
+            let scores = self.gumbel_scores(c_visit, c_scale);
+            sort_by_score(&mut considered, first_child, &scores);
+            considered.truncate(considered.len().div_ceil(2));
+        }
+        self.considered = considered;
+    }
+
+    // gumbel + logits + sigma(completed q) of every root child, the noise is 0 before any search
+    fn gumbel_scores(&self, c_visit: f32, c_scale: f32) -> Vec<f32> {
+        let mut scores = self.improved_logits(self.node(self.root), c_visit, c_scale);
+        for (score, gumbel) in scores.iter_mut().zip(self.gumbel.iter()) {
+            *score += gumbel;
+        }
+        scores
+    }
+
+    // Q of every child from the parent's view rescaled to [0, 1]. Unvisited children are completed
+    // with the parent's own Q, which mixes its value with what the search found below it.
+    fn completed_q(&self, parent: &Node<G, N>) -> Vec<f32> {
+        self.children_of(parent)
+            .iter()
+            .map(|child| {
+                let q = if let Some(outcome) = child.solution {
+                    outcome.reversed().value()
+                } else if child.visits() > 0.0 {
+                    -child.q()
+                } else {
+                    parent.q()
+                };
+                (q + 1.0) / 2.0
+            })
+            .collect()
+    }
+
+    fn improved_logits(&self, parent: &Node<G, N>, c_visit: f32, c_scale: f32) -> Vec<f32> {
+        let children = self.children_of(parent);
+        let max_visits = children
+            .iter()
+            .map(|child| child.visits())
+            .fold(0.0, f32::max);
+        let sigma = (c_visit + max_visits) * c_scale;
+        children
+            .iter()
+            .zip(self.completed_q(parent))
+            .map(|(child, q)| child.action_prob.ln() + sigma * q)
+            .collect()
+    }
+
+    // softmax(logits + sigma(completed q)), the policy target of Gumbel AlphaZero
+    fn improved_policy(&self, parent: &Node<G, N>, c_visit: f32, c_scale: f32) -> Vec<f32> {
+        let mut policy = self.improved_logits(parent, c_visit, c_scale);
+        let max_logit = policy.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let mut total = 0.0;
+        for p in policy.iter_mut() {
+            *p = (*p - max_logit).exp();
+            total += *p;
+        }
+        for p in policy.iter_mut() {
+            *p /= total;
+        }
+        policy
+    }
+
+    // deterministic selection below the root: the child whose share of visits lags its improved
+    // policy the most
+    fn select_improved_child(&self, parent: &Node<G, N>, c_visit: f32, c_scale: f32) -> NodeId {
+        let policy = self.improved_policy(parent, c_visit, c_scale);
+        let children = self.children_of(parent);
+        let total_visits: f32 = children.iter().map(|child| child.visits()).sum();
+        let mut best_child_id = None;
+        let mut best_value = None;
+        for (i, (child, p)) in children.iter().zip(policy).enumerate() {
+            let value = if child.solution.is_some() && !self.cfg.select_solved_nodes {
+                f32::NEG_INFINITY
+            } else {
+                p - child.visits() / (1.0 + total_visits)
+            };
+            if Some(value) > best_value {
+                best_child_id = Some(parent.first_child + i as NodeId);
+                best_value = Some(value);
+            }
+        }
+        best_child_id.unwrap()
+    }
+}
+
+// best first, `scores` are indexed from the first child
+fn sort_by_score(child_ids: &mut [NodeId], first_child: NodeId, scores: &[f32]) {
+    child_ids.sort_by(|&a, &b| {
+        let (a, b) = (
+            scores[(a - first_child) as usize],
+            scores[(b - first_child) as usize],
+        );
+        b.partial_cmp(&a).unwrap()
+    });
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use rand::prelude::{SeedableRng, StdRng};
 
     use super::*;
-    use crate::game::HasTurnOrder;
+    use crate::config::Fpu;
+    use crate::game::{HasTurnOrder, Notation, Undo};
     use crate::policies::RolloutPolicy;
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq, std::hash::Hash, PartialOrd, Ord)]
@@ -516,7 +889,7 @@ mod tests {
     }
 
     #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-    struct Action {
+    pub(crate) struct Action {
         row: usize,
         col: usize,
     }
@@ -536,13 +909,13 @@ mod tests {
     }
 
     #[derive(Debug, PartialEq, Eq, std::hash::Hash, Clone)]
-    struct TicTacToe {
+    pub(crate) struct TicTacToe {
         board: [[Option<PlayerId>; 3]; 3],
         player: PlayerId,
         turn: usize,
     }
 
-    struct ActionIterator {
+    pub(crate) struct ActionIterator {
         game: TicTacToe,
         i: usize,
     }
@@ -639,6 +1012,10 @@ mod tests {
                 i: 0,
             }
         }
+
+        fn decode_action(&self, action_id: usize) -> Self::Action {
+            action_id.into()
+        }
         fn step(&mut self, action: &Self::Action) -> bool {
             assert!(action.row < 3);
             assert!(action.col < 3);
@@ -685,6 +1062,16 @@ mod tests {
         }
     }
 
+    impl Notation<9> for TicTacToe {}
+
+    impl Undo<9> for TicTacToe {
+        fn undo(&mut self, action: &Self::Action) {
+            self.board[action.row][action.col] = None;
+            self.player = self.player.prev();
+            self.turn -= 1;
+        }
+    }
+
     // https://en.wikipedia.org/wiki/Tic-tac-toe
 
     #[test]
@@ -778,7 +1165,7 @@ mod tests {
         // assert_eq!(mcts.solution(&8.into()), Some(Outcome::Win));
         // assert_eq!(mcts.target_q(), -1.0);
         // assert_eq!(mcts.best_action(ActionSelection::Q), 1.into());
-        assert_eq!(mcts.nodes.len(), 69);
+        assert_eq!(mcts.nodes.len(), 72);
     }
 
     #[test]
@@ -866,4 +1253,207 @@ mod tests {
         }
         assert!((total - 1.0).abs() < 1e-6);
     }
+
+    #[test]
+    fn test_advance() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut game = TicTacToe::new();
+        let mut mcts = MCTS::with_capacity(
+            1601,
+            MCTSConfig {
+                exploration: Exploration::PolynomialUct { c: 2.0 },
+                solve: true,
+                correct_values_on_solve: true,
+                fpu: Fpu::Const(f32::INFINITY),
+                select_solved_nodes: true,
+                auto_extend: false,
+                root_policy_noise: PolicyNoise::None,
+            },
+            &mut policy,
+            game.clone(),
+        );
+
+        // an unvisited child starts a new tree
+        let action = Action { row: 1, col: 1 };
+        mcts.advance(&action);
+        game.step(&action);
+        assert_eq!(mcts.node(mcts.root).game, game);
+        assert_eq!(mcts.nodes.len(), 1 + 8);
+
+        for _ in 0..2 {
+            // the most visited unsolved child, solved ones start over
+            mcts.explore_n(100);
+            let root = mcts.node(mcts.root);
+            let child_id = (root.first_child..root.last_child())
+                .filter(|&child_id| mcts.node(child_id).solution.is_none())
+                .max_by(|&a, &b| {
+                    let (a, b) = (mcts.node(a).num_visits, mcts.node(b).num_visits);
+                    a.partial_cmp(&b).unwrap()
+                })
+                .unwrap();
+            let child = mcts.node(child_id);
+            let action: Action = (child.action as usize).into();
+            assert!(child.num_children > 0);
+            let (num_visits, outcome_probs) = (child.num_visits, child.outcome_probs);
+            let mut subtree_size = 0;
+            let mut stack = vec![child_id];
+            while let Some(node_id) = stack.pop() {
+                subtree_size += 1;
+                let node = mcts.node(node_id);
+                stack.extend(node.first_child..node.last_child());
+            }
+
+            // the child's subtree is all that is left, with its statistics and links intact
+            mcts.advance(&action);
+            game.step(&action);
+            let root = mcts.node(mcts.root);
+            assert_eq!(root.game, game);
+            assert_eq!(root.num_visits, num_visits);
+            assert_eq!(root.outcome_probs, outcome_probs);
+            assert_eq!(mcts.nodes.len(), subtree_size);
+            for node_id in 0..mcts.nodes.len() as NodeId {
+                let node = mcts.node(node_id);
+                for child_id in node.first_child..node.last_child() {
+                    let child = mcts.node(child_id);
+                    assert_eq!(child.parent, node_id);
+                    let mut child_game = node.game.clone();
+                    child_game.step(&(child.action as usize).into());
+                    assert_eq!(child.game, child_game);
+                }
+            }
+        }
+
+        // the reused tree keeps searching
+        mcts.explore_n(800);
+        let mut search_policy = [0.0; 9];
+        mcts.target_policy(&mut search_policy);
+        assert!((search_policy.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_batch_of_one_explores_like_serial() {
+        let cfg = MCTSConfig {
+            exploration: Exploration::PolynomialUct { c: 2.0 },
+            solve: true,
+            correct_values_on_solve: true,
+            fpu: Fpu::Const(f32::INFINITY),
+            select_solved_nodes: true,
+            auto_extend: true,
+            root_policy_noise: PolicyNoise::None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut serial = MCTS::with_capacity(1601, cfg, &mut policy, TicTacToe::new());
+        serial.explore_n(800);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut batched = MCTS::with_capacity(1601, cfg, &mut policy, TicTacToe::new());
+        batched.explore_n_batched(800, 1);
+
+        assert_eq!(batched.nodes.len(), serial.nodes.len());
+        for (a, b) in batched.nodes.iter().zip(serial.nodes.iter()) {
+            assert_eq!(a.num_visits, b.num_visits);
+            assert_eq!(a.outcome_probs, b.outcome_probs);
+            assert_eq!(a.solution, b.solution);
+        }
+    }
+
+    #[test]
+    fn test_batched_solve_win() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut game = TicTacToe::new();
+        game.step(&Action { row: 0, col: 0 });
+        game.step(&Action { row: 0, col: 2 });
+        let mut mcts = MCTS::with_capacity(
+            1601,
+            MCTSConfig {
+                exploration: Exploration::PolynomialUct { c: 2.0 },
+                solve: true,
+                correct_values_on_solve: true,
+                fpu: Fpu::Const(f32::INFINITY),
+                select_solved_nodes: true,
+                auto_extend: true,
+                root_policy_noise: PolicyNoise::None,
+            },
+            &mut policy,
+            game.clone(),
+        );
+        mcts.explore_n_batched(100_000, 8);
+        assert_eq!(mcts.node(mcts.root).solution.map(|o| o.value()), Some(1.0));
+        let action = mcts.best_action(ActionSelection::Q);
+        assert!(matches!(mcts.solution(&action), Some(Outcome::Lose(_))));
+        // every pending leaf was evaluated and took its virtual loss back
+        assert!(mcts.nodes.iter().all(|node| node.virtual_losses == 0.0));
+    }
+
+    fn gumbel_cfg() -> MCTSConfig {
+        MCTSConfig {
+            exploration: Exploration::Gumbel {
+                num_considered: 4,
+                c_visit: 50.0,
+                c_scale: 0.1,
+            },
+            solve: true,
+            correct_values_on_solve: true,
+            fpu: Fpu::Const(f32::INFINITY),
+            select_solved_nodes: true,
+            auto_extend: false,
+            root_policy_noise: PolicyNoise::None,
+        }
+    }
+
+    #[test]
+    fn test_gumbel_sequential_halving() {
+        for batch_size in [None, Some(4)] {
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut policy = RolloutPolicy { rng: &mut rng };
+            let mut mcts = MCTS::with_capacity(1601, gumbel_cfg(), &mut policy, TicTacToe::new());
+            match batch_size {
+                None => mcts.explore_n(32),
+                Some(batch_size) => mcts.explore_n_batched(32, batch_size),
+            }
+
+            // 4 considered get 4 explores each, the better 2 then split the other 16
+            let root = mcts.node(mcts.root);
+            assert_eq!(root.num_visits, 33.0);
+            let mut visits: Vec<f32> = mcts
+                .children_of(root)
+                .iter()
+                .map(|child| child.num_visits)
+                .filter(|&v| v > 0.0)
+                .collect();
+            visits.sort_by(|a, b| b.partial_cmp(a).unwrap());
+            assert_eq!(visits, [12.0, 12.0, 4.0, 4.0]);
+            assert_eq!(mcts.considered.len(), 1);
+            let winner = mcts.node(mcts.considered[0]);
+            assert_eq!(winner.num_visits, 12.0);
+            let action = mcts.best_action(ActionSelection::Gumbel);
+            assert_eq!(action, (winner.action as usize).into());
+
+            // completed Q gives every legal action some of the improved policy
+            let mut search_policy = [0.0; 9];
+            mcts.target_policy(&mut search_policy);
+            assert!((search_policy.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+            assert!(search_policy.iter().all(|&p| p > 0.0));
+        }
+    }
+
+    #[test]
+    fn test_gumbel_solve_win() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut game = TicTacToe::new();
+        game.step(&Action { row: 0, col: 0 });
+        game.step(&Action { row: 0, col: 2 });
+        let mut mcts = MCTS::with_capacity(1601, gumbel_cfg(), &mut policy, game.clone());
+        while mcts.node(mcts.root).solution.is_none() {
+            mcts.explore_n(64);
+        }
+        assert_eq!(mcts.target_q(), [0.0, 0.0, 1.0]);
+        let action = mcts.best_action(ActionSelection::Gumbel);
+        assert!(matches!(mcts.solution(&action), Some(Outcome::Lose(_))));
+    }
 }
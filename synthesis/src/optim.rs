@@ -0,0 +1,201 @@
+use crate::data::tensor;
+use std::collections::HashMap;
+use tch::{nn::VarStore, Kind, Tensor};
+
+// Adam with L2 weight decay, the same update as torch.optim.Adam. The optimizers in tch keep
+// their moments on the C++ side where they can't be saved, these are plain tensors so a
+// checkpointed run resumes without resetting them
+pub struct Adam {
+    lr: f64,
+    weight_decay: f64,
+    beta1: f64,
+    beta2: f64,
+    eps: f64,
+    step: i64,
+    params: Vec<(String, Tensor)>,
+    exp_avg: Vec<Tensor>,
+    exp_avg_sq: Vec<Tensor>,
+}
+
+impl Adam {
+    pub fn new(vs: &VarStore, lr: f64) -> Self {
+        let mut params: Vec<(String, Tensor)> = vs
+            .variables()
+            .into_iter()
+            .filter(|(_name, p)| p.requires_grad())
+            .collect();
+        params.sort_by(|a, b| a.0.cmp(&b.0));
+        let exp_avg = params.iter().map(|(_name, p)| p.zeros_like()).collect();
+        let exp_avg_sq = params.iter().map(|(_name, p)| p.zeros_like()).collect();
+        Self {
+            lr,
+            weight_decay: 0.0,
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            step: 0,
+            params,
+            exp_avg,
+            exp_avg_sq,
+        }
+    }
+
+    pub fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    pub fn set_weight_decay(&mut self, weight_decay: f64) {
+        self.weight_decay = weight_decay;
+    }
+
+    pub fn backward_step(&mut self, loss: &Tensor) {
+        for (_name, p) in self.params.iter_mut() {
+            p.zero_grad();
+        }
+        loss.backward();
+
+        self.step += 1;
+        let (lr, weight_decay, beta1, beta2, eps) =
+            (self.lr, self.weight_decay, self.beta1, self.beta2, self.eps);
+        let bias_correction1 = 1.0 - beta1.powi(self.step as i32);
+        let bias_correction2 = 1.0 - beta2.powi(self.step as i32);
+
+        let _guard = tch::no_grad_guard();
+        for (((_name, p), m), v) in self
+            .params
+            .iter_mut()
+            .zip(self.exp_avg.iter_mut())
+            .zip(self.exp_avg_sq.iter_mut())
+        {
+            let mut grad = p.grad();
+            if !grad.defined() {
+                continue;
+            }
+            if weight_decay != 0.0 {
+                grad = grad + &*p * weight_decay;
+            }
+            m.copy_(&(&*m * beta1 + &grad * (1.0 - beta1)));
+            v.copy_(&(&*v * beta2 + &grad * &grad * (1.0 - beta2)));
+            let denom = (&*v / bias_correction2).sqrt() + eps;
+            p.copy_(&(&*p - (&*m / &denom) * (lr / bias_correction1)));
+        }
+    }
+
+    pub fn named_tensors(&self) -> Vec<(String, Tensor)> {
+        let mut named = Vec::with_capacity(2 * self.params.len() + 1);
+        named.push((
+            String::from("adam.step"),
+            tensor(&[self.step], &[1], Kind::Int64),
+        ));
+        for ((name, _p), (m, v)) in self
+            .params
+            .iter()
+            .zip(self.exp_avg.iter().zip(self.exp_avg_sq.iter()))
+        {
+            named.push((format!("adam.exp_avg.{}", name), m.shallow_clone()));
+            named.push((format!("adam.exp_avg_sq.{}", name), v.shallow_clone()));
+        }
+        named
+    }
+
+    pub fn load_named_tensors(
+        &mut self,
+        named: &HashMap<String, Tensor>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let get = |key: String| {
+            named
+                .get(&key)
+                .ok_or_else(|| format!("checkpoint is missing {}", key))
+        };
+        self.step = get(String::from("adam.step"))?.int64_value(&[0]);
+        let _guard = tch::no_grad_guard();
+        for ((name, _p), (m, v)) in self
+            .params
+            .iter()
+            .zip(self.exp_avg.iter_mut().zip(self.exp_avg_sq.iter_mut()))
+        {
+            m.copy_(get(format!("adam.exp_avg.{}", name))?);
+            v.copy_(get(format!("adam.exp_avg_sq.{}", name))?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X: [f64; 3] = [0.5, -3.0, 0.0];
+
+    // an optimizer of one parameter `w`, the loss is w.X so the gradient is X
+    fn setup(w: &[f64; 3]) -> (VarStore, Adam) {
+        let vs = VarStore::new(tch::Device::Cpu);
+        vs.root().var_copy("w", &tensor(w, &[3], Kind::Double));
+        let opt = Adam::new(&vs, 0.01);
+        (vs, opt)
+    }
+
+    fn step(vs: &VarStore, opt: &mut Adam) {
+        let variables = vs.variables();
+        let w = &variables["w"];
+        opt.backward_step(&(w * tensor(&X, &[3], Kind::Double)).sum(Kind::Double));
+    }
+
+    fn values(vs: &VarStore) -> Vec<f64> {
+        let variables = vs.variables();
+        let w = &variables["w"];
+        (0..3).map(|i| w.double_value(&[i])).collect()
+    }
+
+    #[test]
+    fn test_matches_adam_update() {
+        let init = [1.0, -2.0, 0.25];
+        let (vs, mut opt) = setup(&init);
+        opt.set_weight_decay(0.1);
+
+        let (lr, wd, beta1, beta2, eps) = (0.01, 0.1, 0.9, 0.999, 1e-8);
+        let mut w = init;
+        let mut m = [0.0; 3];
+        let mut v = [0.0; 3];
+        for t in 1..=3 {
+            step(&vs, &mut opt);
+            for i in 0..3 {
+                let g = X[i] + wd * w[i];
+                m[i] = beta1 * m[i] + (1.0 - beta1) * g;
+                v[i] = beta2 * v[i] + (1.0 - beta2) * g * g;
+                let m_hat = m[i] / (1.0 - f64::powi(beta1, t));
+                let v_hat = v[i] / (1.0 - f64::powi(beta2, t));
+                w[i] -= lr * m_hat / (v_hat.sqrt() + eps);
+            }
+            for (actual, expected) in values(&vs).iter().zip(w.iter()) {
+                assert!((actual - expected).abs() < 1e-10, "{} {}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_named_tensors_round_trip() {
+        let (vs, mut opt) = setup(&[1.0, -2.0, 0.25]);
+        step(&vs, &mut opt);
+        step(&vs, &mut opt);
+        // copies, the named tensors share storage with the optimizer
+        let named: HashMap<String, Tensor> = opt
+            .named_tensors()
+            .into_iter()
+            .map(|(name, t)| (name, t.copy()))
+            .collect();
+
+        let w: Vec<f64> = values(&vs);
+        let (restored_vs, mut restored) = setup(&[w[0], w[1], w[2]]);
+        restored.load_named_tensors(&named).unwrap();
+        step(&vs, &mut opt);
+        step(&restored_vs, &mut restored);
+        assert_eq!(values(&restored_vs), values(&vs));
+
+        let other = VarStore::new(tch::Device::Cpu);
+        other
+            .root()
+            .var_copy("u", &tensor(&[0.0f64], &[1], Kind::Double));
+        assert!(Adam::new(&other, 0.01).load_named_tensors(&named).is_err());
+    }
+}
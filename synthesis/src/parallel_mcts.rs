@@ -0,0 +1,561 @@
+use crate::config::{ActionSelection, Exploration, MCTSConfig, PolicyNoise};
+use crate::game::{Game, Outcome};
+use crate::policies::Policy;
+use rand::{distributions::Distribution, thread_rng, Rng};
+use rand_distr::Dirichlet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+type ActionId = u16;
+
+#[derive(Debug)]
+struct Stats {
+    solution: Option<Outcome>,
+    outcome_probs: [f32; 3],
+    num_visits: f32,
+    virtual_losses: f32, // workers below this node that haven't backed up their value yet
+}
+
+impl Stats {
+    // a pending worker counts as a visit the player to move here won, so the parent prefers
+    // other children until the real value comes back
+    fn q(&self) -> f32 {
+        (self.outcome_probs[2] + self.virtual_losses - self.outcome_probs[0]) / self.visits()
+    }
+
+    #[inline]
+    fn visits(&self) -> f32 {
+        self.num_visits + self.virtual_losses
+    }
+}
+
+#[derive(Debug)]
+struct Node<G: Game<N>, const N: usize> {
+    game: G,
+    action: ActionId,
+    action_prob: f32,
+    stats: Mutex<Stats>,
+    children: OnceLock<Box<[Node<G, N>]>>, // set once by the worker that visits the node
+}
+
+impl<G: Game<N>, const N: usize> Node<G, N> {
+    fn unvisited(game: G, solution: Option<Outcome>, action: ActionId, action_prob: f32) -> Self {
+        Self {
+            game,
+            action,
+            action_prob,
+            stats: Mutex::new(Stats {
+                solution,
+                outcome_probs: [0.0; 3],
+                num_visits: 0.0,
+                virtual_losses: 0.0,
+            }),
+            children: OnceLock::new(),
+        }
+    }
+
+    fn stats(&self) -> MutexGuard<'_, Stats> {
+        self.stats.lock().unwrap()
+    }
+
+    fn children(&self) -> &[Node<G, N>] {
+        self.children.get().map_or(&[], |children| children)
+    }
+}
+
+// Tree parallel MCTS: one worker thread per policy, all searching the same tree. Every node has
+// its own lock and is only held while reading or updating its statistics. Workers add a virtual
+// loss to the nodes on their path so the others spread out over the tree, and a worker that
+// reaches a node another one is visiting waits for its children instead of visiting it twice.
+pub struct ParallelMCTS<'a, G: Game<N>, P: Policy<G, N>, const N: usize> {
+    root: Node<G, N>,
+    policies: &'a mut [P],
+    cfg: MCTSConfig,
+}
+
+impl<'a, G: Game<N> + Sync, P: Policy<G, N> + Send, const N: usize> ParallelMCTS<'a, G, P, N> {
+    pub fn exploit(
+        explores: usize,
+        cfg: MCTSConfig,
+        policies: &'a mut [P],
+        game: G,
+        action_selection: ActionSelection,
+    ) -> G::Action {
+        let mut mcts = Self::new(cfg, policies, game);
+        mcts.explore_n(explores);
+        mcts.best_action(action_selection)
+    }
+
+    // one worker per policy, so pass as many policies as threads to search with
+    pub fn new(cfg: MCTSConfig, policies: &'a mut [P], game: G) -> Self {
+        assert!(!policies.is_empty(), "need a policy for every worker");
+        let mut mcts = Self {
+            root: Node::unvisited(game, None, 0, 0.0),
+            policies,
+            cfg,
+        };
+        explore(&mcts.root, &mcts.cfg, &mut mcts.policies[0]);
+        mcts.add_root_noise();
+        mcts
+    }
+
+    pub fn explore_n(&mut self, n: usize) {
+        let Self {
+            root,
+            policies,
+            cfg,
+        } = self;
+        let (root, cfg) = (&*root, &*cfg);
+        let started = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for policy in policies.iter_mut() {
+                let started = &started;
+                scope.spawn(move || {
+                    // NOTE a solved root stops every worker, see `MCTS::explore_n`
+                    while started.fetch_add(1, Ordering::Relaxed) < n
+                        && root.stats().solution.is_none()
+                    {
+                        explore(root, cfg, policy);
+                    }
+                });
+            }
+        });
+    }
+}
+
+impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> ParallelMCTS<'a, G, P, N> {
+    pub fn target_policy(&self, search_policy: &mut [f32; N]) {
+        search_policy.fill(0.0);
+        let mut total = 0.0;
+        let root = self.root.stats();
+        if root.num_visits == 1.0 {
+            match root.solution {
+                Some(Outcome::Win(_)) => {
+                    for child in self.root.children() {
+                        let v = if let Some(Outcome::Lose(_)) = child.stats().solution {
+                            1.0
+                        } else {
+                            0.0
+                        };
+                        search_policy[child.action as usize] = v;
+                        total += v;
+                    }
+                }
+                _ => {
+                    for child in self.root.children() {
+                        search_policy[child.action as usize] = 1.0;
+                        total += 1.0;
+                    }
+                }
+            }
+        } else {
+            for child in self.root.children() {
+                let v = child.stats().num_visits;
+                search_policy[child.action as usize] = v;
+                total += v;
+            }
+        }
+        for i in 0..N {
+            search_policy[i] /= total;
+        }
+    }
+
+    pub fn target_q(&self) -> [f32; 3] {
+        let root = self.root.stats();
+        match root.solution {
+            Some(outcome) => outcome.into(),
+            None => {
+                let mut outcome_probs = [0.0; 3];
+                for i in 0..3 {
+                    outcome_probs[i] = root.outcome_probs[i] / root.num_visits;
+                }
+                outcome_probs
+            }
+        }
+    }
+
+    pub fn best_action(&self, action_selection: ActionSelection) -> G::Action {
+        let mut best_action = None;
+        let mut best_value = None;
+        for child in self.root.children() {
+            let stats = child.stats();
+            let value = match stats.solution {
+                Some(Outcome::Win(turns)) => Some((0.0, turns as f32)),
+                None => match action_selection {
+                    ActionSelection::Q => Some((1.0, -stats.q())),
+                    ActionSelection::NumVisits => Some((1.0, stats.num_visits)),
+                    ActionSelection::Gumbel => panic!("Not supported in parallel mcts"),
+                },
+                Some(Outcome::Draw(turns)) => Some((2.0, -(turns as f32))),
+                Some(Outcome::Lose(turns)) => Some((3.0, -(turns as f32))),
+            };
+            if value > best_value {
+                best_value = value;
+                best_action = Some(self.root.game.decode_action(child.action as usize));
+            }
+        }
+        best_action.unwrap()
+    }
+
+    pub fn solution(&self, action: &G::Action) -> Option<Outcome> {
+        let action: usize = (*action).into();
+        let action = action as ActionId;
+        self.root
+            .children()
+            .iter()
+            .find(|child| child.action == action)
+            .and_then(|child| child.stats().solution)
+    }
+}
+
+impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> ParallelMCTS<'a, G, P, N> {
+    fn add_root_noise(&mut self) {
+        match self.cfg.root_policy_noise {
+            PolicyNoise::None => {}
+            PolicyNoise::Equal { weight } => {
+                self.add_equalizing_noise(weight);
+            }
+            PolicyNoise::Dirichlet { alpha, weight } => {
+                self.add_dirichlet_noise(&mut thread_rng(), alpha, weight);
+            }
+        }
+    }
+
+    fn add_dirichlet_noise<R: Rng>(&mut self, rng: &mut R, alpha: f32, noise_weight: f32) {
+        let children = match self.root.children.get_mut() {
+            Some(children) if children.len() >= 2 => children,
+            _ => return,
+        };
+        let dirichlet = Dirichlet::new_with_size(alpha, children.len()).unwrap();
+        let noise_probs = dirichlet.sample(rng);
+        for (noise, child) in noise_probs.iter().zip(children.iter_mut()) {
+            child.action_prob = child.action_prob * (1.0 - noise_weight) + noise_weight * noise;
+        }
+    }
+
+    fn add_equalizing_noise(&mut self, noise_weight: f32) {
+        let children = match self.root.children.get_mut() {
+            Some(children) if children.len() >= 2 => children,
+            _ => return,
+        };
+        let noise = 1.0 / children.len() as f32;
+        for child in children.iter_mut() {
+            child.action_prob = child.action_prob * (1.0 - noise_weight) + noise_weight * noise;
+        }
+    }
+}
+
+fn explore<G: Game<N>, P: Policy<G, N>, const N: usize>(
+    root: &Node<G, N>,
+    cfg: &MCTSConfig,
+    policy: &mut P,
+) {
+    let mut path = vec![root];
+    let mut node = root;
+    loop {
+        let solution = {
+            let mut stats = node.stats();
+            stats.virtual_losses += 1.0;
+            stats.solution
+        };
+        if let Some(outcome) = solution {
+            backprop(cfg, &path, outcome.into(), true);
+            return;
+        }
+
+        // whoever initializes the children evaluates the node, the rest wait for them
+        let mut evaluated = None;
+        let children = node
+            .children
+            .get_or_init(|| visit(cfg, &node.game, policy, &mut evaluated));
+        if let Some((outcome_probs, any_solved)) = evaluated {
+            backprop(cfg, &path, outcome_probs, any_solved);
+            return;
+        }
+
+        node = select_best_child(cfg, node, children);
+        path.push(node);
+    }
+}
+
+// Expands `game` and evaluates it with `policy`. A single child is left to the caller to visit
+// when `auto_extend` is on, and `evaluated` stays None.
+fn visit<G: Game<N>, P: Policy<G, N>, const N: usize>(
+    cfg: &MCTSConfig,
+    game: &G,
+    policy: &mut P,
+    evaluated: &mut Option<([f32; 3], bool)>,
+) -> Box<[Node<G, N>]> {
+    let mut children = Vec::new();
+    let mut any_solved = false;
+    for action in game.iter_actions() {
+        let mut child_game = game.clone();
+        let is_over = child_game.step(&action);
+        let solution = if is_over {
+            any_solved = true;
+            Some(child_game.reward(child_game.player()).into())
+        } else {
+            None
+        };
+        let action: usize = action.into();
+        children.push(Node::unvisited(
+            child_game,
+            solution,
+            action as ActionId,
+            1.0,
+        ));
+    }
+
+    if cfg.auto_extend && children.len() == 1 {
+        return children.into_boxed_slice();
+    }
+
+    let (logits, outcome_probs) = policy.eval(game);
+
+    // stable softmax
+    let mut max_logit = f32::NEG_INFINITY;
+    for child in children.iter_mut() {
+        let logit = logits[child.action as usize];
+        max_logit = max_logit.max(logit);
+        child.action_prob = logit;
+    }
+    let mut total = 0.0;
+    for child in children.iter_mut() {
+        child.action_prob = (child.action_prob - max_logit).exp();
+        total += child.action_prob;
+    }
+    for child in children.iter_mut() {
+        child.action_prob /= total;
+    }
+
+    *evaluated = Some((outcome_probs, any_solved));
+    children.into_boxed_slice()
+}
+
+fn select_best_child<'t, G: Game<N>, const N: usize>(
+    cfg: &MCTSConfig,
+    parent: &Node<G, N>,
+    children: &'t [Node<G, N>],
+) -> &'t Node<G, N> {
+    let (parent_q, parent_visits) = {
+        let stats = parent.stats();
+        (stats.q(), stats.visits())
+    };
+    let mut best_child = None;
+    let mut best_value = None;
+    for child in children {
+        let stats = child.stats();
+        let q = exploit_value(cfg, parent_q, &stats);
+        let u = explore_value(cfg, parent_visits, child.action_prob, &stats);
+        let value = Some(q + u);
+        if value > best_value {
+            best_child = Some(child);
+            best_value = value;
+        }
+    }
+    best_child.unwrap()
+}
+
+fn exploit_value(cfg: &MCTSConfig, parent_q: f32, child: &Stats) -> f32 {
+    if let Some(outcome) = child.solution {
+        if cfg.select_solved_nodes {
+            outcome.reversed().value()
+        } else {
+            f32::NEG_INFINITY
+        }
+    } else if child.visits() == 0.0 {
+        cfg.fpu.value(parent_q)
+    } else {
+        -child.q()
+    }
+}
+
+fn explore_value(cfg: &MCTSConfig, parent_visits: f32, action_prob: f32, child: &Stats) -> f32 {
+    match cfg.exploration {
+        Exploration::Uct { c } => {
+            let visits = (c * parent_visits.ln()).sqrt();
+            visits / child.visits().sqrt()
+        }
+        Exploration::PolynomialUct { c } => {
+            let visits = parent_visits.sqrt();
+            c * action_prob * visits / (1.0 + child.visits())
+        }
+        Exploration::Gumbel { .. } => panic!("Not supported in parallel mcts"),
+    }
+}
+
+// Same as `MCTS::backprop` along the path a worker took, taking its virtual losses back on the way.
+fn backprop<G: Game<N>, const N: usize>(
+    cfg: &MCTSConfig,
+    path: &[&Node<G, N>],
+    mut outcome_probs: [f32; 3],
+    mut solved: bool,
+) {
+    for node in path.iter().rev() {
+        // children are read one lock at a time, a worker never holds two
+        let mut all_solved = true;
+        let mut best_child_solution = None;
+        if cfg.solve && solved {
+            for child in node.children() {
+                let soln = child.stats().solution.map(|o| o.reversed());
+                all_solved &= soln.is_some();
+                best_child_solution = best_child_solution.max(soln);
+            }
+        }
+
+        let mut stats = node.stats();
+        stats.virtual_losses -= 1.0;
+        if cfg.solve && solved {
+            let best_solution = stats.solution.max(best_child_solution);
+            let correct_values = cfg.correct_values_on_solve;
+            if let Some(Outcome::Win(in_turns)) = best_solution {
+                // at least 1 is a win, so mark this node as a win
+                stats.solution = Some(Outcome::Win(in_turns));
+                if correct_values {
+                    for i in 0..3 {
+                        outcome_probs[i] = -stats.outcome_probs[i];
+                    }
+                    outcome_probs[2] += stats.num_visits + 1.0;
+                }
+            } else if best_solution.is_some() && all_solved {
+                // all children node's are proven losses or draws
+                let best_outcome = best_solution.unwrap();
+                stats.solution = Some(best_outcome);
+                if correct_values {
+                    for i in 0..3 {
+                        outcome_probs[i] = -stats.outcome_probs[i];
+                    }
+                    if let Outcome::Draw(_) = best_outcome {
+                        outcome_probs[1] += stats.num_visits + 1.0;
+                    } else {
+                        outcome_probs[0] += stats.num_visits + 1.0;
+                    }
+                }
+            } else {
+                solved = false;
+            }
+        }
+
+        for i in 0..3 {
+            stats.outcome_probs[i] += outcome_probs[i];
+        }
+        stats.num_visits += 1.0;
+        outcome_probs.swap(0, 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::{SeedableRng, StdRng};
+
+    use super::*;
+    use crate::config::Fpu;
+    use crate::mcts::tests::TicTacToe;
+    use crate::mcts::MCTS;
+    use crate::policies::RolloutPolicy;
+
+    const NUM_WORKERS: usize = 4;
+
+    fn cfg() -> MCTSConfig {
+        MCTSConfig {
+            exploration: Exploration::PolynomialUct { c: 2.0 },
+            solve: true,
+            correct_values_on_solve: true,
+            fpu: Fpu::Const(f32::INFINITY),
+            select_solved_nodes: true,
+            auto_extend: true,
+            root_policy_noise: PolicyNoise::None,
+        }
+    }
+
+    fn game(actions: &[usize]) -> TicTacToe {
+        let mut game = TicTacToe::new();
+        for &action in actions {
+            game.step(&action.into());
+        }
+        game
+    }
+
+    fn rngs() -> Vec<StdRng> {
+        (0..NUM_WORKERS as u64).map(StdRng::seed_from_u64).collect()
+    }
+
+    fn policies(rngs: &mut [StdRng]) -> Vec<RolloutPolicy<'_, StdRng>> {
+        rngs.iter_mut().map(|rng| RolloutPolicy { rng }).collect()
+    }
+
+    // the serial search's solved root value
+    fn solve_serial(game: &TicTacToe) -> [f32; 3] {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut policy = RolloutPolicy { rng: &mut rng };
+        let mut mcts = MCTS::with_capacity(1601, cfg(), &mut policy, game.clone());
+        mcts.explore_n(100_000);
+        mcts.target_q()
+    }
+
+    #[test]
+    fn test_solve_win() {
+        let game = game(&[0, 2]);
+        let mut rngs = rngs();
+        let mut policies = policies(&mut rngs);
+        let mut mcts = ParallelMCTS::new(cfg(), &mut policies, game.clone());
+        mcts.explore_n(100_000);
+        assert_eq!(mcts.target_q(), [0.0, 0.0, 1.0]);
+        assert_eq!(solve_serial(&game), mcts.target_q());
+        // there is more than one forced win, any proven one will do
+        let action = mcts.best_action(ActionSelection::Q);
+        assert!(matches!(mcts.solution(&action), Some(Outcome::Lose(_))));
+
+        let mut search_policy = [0.0; 9];
+        mcts.target_policy(&mut search_policy);
+        assert!((search_policy.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_solve_loss() {
+        let game = game(&[0, 2, 6]);
+        let mut rngs = rngs();
+        let mut policies = policies(&mut rngs);
+        let mut mcts = ParallelMCTS::new(cfg(), &mut policies, game.clone());
+        mcts.explore_n(100_000);
+        assert_eq!(mcts.target_q(), [1.0, 0.0, 0.0]);
+        assert_eq!(solve_serial(&game), mcts.target_q());
+    }
+
+    #[test]
+    fn test_solve_draw() {
+        let game = game(&[0, 4]);
+        let mut rngs = rngs();
+        let mut policies = policies(&mut rngs);
+        let mut mcts = ParallelMCTS::new(cfg(), &mut policies, game.clone());
+        mcts.explore_n(100_000);
+        assert_eq!(mcts.target_q(), [0.0, 1.0, 0.0]);
+        assert_eq!(solve_serial(&game), mcts.target_q());
+    }
+
+    #[test]
+    fn test_same_forced_wins_as_serial() {
+        // every position after three moves
+        let mut rngs = rngs();
+        let mut num_wins = 0;
+        for a in 0..9 {
+            for b in (0..9).filter(|&b| b != a) {
+                for c in (0..9).filter(|&c| c != a && c != b) {
+                    let game = game(&[a, b, c]);
+                    let serial_q = solve_serial(&game);
+                    let mut policies = policies(&mut rngs);
+                    let mut mcts = ParallelMCTS::new(cfg(), &mut policies, game);
+                    mcts.explore_n(100_000);
+                    assert_eq!(mcts.target_q(), serial_q, "{:?}", [a, b, c]);
+                    if serial_q == [0.0, 0.0, 1.0] {
+                        // the chosen action must be one of the forced wins
+                        let action = mcts.best_action(ActionSelection::Q);
+                        assert!(matches!(mcts.solution(&action), Some(Outcome::Lose(_))));
+                        num_wins += 1;
+                    }
+                }
+            }
+        }
+        assert!(num_wins > 0);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::game::Notation;
+use std::io::{Error, ErrorKind, Write};
+
+const MOVES_PER_LINE: usize = 8;
+
+// A complete game in PGN-like text: tag pairs such as White, Black, Seed and Result, then the
+// moves in the game's `Notation`. Unlike `SelfPlayRecord` it keeps who played and under which
+// settings, and can be read by people and other tools.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GameRecord {
+    pub tags: Vec<(String, String)>, // in the order they are written
+    pub moves: Vec<String>,
+}
+
+impl GameRecord {
+    pub fn new(game_name: &str, white: &str, black: &str) -> Self {
+        let mut record = Self::default();
+        record.set_tag("Game", game_name);
+        record.set_tag("White", white);
+        record.set_tag("Black", black);
+        record
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _v)| n == name)
+            .map(|(_n, v)| v.as_str())
+    }
+
+    // replaces the tag if it is already there, otherwise adds it at the end
+    pub fn set_tag<V: ToString>(&mut self, name: &str, value: V) {
+        let value = value.to_string();
+        match self.tags.iter_mut().find(|(n, _v)| n == name) {
+            Some((_n, v)) => *v = value,
+            None => self.tags.push((String::from(name), value)),
+        }
+    }
+
+    // `action` as played in `game`, call it before stepping
+    pub fn push<G: Notation<N>, const N: usize>(&mut self, game: &G, action: &G::Action) {
+        self.moves.push(game.notation(action));
+    }
+
+    // sets the Result and Termination tags, `white_reward` is the first player's reward
+    pub fn finish(&mut self, white_reward: f32, termination: &str) {
+        let result = if white_reward > 0.0 {
+            "1-0"
+        } else if white_reward < 0.0 {
+            "0-1"
+        } else {
+            "1/2-1/2"
+        };
+        self.set_tag("Result", result);
+        self.set_tag("Termination", termination);
+    }
+
+    // "*" until the game is finished
+    pub fn result(&self) -> &str {
+        self.tag("Result").unwrap_or("*")
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for (name, value) in self.tags.iter() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(writer, "[{} \"{}\"]", name, value)?;
+        }
+        writeln!(writer)?;
+        for (i, pair) in self.moves.chunks(2).enumerate() {
+            write!(writer, "{}. {}", i + 1, pair.join(" "))?;
+            let end_of_line = (i + 1) % MOVES_PER_LINE == 0;
+            write!(writer, "{}", if end_of_line { "\n" } else { " " })?;
+        }
+        writeln!(writer, "{}", self.result())?;
+        writeln!(writer)
+    }
+
+    // every game in `text`. Move numbers and {comments} are skipped, a result ends a game
+    pub fn parse(text: &str) -> std::io::Result<Vec<Self>> {
+        let mut records = Vec::new();
+        let mut record = Self::default();
+        let mut in_moves = false;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                if in_moves {
+                    return Err(invalid(format!("line {}: tag after the moves", i + 1)));
+                }
+                let (name, value) =
+                    parse_tag(line).ok_or_else(|| invalid(format!("line {}: bad tag", i + 1)))?;
+                record.tags.push((name, value));
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                in_moves = true;
+                if token.starts_with('{') {
+                    // comments end on the line they start
+                    let mut end = token;
+                    while !end.ends_with('}') {
+                        end = tokens
+                            .next()
+                            .ok_or_else(|| invalid(format!("line {}: open comment", i + 1)))?;
+                    }
+                    continue;
+                }
+                if is_result(token) {
+                    match record.tag("Result") {
+                        Some(result) if result != token => {
+                            return Err(invalid(format!(
+                                "line {}: result {} doesn't match the Result tag {}",
+                                i + 1,
+                                token,
+                                result
+                            )))
+                        }
+                        None if token != "*" => record.set_tag("Result", token),
+                        _ => {}
+                    }
+                    records.push(std::mem::take(&mut record));
+                    in_moves = false;
+                    continue;
+                }
+                // "12." before white's move, "12..." before a black move that starts a line
+                let token = strip_move_number(token);
+                if !token.is_empty() {
+                    record.moves.push(String::from(token));
+                }
+            }
+        }
+        if in_moves || !record.tags.is_empty() {
+            return Err(invalid(String::from("last game has no result")));
+        }
+        Ok(records)
+    }
+
+    // plays the moves from `G::new()`, failing on the first one that can't be read or isn't legal
+    pub fn replay<G: Notation<N>, const N: usize>(&self) -> std::io::Result<G> {
+        let mut game = G::new();
+        for (i, text) in self.moves.iter().enumerate() {
+            if game.is_over() {
+                return Err(invalid(format!(
+                    "move {} {} after the game ended",
+                    i + 1,
+                    text
+                )));
+            }
+            let action = game
+                .parse_notation(text)
+                .filter(|action| game.iter_actions().any(|legal| legal == *action))
+                .ok_or_else(|| invalid(format!("move {} {} is not legal", i + 1, text)))?;
+            game.step(&action);
+        }
+        Ok(game)
+    }
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+// TicTacToe style notations are numbers themselves, so only digits followed by a period count
+fn strip_move_number(token: &str) -> &str {
+    let digits = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    if digits.len() < token.len() && digits.starts_with('.') {
+        digits.trim_start_matches('.')
+    } else {
+        token
+    }
+}
+
+pub(crate) fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some((String::from(name), unescaped))
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::mcts::tests::TicTacToe;
+
+    fn played(actions: &[usize]) -> GameRecord {
+        let mut record = GameRecord::new(TicTacToe::NAME, "model_1.ot", "VanillaMCTS\"800\"");
+        record.set_tag("Seed", 3);
+        let mut game = TicTacToe::new();
+        for &action_id in actions {
+            let action = game.decode_action(action_id);
+            record.push(&game, &action);
+            game.step(&action);
+        }
+        if game.is_over() {
+            record.finish(game.reward(TicTacToe::new().player()), "normal");
+        }
+        record
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let records = vec![
+            played(&[4, 0, 8, 2, 1, 7, 6, 3, 5]),
+            played(&[0, 3, 1, 4, 2]),
+            played(&[0, 1, 3, 2, 6]),
+        ];
+        let mut bytes = Vec::new();
+        for record in records.iter() {
+            record.write(&mut bytes).unwrap();
+        }
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.contains("[Black \"VanillaMCTS\\\"800\\\"\"]"));
+        assert!(text.contains("1. 4 0 2. 8 2"));
+        assert_eq!(GameRecord::parse(&text).unwrap(), records);
+    }
+
+    #[test]
+    fn test_results() {
+        assert_eq!(played(&[4, 0, 8, 2, 1, 7, 6, 3, 5]).result(), "1/2-1/2");
+        assert_eq!(played(&[0, 3, 1, 4, 2]).result(), "1-0");
+        assert_eq!(played(&[3, 0, 4, 1, 8, 2]).result(), "0-1");
+        assert_eq!(played(&[4]).result(), "*");
+    }
+
+    #[test]
+    fn test_replay() {
+        let record = played(&[0, 3, 1, 4, 2]);
+        let game: TicTacToe = record.replay().unwrap();
+        assert!(game.is_over());
+
+        let mut illegal = record.clone();
+        illegal.moves[1] = String::from("0");
+        assert!(illegal.replay::<TicTacToe, 9>().is_err());
+        let mut unreadable = record.clone();
+        unreadable.moves[1] = String::from("x");
+        assert!(unreadable.replay::<TicTacToe, 9>().is_err());
+        let mut too_long = record;
+        too_long.moves.push(String::from("8"));
+        assert!(too_long.replay::<TicTacToe, 9>().is_err());
+    }
+
+    #[test]
+    fn test_parse_comments_and_numbers() {
+        let text = "[White \"a\"]\n[Black \"b\"]\n\n1. 4 {center} 0 2. 8\n2... 2 *\n";
+        let records = GameRecord::parse(text).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].moves, vec!["4", "0", "8", "2"]);
+        assert_eq!(records[0].result(), "*");
+    }
+
+    #[test]
+    fn test_ratings_read_records() {
+        let mut bytes = Vec::new();
+        played(&[0, 3, 1, 4, 2]).write(&mut bytes).unwrap();
+        played(&[3, 0, 4, 1, 8, 2]).write(&mut bytes).unwrap();
+        let games = crate::ratings::parse_pgn(&String::from_utf8(bytes).unwrap());
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].white, "model_1.ot");
+        assert_eq!(games[0].outcome, 2);
+        assert_eq!(games[1].outcome, 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(GameRecord::parse("[White \"a\"]\n\n1. 4 0").is_err());
+        assert!(GameRecord::parse("[Result \"1-0\"]\n\n1. 4 0 0-1").is_err());
+        assert!(GameRecord::parse("[White a]\n\n*").is_err());
+    }
+}
@@ -1,13 +1,15 @@
-use crate::game::Game;
-use crate::policies::Policy;
+use crate::game::Symmetries;
+use crate::policies::{BatchPolicy, Policy};
 use std::collections::HashMap;
 
-pub struct PolicyWithCache<'a, G: Game<N>, P: Policy<G, N>, const N: usize> {
+// symmetric positions share one entry, keyed by their canonical image
+
+pub struct PolicyWithCache<'a, G: Symmetries<N>, P: Policy<G, N>, const N: usize> {
     pub policy: &'a mut P,
     pub cache: HashMap<G, ([f32; N], [f32; 3])>,
 }
 
-impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> PolicyWithCache<'a, G, P, N> {
+impl<'a, G: Symmetries<N>, P: Policy<G, N>, const N: usize> PolicyWithCache<'a, G, P, N> {
     pub fn with_capacity(capacity: usize, policy: &'a mut P) -> Self {
         Self {
             policy,
@@ -16,27 +18,20 @@ impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> PolicyWithCache<'a, G, P,
     }
 }
 
-impl<'a, G: Game<N>, P: Policy<G, N>, const N: usize> Policy<G, N>
+impl<'a, G: Symmetries<N>, P: Policy<G, N>, const N: usize> Policy<G, N>
     for PolicyWithCache<'a, G, P, N>
 {
     fn eval(&mut self, game: &G) -> ([f32; N], [f32; 3]) {
-        match self.cache.get(&game) {
-            Some(pi_v) => *pi_v,
-            None => {
-                let pi_v = self.policy.eval(game);
-                self.cache.insert(game.clone(), pi_v);
-                pi_v
-            }
-        }
+        cached_eval(&mut self.cache, &mut *self.policy, game)
     }
 }
 
-pub struct OwnedPolicyWithCache<G: Game<N>, P: Policy<G, N>, const N: usize> {
+pub struct OwnedPolicyWithCache<G: Symmetries<N>, P: Policy<G, N>, const N: usize> {
     pub policy: P,
     pub cache: HashMap<G, ([f32; N], [f32; 3])>,
 }
 
-impl<G: Game<N>, P: Policy<G, N>, const N: usize> OwnedPolicyWithCache<G, P, N> {
+impl<G: Symmetries<N>, P: Policy<G, N>, const N: usize> OwnedPolicyWithCache<G, P, N> {
     pub fn with_capacity(capacity: usize, policy: P) -> Self {
         Self {
             policy,
@@ -45,15 +40,69 @@ impl<G: Game<N>, P: Policy<G, N>, const N: usize> OwnedPolicyWithCache<G, P, N>
     }
 }
 
-impl<G: Game<N>, P: Policy<G, N>, const N: usize> Policy<G, N> for OwnedPolicyWithCache<G, P, N> {
+impl<G: Symmetries<N>, P: Policy<G, N>, const N: usize> Policy<G, N>
+    for OwnedPolicyWithCache<G, P, N>
+{
     fn eval(&mut self, game: &G) -> ([f32; N], [f32; 3]) {
-        match self.cache.get(game) {
-            Some(pi_v) => *pi_v,
-            None => {
-                let pi_v = self.policy.eval(game);
-                self.cache.insert(game.clone(), pi_v);
-                pi_v
-            }
+        cached_eval(&mut self.cache, &mut self.policy, game)
+    }
+}
+
+impl<'a, G: Symmetries<N>, P: BatchPolicy<G, N>, const N: usize> BatchPolicy<G, N>
+    for PolicyWithCache<'a, G, P, N>
+{
+    fn eval_batch(&mut self, games: &[G]) -> Vec<([f32; N], [f32; 3])> {
+        cached_eval_batch(&mut self.cache, &mut *self.policy, games)
+    }
+}
+
+impl<G: Symmetries<N>, P: BatchPolicy<G, N>, const N: usize> BatchPolicy<G, N>
+    for OwnedPolicyWithCache<G, P, N>
+{
+    fn eval_batch(&mut self, games: &[G]) -> Vec<([f32; N], [f32; 3])> {
+        cached_eval_batch(&mut self.cache, &mut self.policy, games)
+    }
+}
+
+fn cached_eval<G: Symmetries<N>, P: Policy<G, N>, const N: usize>(
+    cache: &mut HashMap<G, ([f32; N], [f32; 3])>,
+    policy: &mut P,
+    game: &G,
+) -> ([f32; N], [f32; 3]) {
+    let (canonical, i) = game.canonical();
+    let (pi, v) = match cache.get(&canonical) {
+        Some(pi_v) => *pi_v,
+        None => {
+            let pi_v = policy.eval(&canonical);
+            cache.insert(canonical, pi_v);
+            pi_v
         }
+    };
+    (G::map_policy(G::inverse(i), &pi), v)
+}
+
+// only the positions missing from the cache go to the inner policy, in one batch
+fn cached_eval_batch<G: Symmetries<N>, P: BatchPolicy<G, N>, const N: usize>(
+    cache: &mut HashMap<G, ([f32; N], [f32; 3])>,
+    policy: &mut P,
+    games: &[G],
+) -> Vec<([f32; N], [f32; 3])> {
+    let canonicals: Vec<(G, usize)> = games.iter().map(|game| game.canonical()).collect();
+    let mut misses: Vec<G> = Vec::new();
+    for (canonical, _) in canonicals.iter() {
+        if !cache.contains_key(canonical) && !misses.contains(canonical) {
+            misses.push(canonical.clone());
+        }
+    }
+    if !misses.is_empty() {
+        let outputs = policy.eval_batch(&misses);
+        cache.extend(misses.into_iter().zip(outputs));
     }
+    canonicals
+        .iter()
+        .map(|(canonical, i)| {
+            let (pi, v) = cache[canonical];
+            (G::map_policy(G::inverse(*i), &pi), v)
+        })
+        .collect()
 }
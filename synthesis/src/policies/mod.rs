@@ -3,5 +3,5 @@ mod rollout;
 mod traits;
 
 pub use cache::{OwnedPolicyWithCache, PolicyWithCache};
-pub use rollout::RolloutPolicy;
-pub use traits::{NNPolicy, Policy};
+pub use rollout::{playout, RolloutPolicy};
+pub use traits::{BatchPolicy, NNPolicy, Policy};
@@ -1,5 +1,5 @@
-use crate::game::Game;
-use crate::policies::Policy;
+use crate::game::{Game, Undo};
+use crate::policies::{BatchPolicy, Policy};
 use rand::Rng;
 
 pub struct RolloutPolicy<'a, R: Rng> {
@@ -9,14 +9,7 @@ impl<'a, G: Game<N>, R: Rng, const N: usize> Policy<G, N> for RolloutPolicy<'a,
     fn eval(&mut self, game: &G) -> ([f32; N], [f32; 3]) {
         let player = game.player();
         let mut rollout_game = game.clone();
-        let mut is_over = game.is_over();
-        while !is_over {
-            let actions = rollout_game.iter_actions();
-            let num_actions = actions.count() as u8;
-            let i = self.rng.gen_range(0..num_actions);
-            let action = rollout_game.iter_actions().nth(i as usize).unwrap();
-            is_over = rollout_game.step(&action);
-        }
+        play_randomly(&mut rollout_game, self.rng, |_| {});
         let r = rollout_game.reward(player);
         (
             [0.0; N],
@@ -30,3 +23,38 @@ impl<'a, G: Game<N>, R: Rng, const N: usize> Policy<G, N> for RolloutPolicy<'a,
         )
     }
 }
+
+// no net to batch, the playouts just run one after the other
+impl<'a, G: Game<N>, R: Rng, const N: usize> BatchPolicy<G, N> for RolloutPolicy<'a, R> {
+    fn eval_batch(&mut self, games: &[G]) -> Vec<([f32; N], [f32; 3])> {
+        games.iter().map(|game| self.eval(game)).collect()
+    }
+}
+
+// Plays random actions until the game ends and returns the reward of the player who was to move.
+// The game is played in place and rewound afterwards, so `game` is left as it was.
+pub fn playout<G: Undo<N>, R: Rng, const N: usize>(game: &mut G, rng: &mut R) -> f32 {
+    let player = game.player();
+    let mut actions = Vec::with_capacity(G::MAX_TURNS);
+    play_randomly(game, rng, |action| actions.push(action));
+    let r = game.reward(player);
+    for action in actions.iter().rev() {
+        game.undo(action);
+    }
+    r
+}
+
+fn play_randomly<G: Game<N>, R: Rng, const N: usize>(
+    game: &mut G,
+    rng: &mut R,
+    mut on_step: impl FnMut(G::Action),
+) {
+    let mut is_over = game.is_over();
+    while !is_over {
+        let num_actions = game.iter_actions().count();
+        let i = rng.gen_range(0..num_actions);
+        let action = game.iter_actions().nth(i).unwrap();
+        is_over = game.step(&action);
+        on_step(action);
+    }
+}
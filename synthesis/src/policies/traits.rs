@@ -1,11 +1,57 @@
 use crate::game::Game;
-use tch::{nn::VarStore, Tensor};
+use tch::{nn::VarStore, Device, Kind, Tensor};
 
 pub trait Policy<G: Game<N>, const N: usize> {
     fn eval(&mut self, game: &G) -> ([f32; N], [f32; 3]);
 }
 
+// Policies that evaluate many games at once, e.g. a net running a single forward pass over them.
+// Outputs are in the order of `games` and the same as `eval` would give for each.
+pub trait BatchPolicy<G: Game<N>, const N: usize>: Policy<G, N> {
+    fn eval_batch(&mut self, games: &[G]) -> Vec<([f32; N], [f32; 3])>;
+}
+
 pub trait NNPolicy<G: Game<N>, const N: usize> {
     fn new(vs: &VarStore) -> Self;
     fn forward(&self, xs: &Tensor) -> (Tensor, Tensor);
+
+    // `forward` for inference, nets with layers that act differently while training (batch norm,
+    // dropout) run them in eval mode here
+    fn forward_eval(&self, xs: &Tensor) -> (Tensor, Tensor) {
+        self.forward(xs)
+    }
+
+    // runs one zeroed state through the net so layer widths that don't match DIMS or N fail at startup
+    fn check_shapes(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let xs = Tensor::zeros(G::DIMS, (Kind::Float, Device::Cpu));
+        let forward = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            tch::no_grad(|| self.forward_eval(&xs))
+        }));
+        let (policy, value) = match forward {
+            Ok(outputs) => outputs,
+            Err(_) => {
+                return Err(
+                    format!("{} net failed on input of shape {:?}", G::NAME, G::DIMS).into(),
+                )
+            }
+        };
+        if policy.size() != [1, N as i64] {
+            return Err(format!(
+                "{} net policy head has shape {:?}, expected [1, {}]",
+                G::NAME,
+                policy.size(),
+                N
+            )
+            .into());
+        }
+        if value.size() != [1, 3] {
+            return Err(format!(
+                "{} net value head has shape {:?}, expected [1, 3]",
+                G::NAME,
+                value.size()
+            )
+            .into());
+        }
+        Ok(())
+    }
 }
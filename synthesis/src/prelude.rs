@@ -1,10 +1,20 @@
+pub use crate::alpha_beta::{alpha_beta, AlphaBeta};
 pub use crate::alpha_zero::alpha_zero;
 pub use crate::config::{
-    ActionSelection, EvaluationConfig, Exploration, Fpu, LearningConfig, MCTSConfig, PolicyNoise,
-    RolloutConfig, ValueTarget,
+    ActionSelection, AlphaBetaConfig, EvaluationConfig, Exploration, Fpu, GatingConfig,
+    LearningConfig, MCTSConfig, Pairing, PlayerConfig, PolicyNoise, RolloutConfig, RunConfig,
+    TournamentConfig, ValueTarget,
 };
-pub use crate::data::tensor;
+pub use crate::data::{tensor, unbatch, ReplayBuffer};
 pub use crate::evaluator::evaluator;
-pub use crate::game::{Game, HasTurnOrder};
-pub use crate::policies::{NNPolicy, Policy, PolicyWithCache};
-pub use crate::utils::train_dir;
+pub use crate::game::{
+    Game, GameResult, HasTurnOrder, Notation, Oracle, Outcome, Symmetries, Undo,
+};
+pub use crate::mcts::MCTS;
+pub use crate::parallel_mcts::ParallelMCTS;
+pub use crate::pgn::GameRecord;
+pub use crate::policies::{BatchPolicy, NNPolicy, Policy, PolicyWithCache};
+pub use crate::ratings::{parse_pgn, PgnGame, Rating, Ratings};
+pub use crate::records::{RecordReader, RecordWriter, SelfPlayRecord};
+pub use crate::tournament::tournament;
+pub use crate::utils::{check_new_run, take_config_arg, train_dir};
@@ -0,0 +1,478 @@
+use crate::pgn::parse_tag;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// BayesElo's defaults, `mm` keeps these fixed unless asked to fit them
+const ELO_ADVANTAGE: f64 = 32.8; // bonus of the first player
+const ELO_DRAW: f64 = 97.3; // the wider this is, the more likely draws are between equal players
+const PRIOR_DRAWS: f64 = 2.0; // virtual draws between every pair of opponents, keeps 100% scores finite
+
+// log(10) / 400, converts Elo differences to logits
+const C: f64 = std::f64::consts::LN_10 / 400.0;
+
+// The players and result of a game in the PGN `add_pgn_record` writes. Outcomes index like value
+// targets, 0 the first player lost, 1 draw, 2 the first player won
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnGame {
+    pub white: String,
+    pub black: String,
+    pub outcome: usize,
+}
+
+pub fn parse_pgn(pgn: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut white = None;
+    let mut black = None;
+    for line in pgn.lines() {
+        let (name, value) = match parse_tag(line.trim()) {
+            Some(tag) => tag,
+            None => continue,
+        };
+        match name.as_str() {
+            "White" => white = Some(value),
+            "Black" => black = Some(value),
+            "Result" => {
+                let outcome = match value.as_str() {
+                    "0-1" => 0,
+                    "1/2-1/2" => 1,
+                    "1-0" => 2,
+                    _ => continue,
+                };
+                if let (Some(white), Some(black)) = (white.take(), black.take()) {
+                    games.push(PgnGame {
+                        white,
+                        black,
+                        outcome,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    games
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rating {
+    pub name: String,
+    pub elo: f64,
+    pub lower: f64, // 95% confidence interval
+    pub upper: f64,
+    pub games: usize,
+    pub score: f64, // (wins + draws / 2) / games
+}
+
+// Maximum a posteriori Elo under BayesElo's model, shifted so the average player is at 0
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ratings {
+    pub elo_advantage: f64,
+    pub elo_draw: f64,
+    pub prior_draws: f64,
+    pub players: Vec<Rating>, // best first
+}
+
+impl Ratings {
+    pub fn from_pgn<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self::compute(&parse_pgn(&std::fs::read_to_string(path)?)))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    // one line per player, best first, like bayeselo's `ratings` command prints them
+    pub fn table(&self) -> String {
+        let width = self
+            .players
+            .iter()
+            .map(|r| r.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let mut table = format!(
+            "{:>4} {:<width$} {:>6} {:>5} {:>5} {:>6} {:>6}\n",
+            "Rank",
+            "Name",
+            "Elo",
+            "+",
+            "-",
+            "Games",
+            "Score",
+            width = width
+        );
+        for (i, r) in self.players.iter().enumerate() {
+            table.push_str(&format!(
+                "{:>4} {:<width$} {:>6.0} {:>5.0} {:>5.0} {:>6} {:>5.1}%\n",
+                i + 1,
+                r.name,
+                r.elo,
+                r.upper - r.elo,
+                r.elo - r.lower,
+                r.games,
+                100.0 * r.score,
+                width = width
+            ));
+        }
+        table
+    }
+
+    pub fn compute(games: &[PgnGame]) -> Self {
+        // players in order of appearance
+        let mut names: Vec<String> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut id = |name: &String| {
+            *index.entry(name.clone()).or_insert_with(|| {
+                names.push(name.clone());
+                names.len() - 1
+            })
+        };
+
+        // outcome counts of every (first player, second player) pairing
+        let mut pairs: BTreeMap<(usize, usize), [f64; 3]> = BTreeMap::new();
+        let mut num_games = Vec::new();
+        let mut points = Vec::new();
+        for game in games.iter() {
+            let (w, b) = (id(&game.white), id(&game.black));
+            num_games.resize(num_games.len().max(w.max(b) + 1), 0);
+            points.resize(num_games.len(), 0.0);
+            pairs.entry((w, b)).or_insert([0.0; 3])[game.outcome] += 1.0;
+            num_games[w] += 1;
+            num_games[b] += 1;
+            points[w] += game.outcome as f64 / 2.0;
+            points[b] += (2 - game.outcome) as f64 / 2.0;
+        }
+
+        // the prior, split evenly between colors so it doesn't move the advantage
+        let opponents: Vec<(usize, usize)> =
+            pairs.keys().map(|&(w, b)| (w.min(b), w.max(b))).collect();
+        for &(i, j) in opponents.iter() {
+            pairs.entry((i, j)).or_insert([0.0; 3])[1] += PRIOR_DRAWS / 4.0;
+            pairs.entry((j, i)).or_insert([0.0; 3])[1] += PRIOR_DRAWS / 4.0;
+        }
+        let pairs: Vec<(usize, usize, [f64; 3])> = pairs
+            .into_iter()
+            .map(|((w, b), counts)| (w, b, counts))
+            .collect();
+
+        // Newton's method, the log posterior is concave in the ratings
+        let n = names.len();
+        let mut elos = vec![0.0; n];
+        for _ in 0..100 {
+            let (grad, neg_hess) = derivatives(&pairs, &elos);
+            let cov = centered_inverse(neg_hess);
+            let step: Vec<f64> = (0..n)
+                .map(|i| (0..n).map(|j| cov[i][j] * grad[j]).sum())
+                .collect();
+
+            // halve the step until it doesn't overshoot
+            let before = log_posterior(&pairs, &elos);
+            let mut t = 1.0;
+            let mut next: Vec<f64> = elos.iter().zip(&step).map(|(r, s)| r + s).collect();
+            while log_posterior(&pairs, &next) < before && t > 1e-6 {
+                t /= 2.0;
+                next = elos.iter().zip(&step).map(|(r, s)| r + t * s).collect();
+            }
+            let max_change = step.iter().fold(0.0f64, |m, s| m.max((t * s).abs()));
+            elos = next;
+            if max_change < 1e-6 {
+                break;
+            }
+        }
+        let mean = elos.iter().sum::<f64>() / n.max(1) as f64;
+        for elo in elos.iter_mut() {
+            *elo -= mean;
+        }
+
+        // the covariance of the ratings is the inverse of the negative hessian at the maximum
+        let (_grad, neg_hess) = derivatives(&pairs, &elos);
+        let cov = centered_inverse(neg_hess);
+        let mut players: Vec<Rating> = names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let half_width = 1.96 * cov[i][i].max(0.0).sqrt();
+                Rating {
+                    name,
+                    elo: elos[i],
+                    lower: elos[i] - half_width,
+                    upper: elos[i] + half_width,
+                    games: num_games[i],
+                    score: points[i] / num_games[i] as f64,
+                }
+            })
+            .collect();
+        players.sort_by(|a, b| b.elo.partial_cmp(&a.elo).unwrap());
+
+        Self {
+            elo_advantage: ELO_ADVANTAGE,
+            elo_draw: ELO_DRAW,
+            prior_draws: PRIOR_DRAWS,
+            players,
+        }
+    }
+}
+
+// ln(1 / (1 + e^-x)) without overflow
+fn log_sigmoid(x: f64) -> f64 {
+    if x > 0.0 {
+        -(-x).exp().ln_1p()
+    } else {
+        x - x.exp().ln_1p()
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+// log probability of each outcome of one game, with its first and second derivative in
+// `x`, the first player's rating minus the second's
+pub(crate) fn outcome_terms(x: f64, outcome: usize) -> (f64, f64, f64) {
+    let y = x + ELO_ADVANTAGE;
+    match outcome {
+        0 => {
+            let f = sigmoid(-C * (y + ELO_DRAW));
+            (
+                log_sigmoid(-C * (y + ELO_DRAW)),
+                -C * (1.0 - f),
+                -C * C * f * (1.0 - f),
+            )
+        }
+        2 => {
+            let f = sigmoid(C * (y - ELO_DRAW));
+            (
+                log_sigmoid(C * (y - ELO_DRAW)),
+                C * (1.0 - f),
+                -C * C * f * (1.0 - f),
+            )
+        }
+        _ => {
+            let f1 = sigmoid(C * (y + ELO_DRAW));
+            let f2 = sigmoid(C * (y - ELO_DRAW));
+            let d = (f1 - f2).max(f64::MIN_POSITIVE);
+            let d1 = C * (f1 * (1.0 - f1) - f2 * (1.0 - f2));
+            let d2 =
+                C * C * (f1 * (1.0 - f1) * (1.0 - 2.0 * f1) - f2 * (1.0 - f2) * (1.0 - 2.0 * f2));
+            let g = d1 / d;
+            (d.ln(), g, d2 / d - g * g)
+        }
+    }
+}
+
+fn log_posterior(pairs: &[(usize, usize, [f64; 3])], elos: &[f64]) -> f64 {
+    let mut total = 0.0;
+    for &(w, b, counts) in pairs.iter() {
+        for (outcome, &count) in counts.iter().enumerate() {
+            if count > 0.0 {
+                total += count * outcome_terms(elos[w] - elos[b], outcome).0;
+            }
+        }
+    }
+    total
+}
+
+// gradient and negative hessian of the log posterior
+fn derivatives(pairs: &[(usize, usize, [f64; 3])], elos: &[f64]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = elos.len();
+    let mut grad = vec![0.0; n];
+    let mut neg_hess = vec![vec![0.0; n]; n];
+    for &(w, b, counts) in pairs.iter() {
+        let (mut g, mut h) = (0.0, 0.0);
+        for (outcome, &count) in counts.iter().enumerate() {
+            if count > 0.0 {
+                let (_lp, g1, h1) = outcome_terms(elos[w] - elos[b], outcome);
+                g += count * g1;
+                h += count * h1;
+            }
+        }
+        grad[w] += g;
+        grad[b] -= g;
+        neg_hess[w][w] -= h;
+        neg_hess[b][b] -= h;
+        neg_hess[w][b] += h;
+        neg_hess[b][w] += h;
+    }
+    (grad, neg_hess)
+}
+
+// The posterior only depends on rating differences, so the negative hessian H is singular
+// along all ones. With P the projection onto that direction, (H + P)^-1 - P is the inverse
+// restricted to ratings that average to 0
+fn centered_inverse(mut a: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    let n = a.len();
+    let p = 1.0 / n as f64;
+    for (i, row) in a.iter_mut().enumerate() {
+        for x in row.iter_mut() {
+            *x += p;
+        }
+        // players never connected to the rest would make this singular
+        row[i] += 1e-9;
+    }
+    let mut inv = invert(a);
+    for row in inv.iter_mut() {
+        for x in row.iter_mut() {
+            *x -= p;
+        }
+    }
+    inv
+}
+
+// Gauss-Jordan elimination with partial pivoting
+fn invert(mut a: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    let n = a.len();
+    let mut inv: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+            .unwrap();
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let scale = 1.0 / a[col][col];
+        for j in 0..n {
+            a[col][j] *= scale;
+            inv[col][j] *= scale;
+        }
+        for i in 0..n {
+            if i == col || a[i][col] == 0.0 {
+                continue;
+            }
+            let factor = a[i][col];
+            for j in 0..n {
+                a[i][j] -= factor * a[col][j];
+                inv[i][j] -= factor * inv[col][j];
+            }
+        }
+    }
+    inv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::GameRecord;
+
+    fn games(white: &str, black: &str, outcome: usize, n: usize) -> Vec<PgnGame> {
+        vec![
+            PgnGame {
+                white: String::from(white),
+                black: String::from(black),
+                outcome,
+            };
+            n
+        ]
+    }
+
+    fn rating<'a>(ratings: &'a Ratings, name: &str) -> &'a Rating {
+        ratings.players.iter().find(|r| r.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_parse_pgn() {
+        let pgn = "[White \"model_1.ot\"]\n[Black \"VanillaMCTS800\"]\n[Termination \"normal\"]\n\
+                   [Result \"1-0\"]\n1-0\n[White \"VanillaMCTS800\"]\n[Black \"model_1.ot\"]\n\
+                   [Termination \"repetition\"]\n[Result \"1/2-1/2\"]\n1/2-1/2\n";
+        let games = parse_pgn(pgn);
+        assert_eq!(
+            games,
+            vec![
+                PgnGame {
+                    white: String::from("model_1.ot"),
+                    black: String::from("VanillaMCTS800"),
+                    outcome: 2,
+                },
+                PgnGame {
+                    white: String::from("VanillaMCTS800"),
+                    black: String::from("model_1.ot"),
+                    outcome: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_pgn_names_match_the_writer() {
+        let (white, black) = ("Rollout \"800\"", "a\\b");
+        let mut record = GameRecord::new("test", white, black);
+        record.finish(-1.0, "normal");
+        let mut bytes = Vec::new();
+        record.write(&mut bytes).unwrap();
+        assert_eq!(
+            parse_pgn(&String::from_utf8(bytes).unwrap()),
+            games(white, black, 0, 1)
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let mut results = games("model_1.ot", "VanillaMCTS800", 2, 6);
+        results.extend(games("VanillaMCTS800", "model_1.ot", 0, 4));
+        let table = Ratings::compute(&results).table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("Elo"));
+        assert!(lines[1].trim_start().starts_with("1 model_1.ot"));
+        assert!(lines[1].ends_with("100.0%"));
+        assert!(lines[2].ends_with("0.0%"));
+    }
+
+    #[test]
+    fn test_even_results_are_even_ratings() {
+        let mut results = games("a", "b", 2, 5);
+        results.extend(games("b", "a", 2, 5));
+        let ratings = Ratings::compute(&results);
+        assert!(rating(&ratings, "a").elo.abs() < 1e-6);
+        assert!(rating(&ratings, "b").elo.abs() < 1e-6);
+        assert_eq!(rating(&ratings, "a").score, 0.5);
+    }
+
+    #[test]
+    fn test_stronger_player_is_ranked_first() {
+        let mut results = games("a", "b", 2, 8);
+        results.extend(games("b", "a", 0, 6));
+        results.extend(games("b", "a", 1, 2));
+        results.extend(games("b", "c", 2, 4));
+        results.extend(games("c", "b", 1, 4));
+        let ratings = Ratings::compute(&results);
+        let names: Vec<&str> = ratings.players.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        let mean: f64 = ratings.players.iter().map(|r| r.elo).sum::<f64>() / 3.0;
+        assert!(mean.abs() < 1e-6);
+        for r in ratings.players.iter() {
+            assert!(r.lower < r.elo && r.elo < r.upper);
+        }
+    }
+
+    #[test]
+    fn test_perfect_score_stays_finite() {
+        let ratings = Ratings::compute(&games("a", "b", 2, 100));
+        let a = rating(&ratings, "a");
+        assert!(a.elo.is_finite() && a.elo > 0.0);
+        assert_eq!(a.score, 1.0);
+    }
+
+    #[test]
+    fn test_more_games_narrow_the_interval() {
+        let mut results = games("a", "b", 2, 2);
+        results.extend(games("a", "b", 0, 1));
+        let few = Ratings::compute(&results);
+        let many: Vec<PgnGame> = results
+            .iter()
+            .cycle()
+            .take(50 * results.len())
+            .cloned()
+            .collect();
+        let many = Ratings::compute(&many);
+        let width = |ratings: &Ratings| {
+            let a = rating(ratings, "a");
+            a.upper - a.lower
+        };
+        assert!(width(&many) < width(&few));
+    }
+}
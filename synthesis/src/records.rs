@@ -0,0 +1,327 @@
+use crate::game::{Game, Notation};
+use crate::pgn::GameRecord;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"SYNGAMES";
+pub const RECORDS_VERSION: u64 = 1;
+
+// One self-play game. Position 0 is `G::new()` and position i + 1 follows from `actions[i]`, so
+// the states themselves aren't stored. Each position has its search policy, the root Q after the
+// search and Z, the final outcome, both from the view of the player to move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfPlayRecord<const N: usize> {
+    pub model: String, // weights that played the game
+    pub seed: u64,     // seed of the rng that played it, unique to the game
+    pub actions: Vec<usize>,
+    pub pis: Vec<[f32; N]>,
+    pub qs: Vec<[f32; 3]>,
+    pub zs: Vec<[f32; 3]>,
+}
+
+impl<const N: usize> SelfPlayRecord<N> {
+    pub fn new(model: String, seed: u64) -> Self {
+        Self {
+            model,
+            seed,
+            actions: Vec::new(),
+            pis: Vec::new(),
+            qs: Vec::new(),
+            zs: Vec::new(),
+        }
+    }
+
+    // number of positions
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    // the recorded positions in the order they were played
+    pub fn replay<G: Game<N>>(&self) -> Vec<G> {
+        let mut game = G::new();
+        let mut positions = Vec::with_capacity(self.len());
+        for &action_id in self.actions.iter() {
+            positions.push(game.clone());
+            let action = game.decode_action(action_id);
+            game.step(&action);
+        }
+        positions
+    }
+
+    // the game as a `GameRecord` with the model on both sides. Games stopped once the search
+    // solved them end with a "solved" termination
+    pub fn to_game_record<G: Notation<N>>(&self) -> GameRecord {
+        let mut record = GameRecord::new(G::NAME, &self.model, &self.model);
+        record.set_tag("Seed", self.seed);
+        let mut game = G::new();
+        for &action_id in self.actions.iter() {
+            let action = game.decode_action(action_id);
+            record.push(&game, &action);
+            game.step(&action);
+        }
+        if let Some(z) = self.zs.first() {
+            let termination = game
+                .result()
+                .map_or("solved", |result| result.termination());
+            record.finish(z[2] - z[0], termination);
+        }
+        record
+    }
+}
+
+// Writes a header naming the game and N, then one record after another.
+pub struct RecordWriter<W: Write, const N: usize> {
+    writer: W,
+}
+
+impl<const N: usize> RecordWriter<BufWriter<File>, N> {
+    pub fn create<P: AsRef<Path>>(path: P, game_name: &str) -> std::io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), game_name)
+    }
+}
+
+impl<W: Write, const N: usize> RecordWriter<W, N> {
+    pub fn new(mut writer: W, game_name: &str) -> std::io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&RECORDS_VERSION.to_le_bytes())?;
+        writer.write_all(&(game_name.len() as u64).to_le_bytes())?;
+        writer.write_all(game_name.as_bytes())?;
+        writer.write_all(&(N as u64).to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, record: &SelfPlayRecord<N>) -> std::io::Result<()> {
+        assert_eq!(record.pis.len(), record.len());
+        assert_eq!(record.qs.len(), record.len());
+        assert_eq!(record.zs.len(), record.len());
+        let w = &mut self.writer;
+        w.write_all(&(record.model.len() as u64).to_le_bytes())?;
+        w.write_all(record.model.as_bytes())?;
+        w.write_all(&record.seed.to_le_bytes())?;
+        w.write_all(&(record.len() as u64).to_le_bytes())?;
+        for i in 0..record.len() {
+            w.write_all(&(record.actions[i] as u64).to_le_bytes())?;
+            for p in record.pis[i].iter() {
+                w.write_all(&p.to_le_bytes())?;
+            }
+            for q in record.qs[i].iter() {
+                w.write_all(&q.to_le_bytes())?;
+            }
+            for z in record.zs[i].iter() {
+                w.write_all(&z.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Reads the records of a file one at a time, so files much larger than memory can be streamed.
+pub struct RecordReader<R: Read, const N: usize> {
+    reader: R,
+    game_name: String,
+}
+
+impl<const N: usize> RecordReader<BufReader<File>, N> {
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read, const N: usize> RecordReader<R, N> {
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "not a self-play records file",
+            ));
+        }
+        let version = read_u64(&mut reader)?;
+        if version != RECORDS_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "records are version {}, expected {}",
+                    version, RECORDS_VERSION
+                ),
+            ));
+        }
+        let game_name = read_string(&mut reader)?;
+        let num_actions = read_u64(&mut reader)? as usize;
+        if num_actions != N {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("records have {} actions, expected {}", num_actions, N),
+            ));
+        }
+        Ok(Self { reader, game_name })
+    }
+
+    // `Game::NAME` of the game the records were played in
+    pub fn game_name(&self) -> &str {
+        &self.game_name
+    }
+
+    // None at the end of the file, an error if it ends in the middle of a record
+    pub fn read(&mut self) -> std::io::Result<Option<SelfPlayRecord<N>>> {
+        let mut bytes = [0; 8];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(Error::from(ErrorKind::UnexpectedEof)),
+                n => filled += n,
+            }
+        }
+        let mut model = vec![0; u64::from_le_bytes(bytes) as usize];
+        self.reader.read_exact(&mut model)?;
+        let model = String::from_utf8(model).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let seed = read_u64(&mut self.reader)?;
+
+        let mut record = SelfPlayRecord::new(model, seed);
+        let len = read_u64(&mut self.reader)? as usize;
+        for _ in 0..len {
+            record.actions.push(read_u64(&mut self.reader)? as usize);
+            let mut pi = [0.0; N];
+            read_f32s(&mut self.reader, &mut pi)?;
+            record.pis.push(pi);
+            let mut q = [0.0; 3];
+            read_f32s(&mut self.reader, &mut q)?;
+            record.qs.push(q);
+            let mut z = [0.0; 3];
+            read_f32s(&mut self.reader, &mut z)?;
+            record.zs.push(z);
+        }
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read, const N: usize> Iterator for RecordReader<R, N> {
+    type Item = std::io::Result<SelfPlayRecord<N>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> std::io::Result<String> {
+    let mut bytes = vec![0; read_u64(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn read_f32s<R: Read>(reader: &mut R, values: &mut [f32]) -> std::io::Result<()> {
+    let mut bytes = [0; 4];
+    for value in values.iter_mut() {
+        reader.read_exact(&mut bytes)?;
+        *value = f32::from_le_bytes(bytes);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mcts::tests::TicTacToe;
+
+    pub(crate) fn record(model: &str, seed: u64, actions: &[usize]) -> SelfPlayRecord<9> {
+        let mut record = SelfPlayRecord::new(String::from(model), seed);
+        for (i, &action) in actions.iter().enumerate() {
+            let mut pi = [0.0; 9];
+            pi[action] = 1.0;
+            record.actions.push(action);
+            record.pis.push(pi);
+            record.qs.push([0.25, 0.5, i as f32]);
+            record.zs.push([0.0, 1.0, 0.0]);
+        }
+        record
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let records = vec![
+            record("model_0.ot", 3, &[4, 0, 8]),
+            record("model_1.ot", 7, &[]),
+            record("model_1.ot", 8, &[0, 1, 2, 3]),
+        ];
+        let mut bytes = Vec::new();
+        let mut writer = RecordWriter::new(&mut bytes, TicTacToe::NAME).unwrap();
+        for record in records.iter() {
+            writer.write(record).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let reader = RecordReader::<_, 9>::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.game_name(), TicTacToe::NAME);
+        let read: Vec<SelfPlayRecord<9>> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let mut bytes = Vec::new();
+        let mut writer = RecordWriter::new(&mut bytes, TicTacToe::NAME).unwrap();
+        writer.write(&record("model_0.ot", 0, &[4, 0])).unwrap();
+        bytes.pop();
+
+        let mut reader = RecordReader::<_, 9>::new(bytes.as_slice()).unwrap();
+        assert!(reader.read().is_err());
+    }
+
+    #[test]
+    fn test_wrong_num_actions() {
+        let mut bytes = Vec::new();
+        RecordWriter::<_, 9>::new(&mut bytes, TicTacToe::NAME).unwrap();
+        assert!(RecordReader::<_, 7>::new(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_to_game_record() {
+        let mut won = record("model_0.ot", 5, &[0, 3, 1, 4, 2]);
+        for (i, z) in won.zs.iter_mut().enumerate() {
+            *z = if i % 2 == 0 {
+                [0.0, 0.0, 1.0]
+            } else {
+                [1.0, 0.0, 0.0]
+            };
+        }
+        let game_record = won.to_game_record::<TicTacToe>();
+        assert_eq!(game_record.tag("White"), Some("model_0.ot"));
+        assert_eq!(game_record.tag("Seed"), Some("5"));
+        assert_eq!(game_record.result(), "1-0");
+        assert_eq!(game_record.tag("Termination"), Some("normal"));
+        assert_eq!(game_record.moves, vec!["0", "3", "1", "4", "2"]);
+        assert!(game_record.replay::<TicTacToe, 9>().unwrap().is_over());
+
+        let solved = record("model_0.ot", 5, &[4, 0]).to_game_record::<TicTacToe>();
+        assert_eq!(solved.result(), "1/2-1/2");
+        assert_eq!(solved.tag("Termination"), Some("solved"));
+    }
+
+    #[test]
+    fn test_replay() {
+        let positions = record("model_0.ot", 0, &[4, 0, 8]).replay::<TicTacToe>();
+        assert_eq!(positions.len(), 3);
+        assert_eq!(positions[0], TicTacToe::new());
+        let mut game = TicTacToe::new();
+        game.step(&game.decode_action(4));
+        game.step(&game.decode_action(0));
+        assert_eq!(positions[2], game);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::alpha_beta::AlphaBeta;
+use crate::config::{Pairing, PlayerConfig, TournamentConfig};
+use crate::evaluator::FrozenMCTS;
+use crate::game::{Game, GameResult, Notation, Undo};
+use crate::mcts::MCTS;
+use crate::parallel_mcts::ParallelMCTS;
+use crate::pgn::GameRecord;
+use crate::policies::{playout, NNPolicy, Policy, RolloutPolicy};
+use crate::ratings::Ratings;
+use crate::utils::add_pgn_record;
+use rand::prelude::{Rng, SeedableRng, StdRng};
+use tch::nn::VarStore;
+
+// A player of the tournament, with the network of a `Model` loaded once per search thread
+struct Entrant<P> {
+    cfg: PlayerConfig,
+    policies: Vec<P>,
+}
+
+impl<P> Entrant<P> {
+    fn load<G: Game<N>, const N: usize>(
+        cfg: &PlayerConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        P: NNPolicy<G, N>,
+    {
+        let mut policies = Vec::new();
+        if let PlayerConfig::Model {
+            path, num_threads, ..
+        } = cfg
+        {
+            for _ in 0..(*num_threads).max(1) {
+                let mut vs = VarStore::new(tch::Device::Cpu);
+                let policy = P::new(&vs);
+                policy.check_shapes()?;
+                vs.load(path)?;
+                policies.push(policy);
+            }
+        }
+        Ok(Self {
+            cfg: cfg.clone(),
+            policies,
+        })
+    }
+
+    fn act<G: Undo<N> + Sync, const N: usize>(&mut self, game: &G, rng: &mut StdRng) -> G::Action
+    where
+        P: Policy<G, N> + Send,
+    {
+        match &self.cfg {
+            PlayerConfig::Model {
+                num_explores,
+                action,
+                mcts_cfg,
+                ..
+            } if self.policies.len() > 1 => ParallelMCTS::exploit(
+                *num_explores,
+                *mcts_cfg,
+                &mut self.policies,
+                game.clone(),
+                *action,
+            ),
+            PlayerConfig::Model {
+                num_explores,
+                action,
+                mcts_cfg,
+                ..
+            } => MCTS::exploit(
+                *num_explores,
+                *mcts_cfg,
+                &mut self.policies[0],
+                game.clone(),
+                *action,
+            ),
+            PlayerConfig::Rollout {
+                num_explores,
+                action,
+                mcts_cfg,
+                ..
+            } => FrozenMCTS::exploit(
+                *num_explores,
+                *mcts_cfg,
+                &mut RolloutPolicy { rng },
+                game.clone(),
+                *action,
+            ),
+            PlayerConfig::AlphaBeta {
+                cfg, num_playouts, ..
+            } => {
+                let num_playouts = (*num_playouts).max(1);
+                let eval = |rollout_policy: &mut RolloutPolicy<StdRng>, game: &mut G| {
+                    let total: f32 = (0..num_playouts)
+                        .map(|_| playout::<G, StdRng, N>(game, rollout_policy.rng))
+                        .sum();
+                    total / num_playouts as f32
+                };
+                AlphaBeta::exploit(*cfg, &mut RolloutPolicy { rng }, eval, game.clone())
+            }
+            PlayerConfig::Random { .. } => {
+                let actions: Vec<G::Action> = game.iter_actions().collect();
+                actions[rng.gen_range(0..actions.len())]
+            }
+        }
+    }
+}
+
+// Plays the players of `cfg` against each other and rates them. Every game goes to results.pgn
+// in `cfg.logs`, the ratings to ratings.json and ratings.txt. Models can come from any run as
+// long as they were trained with the same `P`.
+pub fn tournament<
+    G: Undo<N> + Notation<N> + Sync,
+    P: Policy<G, N> + NNPolicy<G, N> + Send,
+    const N: usize,
+>(
+    cfg: &TournamentConfig,
+) -> Result<Ratings, Box<dyn std::error::Error>> {
+    for (i, player) in cfg.players.iter().enumerate() {
+        if cfg.players[..i].iter().any(|p| p.name() == player.name()) {
+            return Err(format!("two players are named {}", player.name()).into());
+        }
+    }
+    let _guard = tch::no_grad_guard();
+    let mut entrants = Vec::with_capacity(cfg.players.len());
+    for player in cfg.players.iter() {
+        entrants.push(Entrant::<P>::load::<G, N>(player)?);
+    }
+
+    std::fs::create_dir_all(&cfg.logs)?;
+    let pgn_path = cfg.logs.join("results.pgn");
+    let mut pgn = std::fs::File::create(&pgn_path)?;
+    for [a, b] in pairings(cfg.pairing, entrants.len()) {
+        let mut score = [0.0, 0.0];
+        for i in 0..cfg.games_per_color {
+            let seed = cfg.seed + i as u64;
+            for (white, black) in [(a, b), (b, a)] {
+                let mut record = GameRecord::new(
+                    G::NAME,
+                    entrants[white].cfg.name(),
+                    entrants[black].cfg.name(),
+                );
+                record.set_tag("Seed", seed);
+                record.set_tag("WhiteConfig", format!("{:?}", entrants[white].cfg));
+                record.set_tag("BlackConfig", format!("{:?}", entrants[black].cfg));
+                let (reward, result) = play_game(&mut entrants, [white, black], seed, &mut record);
+                add_pgn_record(&mut pgn, &mut record, reward, result.termination())?;
+                let white_score = (reward + 1.0) / 2.0;
+                score[(white != a) as usize] += white_score;
+                score[(white == a) as usize] += 1.0 - white_score;
+            }
+        }
+        println!(
+            "{} {} - {} {}",
+            cfg.players[a].name(),
+            score[0],
+            score[1],
+            cfg.players[b].name()
+        );
+    }
+
+    let ratings = Ratings::from_pgn(&pgn_path)?;
+    ratings.save(cfg.logs.join("ratings.json"))?;
+    std::fs::write(cfg.logs.join("ratings.txt"), ratings.table())?;
+    Ok(ratings)
+}
+
+// pairs of player indices that meet, each pair plays both colors
+fn pairings(pairing: Pairing, num_players: usize) -> Vec<[usize; 2]> {
+    match pairing {
+        Pairing::RoundRobin => (0..num_players)
+            .flat_map(|a| (a + 1..num_players).map(move |b| [a, b]))
+            .collect(),
+        Pairing::Gauntlet => (1..num_players).map(|b| [0, b]).collect(),
+    }
+}
+
+// both sides draw their randomness from one rng seeded with `seed`, so a game without `Model`
+// players can be played again exactly. A `Model`'s root policy noise and random first play
+// urgency are unseeded
+fn play_game<G: Undo<N> + Notation<N> + Sync, P: Policy<G, N> + Send, const N: usize>(
+    entrants: &mut [Entrant<P>],
+    [white, black]: [usize; 2],
+    seed: u64,
+    record: &mut GameRecord,
+) -> (f32, GameResult<G::PlayerId>) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut game = G::new();
+    let first_player = game.player();
+    loop {
+        let i = if game.player() == first_player {
+            white
+        } else {
+            black
+        };
+        let action = entrants[i].act(&game, &mut rng);
+        record.push(&game, &action);
+        if game.step(&action) {
+            break;
+        }
+    }
+    (game.reward(first_player), game.result().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        ActionSelection, AlphaBetaConfig, Exploration, Fpu, MCTSConfig, PolicyNoise,
+    };
+    use crate::mcts::tests::TicTacToe;
+
+    #[test]
+    fn test_pairings() {
+        assert_eq!(
+            pairings(Pairing::RoundRobin, 4),
+            vec![[0, 1], [0, 2], [0, 3], [1, 2], [1, 3], [2, 3]]
+        );
+        assert_eq!(pairings(Pairing::Gauntlet, 4), vec![[0, 1], [0, 2], [0, 3]]);
+        assert!(pairings(Pairing::RoundRobin, 1).is_empty());
+    }
+
+    #[test]
+    fn test_games_are_reproducible() {
+        let mut entrants: Vec<Entrant<RolloutPolicy<StdRng>>> = vec![
+            Entrant {
+                cfg: PlayerConfig::Random {
+                    name: String::from("random"),
+                },
+                policies: Vec::new(),
+            },
+            Entrant {
+                cfg: PlayerConfig::AlphaBeta {
+                    name: String::from("alpha-beta"),
+                    cfg: AlphaBetaConfig {
+                        max_depth: 9,
+                        max_nodes: 200,
+                        table_capacity: 1 << 10,
+                        order_by_policy: false,
+                    },
+                    num_playouts: 2,
+                },
+                policies: Vec::new(),
+            },
+        ];
+        let mut play = |seed| {
+            let mut record = GameRecord::new(TicTacToe::NAME, "random", "alpha-beta");
+            play_game::<TicTacToe, _, 9>(&mut entrants, [0, 1], seed, &mut record);
+            record
+        };
+        let first = play(3);
+        assert_eq!(play(3), first);
+        let game: TicTacToe = first.replay().unwrap();
+        assert!(game.is_over());
+    }
+
+    #[test]
+    fn test_model_searches_on_threads() {
+        let mut rngs: Vec<StdRng> = (0..3).map(StdRng::seed_from_u64).collect();
+        let model = |num_threads| PlayerConfig::Model {
+            name: format!("model-{}", num_threads),
+            path: Default::default(),
+            num_explores: 200,
+            action: ActionSelection::NumVisits,
+            mcts_cfg: MCTSConfig {
+                exploration: Exploration::PolynomialUct { c: 2.0 },
+                solve: true,
+                correct_values_on_solve: true,
+                select_solved_nodes: true,
+                auto_extend: true,
+                fpu: Fpu::Const(f32::INFINITY),
+                root_policy_noise: PolicyNoise::None,
+            },
+            num_threads,
+        };
+        let (serial, parallel) = rngs.split_at_mut(1);
+        let mut entrants = vec![
+            Entrant {
+                cfg: model(1),
+                policies: serial.iter_mut().map(|rng| RolloutPolicy { rng }).collect(),
+            },
+            Entrant {
+                cfg: model(2),
+                policies: parallel
+                    .iter_mut()
+                    .map(|rng| RolloutPolicy { rng })
+                    .collect(),
+            },
+        ];
+        let mut record = GameRecord::new(TicTacToe::NAME, "model-1", "model-2");
+        play_game::<TicTacToe, _, 9>(&mut entrants, [0, 1], 0, &mut record);
+        let game: TicTacToe = record.replay().unwrap();
+        assert!(game.is_over());
+    }
+}
@@ -1,14 +1,43 @@
+use crate::chart::ratings_svg;
+use crate::pgn::GameRecord;
+use crate::ratings::Ratings;
 use chrono::prelude::*;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::Command;
 
 pub fn train_dir(root: &'static str, tag: &'static str) -> std::io::Result<PathBuf> {
     let time = Local::now().format("%m-%d-%YT%H-%M-%SZ").to_string();
     Ok(Path::new(root).join(tag).join(time))
 }
 
+// a run that doesn't resume would write over the models, checkpoint, games and results of any
+// run already in `logs`
+pub fn check_new_run(logs: &Path, resume: bool) -> Result<(), String> {
+    if !resume && logs.join("models").exists() {
+        return Err(format!(
+            "{} already has models, set resume to go on with that run",
+            logs.display()
+        ));
+    }
+    Ok(())
+}
+
+// removes `--config path` from the command line arguments and returns the path, None without
+// `--config` and an error when no path follows it
+pub fn take_config_arg(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    let i = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    args.remove(i);
+    if i == args.len() {
+        return Err(String::from("--config needs a path"));
+    }
+    Ok(Some(args.remove(i)))
+}
+
 pub fn save_str(path: &PathBuf, filename: &'static str, value: &String) -> std::io::Result<()> {
     std::fs::File::create(&path.join(filename)).and_then(|mut f| f.write_all(value.as_bytes()))
 }
@@ -29,70 +58,95 @@ pub fn git_diff() -> std::io::Result<String> {
     })
 }
 
-pub fn add_pgn_result(
+// finishes `record` with the result and appends the whole game to `pgn`
+pub fn add_pgn_record(
     pgn: &mut File,
-    white_name: &String,
-    black_name: &String,
+    record: &mut GameRecord,
     white_reward: f32,
+    termination: &str,
 ) -> std::io::Result<()> {
-    write!(pgn, "[White \"{}\"]\n", white_name)?;
-    write!(pgn, "[Black \"{}\"]\n", black_name)?;
-    let result = if white_reward == 1.0 {
-        // white wins
-        "1-0"
-    } else if white_reward == -1.0 {
-        // black wins
-        "0-1"
-    } else {
-        assert_eq!(white_reward, 0.0);
-        // draw
-        "1/2-1/2"
-    };
-    write!(pgn, "[Result \"{}\"]\n", result)?;
-    write!(pgn, "{}\n", result)
+    record.finish(white_reward, termination);
+    record.write(pgn)
+}
+
+// appends how often the value head's most likely outcome was the solved one to value_accuracy.csv
+pub fn add_value_accuracy(
+    dir: &PathBuf,
+    name: &String,
+    num_correct: usize,
+    num_positions: usize,
+) -> std::io::Result<()> {
+    let path = dir.join("value_accuracy.csv");
+    let is_new = !path.exists();
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    if is_new {
+        write!(f, "model,correct,positions\n")?;
+    }
+    println!(
+        "{} value head: {}/{} solved outcomes",
+        name, num_correct, num_positions
+    );
+    write!(f, "{},{},{}\n", name, num_correct, num_positions)
 }
 
+// rates every player in results.pgn and writes the ratings, best first, to ratings.json
 pub fn calculate_ratings(dir: &PathBuf) -> std::io::Result<()> {
-    let mut child = Command::new("bayeselo.exe")
-        .current_dir(dir)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
-    let mut stdin = child.stdin.take().unwrap();
-    write!(stdin, "readpgn results.pgn\n")?;
-    write!(stdin, "elo\n")?;
-    write!(stdin, "mm\n")?;
-    write!(stdin, "exactdist\n")?;
-    write!(stdin, "ratings >ratings\n")?;
-    write!(stdin, "x\n")?;
-    write!(stdin, "x\n")?;
-    child.wait()?;
-    Ok(())
+    Ratings::from_pgn(dir.join("results.pgn"))?.save(dir.join("ratings.json"))
 }
 
+// draws ratings.json as ratings.svg, Elo of the models by iteration against the baselines
 pub fn plot_ratings(dir: &PathBuf) -> std::io::Result<()> {
-    let output = Command::new("python")
-        .arg("plot_ratings.py")
-        .arg(dir.join("ratings").to_str().unwrap())
-        .status()?;
-    assert!(output.success());
-    Ok(())
+    let ratings = Ratings::load(dir.join("ratings.json"))?;
+    std::fs::write(dir.join("ratings.svg"), ratings_svg(&ratings))
 }
 
+// names of the models in ratings.json, best first
 pub fn rankings(dir: &PathBuf) -> std::io::Result<Vec<String>> {
-    let file = File::open(dir.join("ratings"))?;
-    let reader = std::io::BufReader::new(file);
-    let mut names = Vec::new();
-    for line in reader.lines().skip(1) {
-        let l = String::from(line?.trim());
-        match l.find("model_") {
-            Some(start_i) => {
-                let end_i = l.find(".ot").unwrap();
-                names.push(String::from(l[start_i..end_i + 3].trim()));
-            }
-            None => {}
-        }
+    Ok(Ratings::load(dir.join("ratings.json"))?
+        .players
+        .into_iter()
+        .map(|rating| rating.name)
+        .filter(|name| name.contains("model_"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| String::from(arg)).collect()
+    }
+
+    #[test]
+    fn test_take_config_arg() {
+        let mut with_path = args(&["gobblet", "--config", "run.toml", "classic"]);
+        assert_eq!(
+            take_config_arg(&mut with_path),
+            Ok(Some(String::from("run.toml")))
+        );
+        assert_eq!(with_path, args(&["gobblet", "classic"]));
+
+        let mut without = args(&["gobblet", "classic"]);
+        assert_eq!(take_config_arg(&mut without), Ok(None));
+        assert_eq!(without, args(&["gobblet", "classic"]));
+
+        let mut missing_path = args(&["gobblet", "classic", "--config"]);
+        assert!(take_config_arg(&mut missing_path).is_err());
+    }
+
+    #[test]
+    fn test_check_new_run() {
+        let logs = std::env::temp_dir().join(format!("synthesis_{}_run", std::process::id()));
+        assert!(check_new_run(&logs, false).is_ok());
+        std::fs::create_dir_all(logs.join("models")).unwrap();
+        let new_run = check_new_run(&logs, false);
+        let resumed = check_new_run(&logs, true);
+        std::fs::remove_dir_all(&logs).unwrap();
+        assert!(new_run.is_err());
+        assert!(resumed.is_ok());
     }
-    Ok(names)
 }